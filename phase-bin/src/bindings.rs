@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use winit::keyboard::KeyCode;
+use phase::{Axis, Button, Port, Slot};
+
+/// Bindings used when no file is given.
+pub const DEFAULT_BINDINGS: &str = include_str!("bindings.cfg");
+
+/// A host input that can be bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(KeyCode),
+    PadButton(gilrs::Button),
+    PadAxis(gilrs::Axis),
+}
+
+/// What an input does on an emulated controller.
+#[derive(Clone, Copy, Debug)]
+pub enum Target {
+    Button(Button),
+    /// Axis, and the scale (1 or -1) applied to the input.
+    Axis(Axis, f32),
+    /// DualShock analog mode button.
+    Analog,
+}
+
+/// Emulator functions that can be bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    Pause,
+    Fullscreen,
+    ReleaseMouse,
+}
+
+/// Keyboard and gamepad bindings, loaded from a config file.
+pub struct Bindings {
+    controllers: HashMap<(Port, Slot), HashMap<Input, Vec<Target>>>,
+    hotkeys: HashMap<Input, Hotkey>,
+}
+
+impl Bindings {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let mut bindings = Self {
+            controllers: HashMap::new(),
+            hotkeys: HashMap::new(),
+        };
+        let mut section = None;
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            bindings.parse_line(line, &mut section)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_num + 1, e)))?;
+        }
+        Ok(bindings)
+    }
+
+    /// Targets of an input on a controller.
+    pub fn targets(&self, port: Port, slot: Slot, input: Input) -> &[Target] {
+        self.controllers.get(&(port, slot))
+            .and_then(|inputs| inputs.get(&input))
+            .map_or(&[][..], Vec::as_slice)
+    }
+
+    pub fn hotkey(&self, input: Input) -> Option<Hotkey> {
+        self.hotkeys.get(&input).copied()
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::parse(DEFAULT_BINDINGS).expect("invalid default bindings")
+    }
+}
+
+enum Section {
+    Controllers(Vec<(Port, Slot)>),
+    Hotkeys,
+}
+
+// Internal
+impl Bindings {
+    fn parse_line(&mut self, line: &str, section: &mut Option<Section>) -> Result<(), String> {
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.strip_suffix(']').ok_or("missing ]")?;
+            *section = Some(parse_section(header)?);
+            return Ok(());
+        }
+        let (input_name, targets) = line.split_once('=').ok_or("expected input = target")?;
+        let input_name = input_name.trim();
+        let input = parse_input(input_name)?;
+        let already_bound = || format!("{} is already bound", input_name);
+        match section.as_ref().ok_or("binding outside of a section")? {
+            Section::Controllers(controllers) => {
+                let targets = targets.split(',')
+                    .map(|target| parse_target(target.trim()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for controller in controllers {
+                    if self.controllers.entry(*controller).or_default().insert(input, targets.clone()).is_some() {
+                        return Err(already_bound());
+                    }
+                }
+            },
+            Section::Hotkeys => {
+                let hotkey = parse_hotkey(targets.trim())?;
+                if self.hotkeys.insert(input, hotkey).is_some() {
+                    return Err(already_bound());
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+fn parse_section(header: &str) -> Result<Section, String> {
+    if header.trim().eq_ignore_ascii_case("hotkeys") {
+        return Ok(Section::Hotkeys);
+    }
+    header.split(',').map(|name| {
+        let name = name.trim().to_ascii_uppercase();
+        let mut chars = name.chars();
+        let port = match chars.next() {
+            Some('1') => Port::One,
+            Some('2') => Port::Two,
+            _ => return Err(format!("unknown section [{}]", name)),
+        };
+        let slot = match (chars.next(), chars.next()) {
+            (Some('A'), None) => Slot::A,
+            (Some('B'), None) => Slot::B,
+            (Some('C'), None) => Slot::C,
+            (Some('D'), None) => Slot::D,
+            _ => return Err(format!("unknown section [{}]", name)),
+        };
+        Ok((port, slot))
+    }).collect::<Result<Vec<_>, _>>().map(Section::Controllers)
+}
+
+fn parse_input(name: &str) -> Result<Input, String> {
+    let unknown = || format!("unknown input {}", name);
+    match name.split_once('.').ok_or_else(unknown)? {
+        ("key", key) => key_code(key).map(Input::Key),
+        ("pad", button) => pad_button(button).map(Input::PadButton),
+        ("axis", axis) => pad_axis(axis).map(Input::PadAxis),
+        _ => None,
+    }.ok_or_else(unknown)
+}
+
+fn parse_target(name: &str) -> Result<Target, String> {
+    if name == "Analog" {
+        return Ok(Target::Analog);
+    }
+    if let Some(button) = button(name) {
+        return Ok(Target::Button(button));
+    }
+    let (axis_name, scale) = match name.strip_suffix('-') {
+        Some(axis_name) => (axis_name, -1.0),
+        None => (name, 1.0),
+    };
+    axis(axis_name)
+        .map(|axis| Target::Axis(axis, scale))
+        .ok_or_else(|| format!("unknown target {}", name))
+}
+
+fn parse_hotkey(name: &str) -> Result<Hotkey, String> {
+    match name {
+        "Quit" => Ok(Hotkey::Quit),
+        "Pause" => Ok(Hotkey::Pause),
+        "Fullscreen" => Ok(Hotkey::Fullscreen),
+        "ReleaseMouse" => Ok(Hotkey::ReleaseMouse),
+        _ => Err(format!("unknown hotkey {}", name)),
+    }
+}
+
+/// Look up enum variants by name.
+macro_rules! names {
+    ($func:ident -> $ty:ty { $($name:ident),* $(,)? }) => {
+        fn $func(name: &str) -> Option<$ty> {
+            match name {
+                $(stringify!($name) => Some(<$ty>::$name),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+names!(button -> Button {
+    Select, L3, R3, Start, DUp, DRight, DDown, DLeft,
+    L2, R2, L1, R1, Triangle, Circle, Cross, Square,
+});
+
+names!(axis -> Axis {
+    LeftX, LeftY, RightX, RightY, Twist, I, II, L,
+});
+
+names!(pad_button -> gilrs::Button {
+    South, East, North, West, C, Z,
+    LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb,
+    DPadUp, DPadDown, DPadLeft, DPadRight,
+});
+
+names!(pad_axis -> gilrs::Axis {
+    LeftStickX, LeftStickY, LeftZ, RightStickX, RightStickY, RightZ, DPadX, DPadY,
+});
+
+names!(key_code -> KeyCode {
+    KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM,
+    KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadSubtract, NumpadMultiply, NumpadDivide, NumpadDecimal, NumpadEnter,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    ArrowUp, ArrowDown, ArrowLeft, ArrowRight,
+    Enter, Space, Escape, Tab, Backspace, Insert, Delete, Home, End, PageUp, PageDown,
+    ShiftLeft, ShiftRight, ControlLeft, ControlRight, AltLeft, AltRight,
+    Minus, Equal, BracketLeft, BracketRight, Backslash, Semicolon, Quote,
+    Backquote, Comma, Period, Slash,
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        match Bindings::parse(text) {
+            Ok(_) => panic!("parsed {:?}", text),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn default_bindings() {
+        let bindings = Bindings::default();
+        assert!(matches!(bindings.targets(Port::One, Slot::A, Input::Key(KeyCode::KeyX)), [Target::Button(Button::Cross)]));
+        assert!(bindings.targets(Port::Two, Slot::D, Input::Key(KeyCode::KeyX)).is_empty());
+    }
+
+    #[test]
+    fn parse_bindings() {
+        let bindings = Bindings::parse("
+            # Comment
+            [1A, 2b]
+            key.KeyX = Cross, LeftY-   # Trailing comment
+
+            [hotkeys]
+            pad.Mode = Pause
+        ").unwrap();
+        for (port, slot) in [(Port::One, Slot::A), (Port::Two, Slot::B)] {
+            let targets = bindings.targets(port, slot, Input::Key(KeyCode::KeyX));
+            assert!(matches!(targets, [Target::Button(Button::Cross), Target::Axis(Axis::LeftY, -1.0)]));
+        }
+        assert!(bindings.targets(Port::Two, Slot::A, Input::Key(KeyCode::KeyX)).is_empty());
+        assert_eq!(bindings.hotkey(Input::PadButton(gilrs::Button::Mode)), Some(Hotkey::Pause));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        assert_eq!(parse_error("[1A\nkey.KeyX = Cross"), "line 1: missing ]");
+        assert_eq!(parse_error("[1A]\n\nkey.KeyX Cross"), "line 3: expected input = target");
+        assert_eq!(parse_error("key.KeyX = Cross"), "line 1: binding outside of a section");
+        assert_eq!(parse_error("[3A]"), "line 1: unknown section [3A]");
+        assert_eq!(parse_error("[1E]"), "line 1: unknown section [1E]");
+        assert_eq!(parse_error("[1AB]"), "line 1: unknown section [1AB]");
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(parse_error("[1A]\nkey.KeyX = Crosss"), "line 2: unknown target Crosss");
+        assert_eq!(parse_error("[1A]\nkey.KeyX = Cross, Up"), "line 2: unknown target Up");
+        assert_eq!(parse_error("[1A]\nkey.Comma2 = Cross"), "line 2: unknown input key.Comma2");
+        assert_eq!(parse_error("[1A]\nmouse.Left = Cross"), "line 2: unknown input mouse.Left");
+        assert_eq!(parse_error("[1A]\nKeyX = Cross"), "line 2: unknown input KeyX");
+        assert_eq!(parse_error("[hotkeys]\nkey.F1 = Reset"), "line 2: unknown hotkey Reset");
+    }
+
+    #[test]
+    fn duplicate_bindings() {
+        assert_eq!(parse_error("[1A]\nkey.KeyX = Cross\nkey.KeyX = Circle"), "line 3: key.KeyX is already bound");
+        // Sections can overlap, but not bind the same input twice.
+        assert_eq!(parse_error("[1A]\nkey.KeyX = Cross\n[2A, 1A]\nkey.KeyX = Circle"), "line 4: key.KeyX is already bound");
+        assert_eq!(parse_error("[hotkeys]\nkey.F1 = Pause\nkey.F1 = Quit"), "line 3: key.F1 is already bound");
+        // The same input can be bound on different controllers, and as a hotkey.
+        assert!(Bindings::parse("[1A]\nkey.KeyX = Cross\n[2A]\nkey.KeyX = Circle\n[hotkeys]\nkey.KeyX = Pause").is_ok());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+
+use gilrs::{Gilrs, GamepadId};
+use winit::keyboard::KeyCode;
+use phase::{Axis, Button, PlayStation, Port, Slot};
+
+use crate::bindings::{Bindings, Hotkey, Input, Target};
+use crate::rumble::Rumble;
+
+/// Something on an emulated controller that inputs can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Output {
+    Button(Button),
+    Axis(Axis),
+    Analog,
+}
+
+/// Routes keyboard and gamepad input to the emulated controllers.
+pub struct InputHandler {
+    bindings: Bindings,
+    gilrs: Gilrs,
+    rumble: Rumble,
+    /// Connected controllers, in the order gamepads are given to them.
+    controllers: Vec<(Port, Slot)>,
+    gamepads: HashMap<GamepadId, (Port, Slot)>,
+    /// Value of each input driving an output.
+    active: HashMap<(Port, Slot, Output, Input), f32>,
+    held_hotkeys: HashSet<Input>,
+}
+
+impl InputHandler {
+    pub fn new(bindings: Bindings, controllers: Vec<(Port, Slot)>) -> Self {
+        let gilrs = Gilrs::new().unwrap();
+        let connected = gilrs.gamepads().map(|(id, _)| id).collect::<Vec<_>>();
+        let mut handler = Self {
+            bindings,
+            gilrs,
+            rumble: Rumble::default(),
+            controllers,
+            gamepads: HashMap::new(),
+            active: HashMap::new(),
+            held_hotkeys: HashSet::new(),
+        };
+        for id in connected {
+            handler.connect_gamepad(id);
+        }
+        handler
+    }
+
+    /// Returns the hotkey triggered by the key, if any.
+    pub fn key(&mut self, console: &mut PlayStation, key: KeyCode, pressed: bool) -> Option<Hotkey> {
+        let input = Input::Key(key);
+        let value = if pressed {1.0} else {0.0};
+        for (port, slot) in self.controllers.clone() {
+            self.set_input(console, port, slot, input, value);
+        }
+        self.hotkey(input, value)
+    }
+
+    /// Handle gamepad events. Returns any hotkeys triggered.
+    pub fn poll_gamepads(&mut self, console: &mut PlayStation) -> Vec<Hotkey> {
+        use gilrs::EventType::*;
+        let mut hotkeys = Vec::new();
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let (input, value) = match event {
+                Connected => {
+                    self.connect_gamepad(id);
+                    continue;
+                },
+                Disconnected => {
+                    self.disconnect_gamepad(console, id);
+                    continue;
+                },
+                ButtonChanged(button, value, _) => (Input::PadButton(button), value),
+                AxisChanged(axis, value, _) => (Input::PadAxis(axis), value),
+                _ => continue,
+            };
+            if let Some((port, slot)) = self.gamepads.get(&id).copied() {
+                self.set_input(console, port, slot, input, value);
+            }
+            hotkeys.extend(self.hotkey(input, value));
+        }
+        hotkeys
+    }
+
+    /// Set the motors of the gamepad driving a controller.
+    pub fn rumble(&self, port: Port, slot: Slot, small: u8, large: u8) {
+        for (id, _) in self.gamepads.iter().filter(|(_, controller)| **controller == (port, slot)) {
+            self.rumble.set(*id, small, large);
+        }
+    }
+}
+
+// Internal
+impl InputHandler {
+    /// Give a gamepad the first controller without one.
+    fn connect_gamepad(&mut self, id: GamepadId) {
+        if self.gamepads.contains_key(&id) {
+            return;
+        }
+        let free = self.controllers.iter().find(|controller| !self.gamepads.values().any(|c| c == *controller)).copied();
+        if let Some((port, slot)) = free {
+            println!("Gamepad {} assigned to port {:?}{:?}", self.gilrs.gamepad(id).name(), port, slot);
+            self.gamepads.insert(id, (port, slot));
+            self.rumble.add_gamepad(&mut self.gilrs, id);
+        }
+    }
+
+    /// Release everything the gamepad was holding.
+    fn disconnect_gamepad(&mut self, console: &mut PlayStation, id: GamepadId) {
+        let Some((port, slot)) = self.gamepads.remove(&id) else {
+            return;
+        };
+        self.rumble.remove_gamepad(id);
+        let inputs = self.active.keys()
+            .filter(|(p, s, _, input)| (*p, *s) == (port, slot) && !matches!(input, Input::Key(_)))
+            .map(|(_, _, _, input)| *input)
+            .collect::<HashSet<_>>();
+        for input in inputs {
+            self.set_input(console, port, slot, input, 0.0);
+        }
+    }
+
+    fn set_input(&mut self, console: &mut PlayStation, port: Port, slot: Slot, input: Input, value: f32) {
+        for target in self.bindings.targets(port, slot, input) {
+            let (output, value) = match *target {
+                Target::Button(button) => (Output::Button(button), value),
+                Target::Axis(axis, scale) => (Output::Axis(axis), value * scale),
+                Target::Analog => (Output::Analog, value),
+            };
+            let old = output_value(&self.active, port, slot, output);
+            if value == 0.0 {
+                self.active.remove(&(port, slot, output, input));
+            } else {
+                self.active.insert((port, slot, output, input), value);
+            }
+            let new = output_value(&self.active, port, slot, output);
+            match output {
+                Output::Button(button) => if (new > 0.5) != (old > 0.5) {
+                    console.press_button(port, slot, button, new > 0.5);
+                },
+                Output::Axis(axis) => if new != old {
+                    console.update_axis(port, slot, axis, new);
+                },
+                Output::Analog => if new > 0.5 && old <= 0.5 {
+                    console.press_analog_button(port, slot);
+                },
+            }
+        }
+    }
+
+    /// Returns the hotkey if the input was just pressed.
+    fn hotkey(&mut self, input: Input, value: f32) -> Option<Hotkey> {
+        let hotkey = self.bindings.hotkey(input)?;
+        if value > 0.5 {
+            self.held_hotkeys.insert(input).then_some(hotkey)
+        } else {
+            self.held_hotkeys.remove(&input);
+            None
+        }
+    }
+}
+
+/// Combined value of all inputs driving an output.
+fn output_value(active: &HashMap<(Port, Slot, Output, Input), f32>, port: Port, slot: Slot, output: Output) -> f32 {
+    active.iter()
+        .filter(|((p, s, o, _), _)| (*p, *s, *o) == (port, slot, output))
+        .map(|(_, value)| value)
+        .sum::<f32>()
+        .clamp(-1.0, 1.0)
+}
@@ -1,4 +1,8 @@
+mod bindings;
 mod debug;
+mod input;
+mod memcard;
+mod rumble;
 
 use wgpu::Extent3d;
 use winit::{
@@ -8,10 +12,9 @@ use winit::{
         ElementState, WindowEvent
     }, event_loop::{
         EventLoop
-    }, window::Window, keyboard::{PhysicalKey, KeyCode}
+    }, window::{Window, CursorGrabMode, Fullscreen}, keyboard::PhysicalKey
 };
 use cpal::traits::StreamTrait;
-use gilrs::{Gilrs};
 
 use std::path::PathBuf;
 
@@ -20,9 +23,13 @@ use clap::Parser;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short, long)]
-    bios: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, required = true)]
+    bios: Option<String>,
 
     #[arg(short, long)]
     debug: bool,
@@ -36,57 +43,331 @@ struct Args {
     #[arg(long)]
     memcard2: Option<String>,
 
+    /// Directory of per-game memory cards, named after the disc serial.
+    /// The card is inserted in port 1 when a disc is inserted.
+    #[arg(long)]
+    memcard_dir: Option<String>,
+
     #[arg(short, long)]
     mute: bool,
 
+    /// Connect a multitap to port 1, with controllers in every slot.
+    /// Gamepads are given to port 1, port 2, then slots B to D.
+    #[arg(long)]
+    multitap: bool,
+
     #[arg(short, long)]
     analog: bool,
 
+    /// Type of controller in port 1. Overrides --analog.
+    /// With --multitap, a comma-separated list gives slots A to D,
+    /// and the last type is used for any slots left.
+    #[arg(long, value_delimiter = ',')]
+    controller: Vec<ControllerArg>,
+
+    /// Connect a controller of this type to port 2.
+    #[arg(long, conflicts_with_all = ["mouse", "lightgun"])]
+    controller2: Option<ControllerArg>,
+
+    /// Keyboard, gamepad and hotkey bindings file.
+    /// See phase-bin/src/bindings.cfg for the format and the default bindings.
+    #[arg(long)]
+    bindings: Option<String>,
+
+    // Stick settings take a comma-separated list, with a value for each
+    // controller in the order gamepads are given to them. The last value
+    // is used for any controllers left.
+
+    /// Analog stick deadzone, from 0 to 1.
+    #[arg(long, value_delimiter = ',')]
+    deadzone: Vec<f32>,
+
+    /// Analog stick sensitivity multiplier.
+    #[arg(long, value_delimiter = ',')]
+    sensitivity: Vec<f32>,
+
+    /// Move the left stick with the D-pad (or arrow keys).
+    #[arg(long, value_delimiter = ',', num_args = 0..=1, default_missing_value = "true")]
+    dpad_to_stick: Vec<bool>,
+
+    /// Frames for the D-pad to reach full stick deflection.
+    #[arg(long, value_delimiter = ',')]
+    dpad_ramp: Vec<u32>,
+
+    /// Connect a mouse to port 2. Click in the window to capture the pointer,
+    /// and press Escape to release it.
+    #[arg(long)]
+    mouse: bool,
+
+    /// Connect a light gun to port 2, aimed with the host mouse.
+    /// Left click is the trigger, right click is A and middle click is B.
+    #[arg(long, conflicts_with = "mouse")]
+    lightgun: Option<LightGunArg>,
+
+    /// Connect the serial port to another emulator with a link cable.
+    /// "listen:ADDR" waits for a TCP connection, "connect:ADDR" connects to one,
+    /// and "tty:PATH" opens a terminal device such as a pseudo-terminal.
+    #[arg(long)]
+    link: Option<String>,
+
     #[arg(long)]
     crtmode: bool,
+
+    /// How to show 480i video. Weave is sharpest, bob avoids combing
+    /// and adaptive weaves still areas and bobs moving ones.
+    #[arg(long)]
+    deinterlace: Option<DeinterlaceArg>,
+
+    /// How much of the picture around the game's display area to show.
+    #[arg(long)]
+    overscan: Option<OverscanArg>,
+
+    /// Emulate the GPU texture cache, for games that rely on stale texels.
+    #[arg(long)]
+    texture_cache: bool,
+
+    /// Draw polygons at this multiple of the native resolution.
+    /// Rounded up to 1, 2, 4 or 8.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=8))]
+    scale: Option<u8>,
+
+    /// Split drawing between this many threads.
+    #[arg(long)]
+    render_threads: Option<usize>,
+
+    /// Shade and blend in 24-bit colour, without dithering.
+    #[arg(long)]
+    true_color: bool,
+
+    /// Print a guest profile report every N frames.
+    #[arg(long)]
+    profile: Option<usize>,
+
+    /// Symbol file to use for profile reports.
+    #[arg(long)]
+    symbols: Option<String>,
+
+    /// Log I/O register accesses for a comma-separated list of devices (or "all").
+    #[arg(long, value_delimiter = ',')]
+    iolog: Vec<String>,
+
+    /// File to write the I/O log to, instead of stdout.
+    #[arg(long)]
+    iolog_file: Option<String>,
+
+    /// Most verbose log messages to show: error, warn, info, debug or trace.
+    #[arg(long)]
+    log_level: Option<LogLevel>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ControllerArg {
+    Digital,
+    Analog,
+    Joystick,
+    #[value(name = "negcon")]
+    NeGcon,
+}
+
+impl From<ControllerArg> for ControllerType {
+    fn from(arg: ControllerArg) -> Self {
+        match arg {
+            ControllerArg::Digital => ControllerType::Digital,
+            ControllerArg::Analog => ControllerType::Analog,
+            ControllerArg::Joystick => ControllerType::AnalogJoystick,
+            ControllerArg::NeGcon => ControllerType::NeGcon,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LightGunArg {
+    #[value(name = "guncon")]
+    GunCon,
+    Justifier,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum DeinterlaceArg {
+    Weave,
+    Bob,
+    Adaptive,
+}
+
+impl From<DeinterlaceArg> for DeinterlaceMode {
+    fn from(arg: DeinterlaceArg) -> Self {
+        match arg {
+            DeinterlaceArg::Weave => DeinterlaceMode::Weave,
+            DeinterlaceArg::Bob => DeinterlaceMode::Bob,
+            DeinterlaceArg::Adaptive => DeinterlaceMode::Adaptive,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OverscanArg {
+    Full,
+    Safe,
+    Game,
+}
+
+impl From<OverscanArg> for OverscanMode {
+    fn from(arg: OverscanArg) -> Self {
+        match arg {
+            OverscanArg::Full => OverscanMode::Full,
+            OverscanArg::Safe => OverscanMode::SafeArea,
+            OverscanArg::Game => OverscanMode::GameArea,
+        }
+    }
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Manage memory card images and saves.
+    Memcard {
+        #[command(subcommand)]
+        action: memcard::MemcardAction,
+    },
 }
 
 fn main() {
     let args = Args::parse();
 
+    if let Some(Command::Memcard { action }) = args.command {
+        memcard::run_command(action);
+        return;
+    }
+
     let config = PlayStationConfig {
-        bios_path: PathBuf::from(args.bios)
+        bios_path: PathBuf::from(args.bios.expect("BIOS path required")),
+        mem_card_dir: args.memcard_dir.map(PathBuf::from),
     };
     let mut playstation = PlayStation::new(config);
+    if let Some(level) = args.log_level {
+        playstation.set_log_level(level);
+    }
     let game_disc = args.game.map(|s| s.try_into().expect("invalid path"));
     if let Some(memcard) = args.memcard1 {
-        playstation.insert_mem_card(memcard.try_into().expect("invalid memcard path"), Port::One);
+        playstation.insert_mem_card(memcard.try_into().expect("invalid memcard path"), Port::One, Slot::A);
     }
     if let Some(memcard2) = args.memcard2 {
-        playstation.insert_mem_card(memcard2.try_into().expect("invalid memcard2 path"), Port::Two);
+        playstation.insert_mem_card(memcard2.try_into().expect("invalid memcard2 path"), Port::Two, Slot::A);
+    }
+    if args.multitap {
+        playstation.attach_multitap(Port::One);
+    }
+    let default_controller = if args.analog {ControllerArg::Analog} else {ControllerArg::Digital};
+    // In the order that gamepads are given to them.
+    let mut controllers = vec![(Port::One, Slot::A)];
+    if let Some(controller2) = args.controller2 {
+        controllers.push((Port::Two, Slot::A));
+        playstation.attach_controller(controller2.into(), Port::Two, Slot::A);
+    }
+    if args.multitap {
+        controllers.extend([Slot::B, Slot::C, Slot::D].map(|slot| (Port::One, slot)));
     }
-    if args.analog {
-        playstation.attach_controller(ControllerType::Analog, Port::One);
+    for (port, slot) in controllers.iter().filter(|(port, _)| *port == Port::One) {
+        let controller = nth_or_last(&args.controller, *slot as usize).unwrap_or(default_controller);
+        playstation.attach_controller(controller.into(), *port, *slot);
+    }
+    for (n, (port, slot)) in controllers.iter().enumerate() {
+        let default = StickConfig::default();
+        playstation.set_stick_config(*port, *slot, StickConfig {
+            deadzone: nth_or_last(&args.deadzone, n).unwrap_or(default.deadzone),
+            sensitivity: nth_or_last(&args.sensitivity, n).unwrap_or(default.sensitivity),
+            dpad_to_stick: nth_or_last(&args.dpad_to_stick, n).unwrap_or(default.dpad_to_stick),
+            dpad_ramp_frames: nth_or_last(&args.dpad_ramp, n).unwrap_or(default.dpad_ramp_frames),
+            ..default
+        });
+    }
+    let mouse_mode = if args.mouse {
+        playstation.attach_controller(ControllerType::Mouse, Port::Two, Slot::A);
+        MouseMode::Mouse(Port::Two)
+    } else if let Some(light_gun) = args.lightgun {
+        let controller = match light_gun {
+            LightGunArg::GunCon => ControllerType::GunCon,
+            LightGunArg::Justifier => ControllerType::Justifier,
+        };
+        playstation.attach_controller(controller, Port::Two, Slot::A);
+        MouseMode::LightGun(Port::Two)
     } else {
-        playstation.attach_controller(ControllerType::Digital, Port::One);
+        MouseMode::None
+    };
+
+    if let Some(deinterlace) = args.deinterlace {
+        playstation.set_deinterlace_mode(deinterlace.into());
+    }
+    if let Some(overscan) = args.overscan {
+        playstation.set_overscan_mode(overscan.into());
+    }
+    if args.texture_cache {
+        playstation.set_texture_cache(true);
+    }
+    if let Some(scale) = args.scale {
+        playstation.set_resolution_scale(scale as usize);
+    }
+    if let Some(threads) = args.render_threads {
+        playstation.set_render_threads(threads);
+    }
+    if args.true_color {
+        playstation.set_true_color(true);
+    }
+
+    if let Some(link) = args.link {
+        let cable = open_link(&link).unwrap_or_else(|e| panic!("could not open link cable {}: {}", link, e));
+        playstation.connect_serial_link(cable);
+    }
+
+    if !args.iolog.is_empty() {
+        let devices = args.iolog.iter().fold(IODevices::empty(), |devices, name| {
+            if name.eq_ignore_ascii_case("all") {
+                devices | IODevices::all()
+            } else {
+                devices | IODevices::from_name(name).unwrap_or_else(|| panic!("unknown I/O device {}", name))
+            }
+        });
+        let config = IOLogConfig {
+            devices,
+            path: args.iolog_file.map(PathBuf::from),
+        };
+        playstation.enable_io_logger(config).expect("could not enable I/O logger");
     }
 
     if args.debug {
         debug::debug_mode(playstation.make_debugger());
     } else {
-        run(playstation, game_disc, args.mute, args.crtmode);
+        let bindings = match args.bindings {
+            Some(path) => bindings::Bindings::load(path.as_ref()).expect("could not load bindings"),
+            None => bindings::Bindings::default(),
+        };
+        let input = input::InputHandler::new(bindings, controllers);
+        let profiler = args.profile.map(|report_frames| {
+            let config = ProfilerConfig {
+                report_frames,
+                symbols_path: args.symbols.map(PathBuf::from),
+                ..Default::default()
+            };
+            playstation.enable_profiler(config).expect("could not enable profiler")
+        });
+        run(playstation, game_disc, args.mute, args.crtmode, profiler, mouse_mode, input);
     }
 }
 
 /// Run playstation with visuals.
-fn run(mut playstation: PlayStation, game_disc: Option<PathBuf>, mute: bool, crt_mode: bool) {
+fn run(mut playstation: PlayStation, game_disc: Option<PathBuf>, mute: bool, crt_mode: bool, profiler: Option<ProfileHandler>, mouse_mode: MouseMode, input: input::InputHandler) {
     let event_loop = EventLoop::new().expect("Failed to create event loop");
 
     let audio_stream = make_audio_stream(&mut playstation, mute);
 
-    let mut app = App::new(playstation, game_disc, audio_stream, crt_mode);
+    let mut app = App::new(playstation, game_disc, audio_stream, crt_mode, profiler, mouse_mode, input);
 
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     event_loop.run_app(&mut app).unwrap();
 }
 
 // TODO: move inside phase core
-const FRAME_TIME: chrono::Duration = chrono::Duration::nanoseconds(1_000_000_000 / 60);
+const NTSC_FRAME_TIME: chrono::Duration = chrono::Duration::nanoseconds(1_000_000_000 / 60);
+const PAL_FRAME_TIME: chrono::Duration = chrono::Duration::nanoseconds(1_000_000_000 / 50);
 
 struct WindowState {
     window:         std::sync::Arc<Window>,
@@ -120,14 +401,32 @@ struct App {
 
     frame:           Frame,
     last_frame_time: chrono::DateTime<chrono::Utc>,
+    /// Set by the video standard the game runs in.
+    frame_time:      chrono::Duration,
 
     audio_stream: cpal::Stream,
 
-    controllers:    Gilrs,
+    input:          input::InputHandler,
+    paused:         bool,
+
+    profiler:       Option<ProfileHandler>,
+
+    mouse_mode:     MouseMode,
+    mouse_captured: bool,
+}
+
+/// What the host mouse controls.
+#[derive(Clone, Copy)]
+enum MouseMode {
+    None,
+    /// PlayStation Mouse in a port.
+    Mouse(Port),
+    /// Light gun in a port, aimed with the cursor.
+    LightGun(Port),
 }
 
 impl App {
-    fn new(console: PlayStation, game_disc: Option<PathBuf>, audio_stream: cpal::Stream, crt_mode: bool) -> Self {
+    fn new(console: PlayStation, game_disc: Option<PathBuf>, audio_stream: cpal::Stream, crt_mode: bool, profiler: Option<ProfileHandler>, mouse_mode: MouseMode, input: input::InputHandler) -> Self {
         // Setup wgpu
         let instance = wgpu::Instance::new(&Default::default());
 
@@ -217,7 +516,7 @@ impl App {
             multiview_mask: None,
             cache: None
         });
-        
+
         Self {
             window: None,
             console,
@@ -235,10 +534,81 @@ impl App {
 
             frame:           Frame::new(),
             last_frame_time: chrono::Utc::now(),
+            frame_time:      NTSC_FRAME_TIME,
 
             audio_stream: audio_stream,
 
-            controllers: Gilrs::new().unwrap(),
+            input,
+            paused: false,
+
+            profiler,
+
+            mouse_mode,
+            mouse_captured: false,
+        }
+    }
+
+    /// Grab and hide the host pointer, so all movement goes to the emulated mouse.
+    fn capture_mouse(&mut self, capture: bool) {
+        let Some(window) = self.window.as_ref().map(|w| &w.window) else {
+            return;
+        };
+        if capture {
+            let grabbed = window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = grabbed {
+                println!("Could not capture mouse: {}", e);
+                return;
+            }
+        } else {
+            let _ = window.set_cursor_grab(CursorGrabMode::None);
+        }
+        window.set_cursor_visible(!capture);
+        self.mouse_captured = capture;
+    }
+
+    fn run_hotkey(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, hotkey: bindings::Hotkey) {
+        use bindings::Hotkey;
+        match hotkey {
+            Hotkey::Quit => event_loop.exit(),
+            Hotkey::Pause => self.set_paused(!self.paused),
+            Hotkey::Fullscreen => if let Some(window) = self.window.as_ref().map(|w| &w.window) {
+                let fullscreen = window.fullscreen().is_none().then_some(Fullscreen::Borderless(None));
+                window.set_fullscreen(fullscreen);
+            },
+            Hotkey::ReleaseMouse => if self.mouse_captured {
+                self.capture_mouse(false);
+            },
+        }
+    }
+
+    /// Stop or restart emulation, and the audio stream with it.
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if paused {
+            if let Err(e) = self.audio_stream.pause() {
+                println!("Could not pause audio stream: {}", e);
+            }
+        } else if let Err(e) = self.audio_stream.play() {
+            println!("Could not resume audio stream: {}", e);
+        }
+    }
+
+    /// Area of the window the frame is drawn to, keeping its aspect ratio:
+    /// x, y, width and height, in pixels.
+    fn display_rect(&self) -> (f32, f32, f32, f32) {
+        let size = self.window.as_ref().unwrap().window.inner_size();
+        let (window_width, window_height) = (size.width as f32, size.height as f32);
+        if self.frame.size.1 == 0 || window_height == 0.0 {
+            return (0.0, 0.0, window_width, window_height);
+        }
+        let aspect = (self.frame.size.0 as f32 * self.frame.pixel_aspect_ratio) / self.frame.size.1 as f32;
+        if window_width / window_height > aspect {
+            let width = window_height * aspect;
+            ((window_width - width) / 2.0, 0.0, width, window_height)
+        } else {
+            let height = window_width / aspect;
+            (0.0, (window_height - height) / 2.0, window_width, height)
         }
     }
 
@@ -330,11 +700,28 @@ impl ApplicationHandler for App {
             WindowEvent::RedrawRequested => {
                 let now = chrono::Utc::now();
                 let since_last_frame = now.signed_duration_since(self.last_frame_time);
-                if since_last_frame >= FRAME_TIME {
+                if since_last_frame >= self.frame_time {
                     //println!("frame time {}", since_last_frame);
                     self.last_frame_time = now;
     
-                    self.console.frame(&mut self.frame);
+                    if !self.paused {
+                        self.console.frame(&mut self.frame);
+                    }
+
+                    for event in self.console.poll_events() {
+                        match event {
+                            Event::RumbleChanged { port, slot, small, large } => self.input.rumble(port, slot, small, large),
+                            Event::VideoModeChanged { pal, .. } => {
+                                self.frame_time = if pal {PAL_FRAME_TIME} else {NTSC_FRAME_TIME};
+                                print_event(event);
+                            },
+                            event => print_event(event),
+                        }
+                    }
+
+                    if let Some(report) = self.profiler.as_ref().and_then(|p| p.poll_report()) {
+                        println!("{}", report);
+                    }
     
                     if let Some(texture) = self.texture.as_ref() {
                         if texture.width() != (self.frame.size.0 as u32) ||
@@ -371,7 +758,7 @@ impl ApplicationHandler for App {
                             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                                 view: &view,
                                 ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                                     store: wgpu::StoreOp::Store,
                                 },
                                 depth_slice: None,
@@ -380,6 +767,8 @@ impl ApplicationHandler for App {
                             depth_stencil_attachment: None,
                             ..Default::default()
                         });
+                        let (x, y, width, height) = self.display_rect();
+                        rpass.set_viewport(x, y, width, height, 0.0, 1.0);
                         rpass.set_pipeline(&self.render_pipeline);
                         rpass.set_bind_group(0, &self.bind_group, &[]);
                         rpass.draw(0..4, 0..1);
@@ -388,56 +777,115 @@ impl ApplicationHandler for App {
                     self.queue.submit([encoder.finish()]);
                     frame.present();
 
-                    while let Some(gilrs::Event { event, .. }) = self.controllers.next_event() {
-                        use gilrs::EventType::*;
-                        match event {
-                            Connected => {},
-                            Disconnected => {},
-                            ButtonPressed(button, _) => if let Some(button) = map_button(button) {
-                                self.console.press_button(Port::One, button, true);
-                            },
-                            ButtonReleased(button, _) => if let Some(button) = map_button(button) {
-                                self.console.press_button(Port::One, button, false);
-                            },
-                            AxisChanged(axis, value, _) => match axis {
-                                gilrs::Axis::LeftStickX  => self.console.update_stick_axis(Port::One, AnalogStickAxis::LeftX, value),
-                                gilrs::Axis::LeftStickY  => self.console.update_stick_axis(Port::One, AnalogStickAxis::LeftY, -value),
-                                gilrs::Axis::RightStickX => self.console.update_stick_axis(Port::One, AnalogStickAxis::RightX, value),
-                                gilrs::Axis::RightStickY => self.console.update_stick_axis(Port::One, AnalogStickAxis::RightY, -value),
-                                _ => {},
-                            },
-                            _ => {}
-                        }
+                    for hotkey in self.input.poll_gamepads(&mut self.console) {
+                        self.run_hotkey(event_loop, hotkey);
                     }
                 }
                 self.window.as_ref().unwrap().window.request_redraw();
             },
+            WindowEvent::MouseInput { device_id: _, state, button } => {
+                use winit::event::MouseButton as HostButton;
+                let pressed = state == ElementState::Pressed;
+                match self.mouse_mode {
+                    MouseMode::Mouse(_) if !self.mouse_captured => self.capture_mouse(true),
+                    MouseMode::Mouse(port) => match button {
+                        HostButton::Left => self.console.press_mouse_button(port, Slot::A, MouseButton::Left, pressed),
+                        HostButton::Right => self.console.press_mouse_button(port, Slot::A, MouseButton::Right, pressed),
+                        _ => {},
+                    },
+                    MouseMode::LightGun(port) => match button {
+                        HostButton::Left => self.console.press_light_gun_button(port, Slot::A, LightGunButton::Trigger, pressed),
+                        HostButton::Right => self.console.press_light_gun_button(port, Slot::A, LightGunButton::A, pressed),
+                        HostButton::Middle => self.console.press_light_gun_button(port, Slot::A, LightGunButton::B, pressed),
+                        _ => {},
+                    },
+                    MouseMode::None => {},
+                }
+            },
+            WindowEvent::CursorMoved { device_id: _, position } => if let MouseMode::LightGun(port) = self.mouse_mode {
+                let (x, y, width, height) = self.display_rect();
+                let aim = ((position.x as f32 - x) / width, (position.y as f32 - y) / height);
+                self.console.aim_light_gun(port, Slot::A, Some(aim));
+            },
+            WindowEvent::CursorLeft { device_id: _ } => if let MouseMode::LightGun(port) = self.mouse_mode {
+                self.console.aim_light_gun(port, Slot::A, None);
+            },
+            WindowEvent::Focused(false) => if self.mouse_captured {
+                self.capture_mouse(false);
+            },
             WindowEvent::KeyboardInput { device_id: _, event, is_synthetic: _ } => {
                 let pressed = match event.state {
                     ElementState::Pressed => true,
                     ElementState::Released => false,
                 };
-                match event.physical_key {
-                    PhysicalKey::Code(KeyCode::KeyX)        => self.console.press_button(Port::One, Button::Cross, pressed),
-                    PhysicalKey::Code(KeyCode::KeyZ)        => self.console.press_button(Port::One, Button::Circle, pressed),
-                    PhysicalKey::Code(KeyCode::KeyD)        => self.console.press_button(Port::One, Button::Triangle, pressed),
-                    PhysicalKey::Code(KeyCode::KeyC)        => self.console.press_button(Port::One, Button::Square, pressed),
-                    PhysicalKey::Code(KeyCode::KeyA)        => self.console.press_button(Port::One, Button::L1, pressed),
-                    PhysicalKey::Code(KeyCode::KeyS)        => self.console.press_button(Port::One, Button::R1, pressed),
-                    PhysicalKey::Code(KeyCode::KeyQ)        => self.console.press_button(Port::One, Button::L2, pressed),
-                    PhysicalKey::Code(KeyCode::KeyW)        => self.console.press_button(Port::One, Button::R2, pressed),
-                    PhysicalKey::Code(KeyCode::Space)       => self.console.press_button(Port::One, Button::Select, pressed),
-                    PhysicalKey::Code(KeyCode::Enter)       => self.console.press_button(Port::One, Button::Start, pressed),
-                    PhysicalKey::Code(KeyCode::ArrowUp)     => self.console.press_button(Port::One, Button::DUp, pressed),
-                    PhysicalKey::Code(KeyCode::ArrowDown)   => self.console.press_button(Port::One, Button::DDown, pressed),
-                    PhysicalKey::Code(KeyCode::ArrowLeft)   => self.console.press_button(Port::One, Button::DLeft, pressed),
-                    PhysicalKey::Code(KeyCode::ArrowRight)  => self.console.press_button(Port::One, Button::DRight, pressed),
-                    _ => {},
+                if let PhysicalKey::Code(key) = event.physical_key {
+                    if let Some(hotkey) = self.input.key(&mut self.console, key, pressed) {
+                        self.run_hotkey(event_loop, hotkey);
+                    }
                 }
             },
             _ => {}
         }
     }
+
+    fn device_event(
+            &mut self,
+            _event_loop: &winit::event_loop::ActiveEventLoop,
+            _device_id: winit::event::DeviceId,
+            event: winit::event::DeviceEvent,
+        ) {
+        if let winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            if let (MouseMode::Mouse(port), true) = (self.mouse_mode, self.mouse_captured) {
+                self.console.move_mouse(port, Slot::A, dx as i32, dy as i32);
+            }
+        }
+    }
+}
+
+/// Show an event from the console.
+fn print_event(event: Event) {
+    match event {
+        Event::Log(record) => println!("{}", record),
+        Event::CDInserted { path } => println!("CD inserted: {}", path.display()),
+        Event::CDRemoved => println!("CD removed"),
+        Event::DiscReadError { message } => println!("Disc error: {}", message),
+        Event::MultitapConnected { port } => println!("Connected multitap to port {:?}", port),
+        Event::MultitapDisconnected { port } => println!("Disconnected multitap at port {:?}", port),
+        Event::ControllerConnected { port, slot } => println!("Connected controller to port {:?}{:?}", port, slot),
+        Event::ControllerDisconnected { port, slot } => println!("Disconnected controller at port {:?}{:?}", port, slot),
+        Event::MemCardInserted { port, slot, path } => println!("Memory Card inserted to port {:?}{:?}: {}", port, slot, path.display()),
+        Event::MemCardRemoved { port, slot } => println!("Memory Card removed from port {:?}{:?}", port, slot),
+        Event::MemCardWritten { port, slot } => println!("Memory Card saved in port {:?}{:?}", port, slot),
+        Event::MemCardChecksumMismatch { path, frames } => println!("Memory Card {} has bad checksums in directory frames {:?}", path.display(), frames),
+        Event::SerialLinkConnected => println!("Link cable connected"),
+        Event::SerialLinkDisconnected => println!("Link cable disconnected"),
+        Event::RumbleChanged { .. } => {},
+        Event::VideoModeChanged { width, height, interlace, rgb24, pal } => println!(
+            "Video mode: {}x{}{} {} {}",
+            width, height, if interlace {"i"} else {"p"}, if rgb24 {"24-bit"} else {"15-bit"}, if pal {"PAL"} else {"NTSC"}
+        ),
+    }
+}
+
+/// Setting for the nth controller: the nth value given, or the last one.
+fn nth_or_last<T: Copy>(values: &[T], n: usize) -> Option<T> {
+    values.get(n).or(values.last()).copied()
+}
+
+fn open_link(link: &str) -> std::io::Result<LinkCable> {
+    use std::net::{TcpListener, TcpStream};
+    match link.split_once(':') {
+        Some(("listen", addr)) => {
+            let listener = TcpListener::bind(addr)?;
+            println!("Waiting for link cable connection on {}", listener.local_addr()?);
+            let (stream, peer) = listener.accept()?;
+            println!("Link cable connected to {}", peer);
+            LinkCable::tcp(stream)
+        },
+        Some(("connect", addr)) => LinkCable::tcp(TcpStream::connect(addr)?),
+        Some(("tty", path)) => LinkCable::tty(path.as_ref()),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "expected listen:ADDR, connect:ADDR or tty:PATH")),
+    }
 }
 
 fn make_audio_stream(console: &mut PlayStation, mute: bool) -> cpal::Stream {
@@ -490,26 +938,3 @@ fn pick_output_config(device: &cpal::Device) -> cpal::SupportedStreamConfigRange
         .next()
         .expect("No supported config")
 }
-
-fn map_button(input: gilrs::Button) -> Option<Button> {
-    use gilrs::Button::*;
-    match input {
-        South           => Some(Button::Cross),
-        East            => Some(Button::Circle),
-        West            => Some(Button::Square),
-        North           => Some(Button::Triangle),
-        DPadUp          => Some(Button::DUp),
-        DPadRight       => Some(Button::DRight),
-        DPadLeft        => Some(Button::DLeft),
-        DPadDown        => Some(Button::DDown),
-        Select          => Some(Button::Select),
-        Start           => Some(Button::Start),
-        LeftTrigger     => Some(Button::L1),
-        LeftTrigger2    => Some(Button::L2),
-        RightTrigger    => Some(Button::R1),
-        RightTrigger2   => Some(Button::R2),
-        LeftThumb       => Some(Button::L3),
-        RightThumb      => Some(Button::R3),
-        _ => None,
-    }
-}
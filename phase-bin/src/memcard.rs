@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use phase::{MemCardImage, SaveInfo};
+
+#[derive(clap::Subcommand)]
+pub enum MemcardAction {
+    /// List the saves on a card.
+    List {
+        card: PathBuf,
+    },
+    /// Create a new formatted card.
+    Create {
+        card: PathBuf,
+    },
+    /// Export a save to a .mcs, .psv or .mcb file.
+    Export {
+        card: PathBuf,
+        /// First block of the save, as shown by list.
+        block: usize,
+        output: PathBuf,
+    },
+    /// Import a save from a .mcs, .psv or .mcb file.
+    Import {
+        card: PathBuf,
+        input: PathBuf,
+    },
+    /// Delete a save.
+    Delete {
+        card: PathBuf,
+        /// First block of the save, as shown by list.
+        block: usize,
+    },
+    /// Copy a save from one card to another.
+    Copy {
+        from: PathBuf,
+        /// First block of the save, as shown by list.
+        block: usize,
+        to: PathBuf,
+    },
+    /// Convert a card between .mcr, .gme and .vmp formats.
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+    },
+}
+
+pub fn run_command(action: MemcardAction) {
+    if let Err(e) = do_action(action) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn do_action(action: MemcardAction) -> std::io::Result<()> {
+    use MemcardAction::*;
+    match action {
+        List { card } => {
+            let card = MemCardImage::open(&card)?;
+            let bad_frames = card.verify();
+            if !bad_frames.is_empty() {
+                println!("Warning: bad checksums in directory frames {:?}", bad_frames);
+            }
+            for save in card.saves() {
+                print_save(&save);
+            }
+            println!("{} blocks free", card.free_blocks());
+        },
+        Create { card } => {
+            MemCardImage::new().save(&card)?;
+        },
+        Export { card, block, output } => {
+            let card = MemCardImage::open(&card)?;
+            card.export_save_to_file(block, &output)?;
+        },
+        Import { card: card_path, input } => {
+            let mut card = MemCardImage::open(&card_path)?;
+            let block = card.import_save_from_file(&input)?;
+            card.save(&card_path)?;
+            println!("Imported to block {}", block);
+        },
+        Delete { card: card_path, block } => {
+            let mut card = MemCardImage::open(&card_path)?;
+            card.delete_save(block)?;
+            card.save(&card_path)?;
+        },
+        Copy { from, block, to } => {
+            let from = MemCardImage::open(&from)?;
+            let mut dest = MemCardImage::open(&to)?;
+            let dest_block = from.copy_save(block, &mut dest)?;
+            dest.save(&to)?;
+            println!("Copied to block {}", dest_block);
+        },
+        Convert { input, output } => {
+            MemCardImage::open(&input)?.save(&output)?;
+        },
+    }
+    Ok(())
+}
+
+fn print_save(save: &SaveInfo) {
+    println!("{:>2}: {:<20} {:>2} block(s) {}", save.first_block, save.file_name, save.block_count, save.title);
+}
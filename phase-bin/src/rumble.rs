@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use gilrs::{
+    Gilrs, GamepadId,
+    ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder}
+};
+
+/// Drives gamepad force feedback from the DualShock motors.
+#[derive(Default)]
+pub struct Rumble {
+    gamepads: HashMap<GamepadId, Motors>,
+}
+
+struct Motors {
+    large: Option<Effect>,
+    small: Option<Effect>,
+}
+
+impl Rumble {
+    /// Call when a gamepad is connected.
+    pub fn add_gamepad(&mut self, gilrs: &mut Gilrs, id: GamepadId) {
+        if !gilrs.gamepad(id).is_ff_supported() {
+            return;
+        }
+        let motors = Motors {
+            large: make_effect(gilrs, id, BaseEffectType::Strong { magnitude: u16::MAX }),
+            small: make_effect(gilrs, id, BaseEffectType::Weak { magnitude: u16::MAX }),
+        };
+        self.gamepads.insert(id, motors);
+    }
+
+    /// Call when a gamepad is disconnected.
+    pub fn remove_gamepad(&mut self, id: GamepadId) {
+        self.gamepads.remove(&id);
+    }
+
+    /// Set motor speeds of a gamepad, as reported by the console.
+    pub fn set(&self, id: GamepadId, small: u8, large: u8) {
+        let Some(motors) = self.gamepads.get(&id) else {
+            return;
+        };
+        if let Some(effect) = motors.small.as_ref() {
+            set_strength(effect, small);
+        }
+        if let Some(effect) = motors.large.as_ref() {
+            set_strength(effect, large);
+        }
+    }
+}
+
+/// Make a continuous effect. The strength is set with the gain.
+fn make_effect(gilrs: &mut Gilrs, id: GamepadId, kind: BaseEffectType) -> Option<Effect> {
+    EffectBuilder::new()
+        .add_effect(BaseEffect { kind, ..Default::default() })
+        .gamepads(&[id])
+        .gain(0.0)
+        .finish(gilrs)
+        .inspect_err(|e| println!("Could not create force feedback effect: {}", e))
+        .ok()
+}
+
+fn set_strength(effect: &Effect, strength: u8) {
+    if strength == 0 {
+        let _ = effect.stop();
+    } else {
+        let _ = effect.set_gain(strength as f32 / 255.0);
+        let _ = effect.play();
+    }
+}
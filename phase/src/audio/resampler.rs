@@ -1,5 +1,6 @@
 
 use crossbeam_channel::Receiver;
+use crate::event::{EventSender, LogLevel, Subsystem};
 use dasp::{
     frame::{Frame, Stereo},
     interpolate::sinc::Sinc,
@@ -20,15 +21,17 @@ pub struct Resampler {
     converter:          Converter<Source, Sinc<[Stereo<f32>; 2]>>,
     source_rate_recv:   Option<Receiver<f64>>,
     target_rate:        f64,
+    events:             EventSender,
 }
 
 impl Resampler {
-    pub fn new(sample_recv: Receiver<SamplePacket>, source_rate_recv: Option<Receiver<f64>>, source_sample_rate: f64, target_sample_rate: f64) -> Self {
+    pub fn new(sample_recv: Receiver<SamplePacket>, source_rate_recv: Option<Receiver<f64>>, source_sample_rate: f64, target_sample_rate: f64, events: EventSender) -> Self {
         let sinc = Sinc::new(Fixed::from([Stereo::EQUILIBRIUM; 2]));
         Resampler {
             converter:          Source::new(sample_recv).from_hz_to_hz(sinc, source_sample_rate, target_sample_rate),
             source_rate_recv:   source_rate_recv,
             target_rate:        target_sample_rate,
+            events,
         }
     }
 }
@@ -40,8 +43,9 @@ impl Iterator for Resampler {
         if let Some(source_sample_rate) = self.source_rate_recv.as_ref().and_then(|r| r.try_recv().ok()) {
             self.converter.set_hz_to_hz(source_sample_rate, self.target_rate);
         }
-        while self.converter.is_exhausted() {
-            println!("dropping audio.");
+        if self.converter.is_exhausted() {
+            self.events.log(LogLevel::Warn, Subsystem::Audio, format_args!("dropping audio."));
+            while self.converter.is_exhausted() {}
         }
         Some(self.converter.next())
     }
@@ -16,9 +16,14 @@ use super::{
     SECTOR_SIZE, DriveLoc,
     cue::*
 };
+use crate::event::{Event, EventSender, LogLevel, Subsystem};
 
 /// Hold 1 second of data in the memory buffer.
 const DISC_BUFFER_SIZE: u64 = 75 * SECTOR_SIZE;
+/// User data in a mode 1 or mode 2 form 1 sector.
+const DATA_SECTOR_SIZE: usize = 2048;
+/// Location of the ISO9660 primary volume descriptor.
+const PVD_SECTOR: u64 = 16;
 
 struct Track {
     num:        u8,
@@ -39,6 +44,8 @@ pub struct Disc {
     buffer: Vec<u8>,
     buffer_file_offset: u64,
     sector_offset: u64,
+
+    events: EventSender,
 }
 
 // Constructors
@@ -54,20 +61,20 @@ impl Disc {
     /// If the filepath points to a directory,
     /// the contents will be scanned for first a .cue,
     /// then a .bin, and will be opened accordingly.
-    pub fn new(path: &Path) -> std::io::Result<Self> {
+    pub fn new(path: &Path, events: EventSender) -> std::io::Result<Self> {
         if path.is_dir() {
             let child_paths = read_dir(path)?;
             for child in child_paths {
                 let child_path = child?.path();
                 if let Some(ext) = child_path.extension().and_then(|e| e.to_str()) {
                     if ext == "cue" {
-                        return Self::new_from_cue(&child_path);
+                        return Self::new_from_cue(&child_path, events);
                     }
                 }
             }
             // TODO: only read directory once...
             if let Some(first_child) = read_dir(path)?.next() {
-                Self::new_from_bin(&first_child?.path())
+                Self::new_from_bin(&first_child?.path(), events)
             } else {
                 // TODO: error.
                 panic!("no children in directory provided");
@@ -75,14 +82,14 @@ impl Disc {
         } else {
             if let Some(ext) = path.extension() {
                 if ext == "cue" {
-                    Self::new_from_cue(path)
+                    Self::new_from_cue(path, events)
                 } else {
                     // TODO: only if .bin?
-                    Self::new_from_bin(path)
+                    Self::new_from_bin(path, events)
                 }
             } else {
                 // TODO: error?
-                Self::new_from_bin(path)
+                Self::new_from_bin(path, events)
             }
         }
     }
@@ -90,7 +97,7 @@ impl Disc {
     /// Open directly from a binary file.
     /// 
     /// This will assume a single track.
-    fn new_from_bin(path: &Path) -> std::io::Result<Self> {
+    fn new_from_bin(path: &Path, events: EventSender) -> std::io::Result<Self> {
         let disc_file = File::open(path)?;
         let start_pos = DriveLoc {
             minute: 0x00,
@@ -112,10 +119,12 @@ impl Disc {
             buffer: vec![0; DISC_BUFFER_SIZE as usize],
             buffer_file_offset: u64::MAX,
             sector_offset: 0,
+
+            events,
         })
     }
 
-    fn new_from_cue(path: &Path) -> std::io::Result<Self> {
+    fn new_from_cue(path: &Path, events: EventSender) -> std::io::Result<Self> {
         let cue_file_str = std::fs::read_to_string(path)?;
         let folder_path = path.parent().unwrap();
         let cue_file = CueFile::parse_from_str(&cue_file_str).expect("invalid cue file");
@@ -139,7 +148,7 @@ impl Disc {
                 end_pos
             });
             current_pos = end_pos;
-            println!("Track {}: {} => {}", i + 1, start_pos, end_pos);
+            events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("Track {}: {} => {}", i + 1, start_pos, end_pos));
         }
         Ok(Self {
             tracks,
@@ -148,6 +157,8 @@ impl Disc {
             buffer: vec![0; DISC_BUFFER_SIZE as usize],
             buffer_file_offset: u64::MAX,
             sector_offset: 0,
+
+            events,
         })
     }
 }
@@ -160,13 +171,13 @@ impl Disc {
     pub fn load_from_file(&mut self, seek_loc: &DriveLoc) {
         let (track, track_pos) = self.calculate_track(seek_loc);
         if track_pos.in_pre_gap() {
-            println!("Loading track {} | pos: {} | pre-gap", track, track_pos);
+            self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("Loading track {} | pos: {} | pre-gap", track, track_pos));
             self.buffer.fill(0);
             return;
         }
         const PRE_GAP_SIZE: u64 = SECTOR_SIZE * 75 * 2;
         let seek_offset = track_pos.byte_offset() - PRE_GAP_SIZE;
-        println!("Loading track {} | pos: {} | offset: {:X}", track, track_pos, seek_offset);
+        self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("Loading track {} | pos: {} | offset: {:X}", track, track_pos, seek_offset));
         let chunk_num = seek_offset / DISC_BUFFER_SIZE;
         let target_file_offset = chunk_num * DISC_BUFFER_SIZE;
         if self.buffer_file_offset == target_file_offset && self.current_track == track {
@@ -176,12 +187,16 @@ impl Disc {
         }
         let track_idx = (track - 1) as usize;
         let disc_file = &mut self.tracks[track_idx].file;
-        disc_file.seek(SeekFrom::Start(target_file_offset)).expect("could not seek in disc");
-        disc_file.read(&mut self.buffer).expect("could not load disc data");
+        let read_result = disc_file.seek(SeekFrom::Start(target_file_offset))
+            .and_then(|_| disc_file.read(&mut self.buffer));
+        if let Err(e) = read_result {
+            self.buffer.fill(0);
+            self.events.send(Event::DiscReadError { message: format!("could not read track {} @ {:X}: {}", track, target_file_offset, e) });
+        }
         self.current_track = track;
         self.buffer_file_offset = target_file_offset;
         self.sector_offset = seek_offset - self.buffer_file_offset;
-        println!("CD load from disc @ {:X}", self.buffer_file_offset);
+        self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("CD load from disc @ {:X}", self.buffer_file_offset));
     }
 
     /// Adjust the sector offset by a relative amount.
@@ -203,6 +218,35 @@ impl Disc {
         &self.buffer[start..(start + size)]
     }
 
+    /// Find the game serial (e.g. "SLUS-00594") from the boot
+    /// executable named in SYSTEM.CNF.
+    /// 
+    /// This reads directly from the first track, without
+    /// disturbing the sector buffer.
+    pub fn read_serial(&mut self) -> Option<String> {
+        let pvd = self.read_data_sector(PVD_SECTOR)?;
+        if &pvd[1..6] != b"CD001" {
+            return None;
+        }
+        // Root directory record.
+        let root_lba = u32::from_le_bytes(pvd[158..162].try_into().unwrap()) as u64;
+        let root_size = u32::from_le_bytes(pvd[166..170].try_into().unwrap()) as usize;
+        let (cnf_lba, cnf_size) = self.find_file(root_lba, root_size, b"SYSTEM.CNF")?;
+        let cnf = self.read_data_sector(cnf_lba)?;
+        let cnf = String::from_utf8_lossy(&cnf[..cnf_size.min(DATA_SECTOR_SIZE)]).into_owned();
+        let boot_line = cnf.lines().find(|line| line.trim_start().starts_with("BOOT"))?;
+        // E.g. "BOOT = cdrom:\SLUS_005.94;1"
+        let exe_path = boot_line.split('=').nth(1)?.trim();
+        let exe_name = exe_path.rsplit(|c| c == '\\' || c == ':' || c == '/').next()?;
+        let exe_name = exe_name.split(';').next()?;
+        let serial = exe_name.replace('_', "-").replace('.', "").to_ascii_uppercase();
+        if serial.is_empty() {
+            None
+        } else {
+            Some(serial)
+        }
+    }
+
     pub fn get_track_count(&self) -> u8 {
         self.tracks.len() as u8
     }
@@ -238,6 +282,63 @@ impl Disc {
     }
 }
 
+// Internal
+impl Disc {
+    /// Read the user data of a sector in the first track.
+    fn read_data_sector(&mut self, lba: u64) -> Option<Vec<u8>> {
+        let mut sector = vec![0; SECTOR_SIZE as usize];
+        let file = &mut self.tracks.first_mut()?.file;
+        file.seek(SeekFrom::Start(lba * SECTOR_SIZE)).ok()?;
+        file.read_exact(&mut sector).ok()?;
+        // Mode 2 sectors have an 8 byte subheader after the header.
+        let data_start = if sector[15] == 2 {24} else {16};
+        Some(sector[data_start..(data_start + DATA_SECTOR_SIZE)].to_vec())
+    }
+
+    /// Find a file in an ISO9660 directory.
+    /// Returns the LBA and size of the file.
+    fn find_file(&mut self, dir_lba: u64, dir_size: usize, name: &[u8]) -> Option<(u64, usize)> {
+        let sector_count = dir_size.div_ceil(DATA_SECTOR_SIZE) as u64;
+        for sector_idx in 0..sector_count {
+            let data = self.read_data_sector(dir_lba + sector_idx)?;
+            if let Some(file) = find_record(&data, name) {
+                return Some(file);
+            }
+        }
+        None
+    }
+}
+
+/// Find a file in one sector of an ISO9660 directory.
+/// Returns the LBA and size of the file.
+fn find_record(data: &[u8], name: &[u8]) -> Option<(u64, usize)> {
+    let mut offset = 0;
+    while offset + 33 < data.len() {
+        let record_len = data[offset] as usize;
+        if record_len == 0 {
+            // Records don't cross sector boundaries.
+            break;
+        }
+        let record = &data[offset..(offset + record_len).min(data.len())];
+        offset += record_len;
+        // Skip records too short to hold their name.
+        let Some(&name_len) = record.get(32) else {
+            continue;
+        };
+        let Some(record_name) = record.get(33..(33 + name_len as usize)) else {
+            continue;
+        };
+        // Strip version suffix (";1").
+        let record_name = record_name.split(|b| *b == b';').next().unwrap_or(record_name);
+        if record_name.eq_ignore_ascii_case(name) {
+            let lba = u32::from_le_bytes(record[2..6].try_into().unwrap()) as u64;
+            let size = u32::from_le_bytes(record[10..14].try_into().unwrap()) as usize;
+            return Some((lba, size));
+        }
+    }
+    None
+}
+
 fn get_file_size(file: &File) -> DriveLoc {
     let metadata = file.metadata().expect("could not get file metadata");
     let file_len = metadata.len();
@@ -247,4 +348,47 @@ fn get_file_size(file: &File) -> DriveLoc {
     let second = (total_seconds % 60) as u8;
     let sector = (sector_count % 75) as u8;
     DriveLoc { minute, second, sector }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Make a directory record for a file.
+    fn record(name: &[u8], lba: u32, size: u32) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    #[test]
+    fn find_record_in_sector() {
+        let mut data = record(b"SYSTEM.CNF;1", 24, 68);
+        data.extend(record(b"SLUS_000.01;1", 30, 0x1000));
+        data.resize(DATA_SECTOR_SIZE, 0);
+        assert_eq!(find_record(&data, b"slus_000.01"), Some((30, 0x1000)));
+        assert_eq!(find_record(&data, b"MISSING"), None);
+    }
+
+    #[test]
+    fn short_records_are_skipped() {
+        // Too short to hold the name length.
+        let mut data = vec![0; 20];
+        data[0] = 20;
+        // Name length runs past the end of the record.
+        let mut long_name = record(b"BAD", 0, 0);
+        long_name[32] = 200;
+        data.extend(long_name);
+        data.extend(record(b"SYSTEM.CNF;1", 24, 68));
+        data.resize(DATA_SECTOR_SIZE, 0);
+        assert_eq!(find_record(&data, b"SYSTEM.CNF"), Some((24, 68)));
+        assert_eq!(find_record(&data, b"BAD"), None);
+    }
+}
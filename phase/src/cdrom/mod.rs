@@ -8,6 +8,7 @@ use dasp::frame::Stereo;
 use disc::Disc;
 use xaaudio::XAAudio;
 use crate::{interrupt::Interrupt, mem::DMADevice};
+use crate::event::{EventSender, LogLevel, Subsystem};
 use crate::utils::{
     bits::*,
     bcd::*,
@@ -82,6 +83,8 @@ pub struct CDROM {
     mute: bool,
     /// Copy-protection.
     sce_string: [u8; 4],
+    /// Serial of the inserted disc, from SYSTEM.CNF.
+    disc_serial: Option<String>,
 
     counter: usize,
     command: u8,
@@ -90,10 +93,12 @@ pub struct CDROM {
     pending_irq: bool,
     irq_latch: bool,
     pending_res_fifo: VecDeque<CommandResponse>,
+
+    events: EventSender,
 }
 
 impl CDROM {
-    pub fn new() -> Self {
+    pub fn new(events: EventSender) -> Self {
         Self {
             disc: None,
             current_loc: DriveLoc { minute: 0, second: 0, sector: 0 },
@@ -117,6 +122,7 @@ impl CDROM {
             current_sector_header: SectorHeader::default(),
             mute: false,
             sce_string: [0, 0, 0, 0],
+            disc_serial: None,
 
             counter: 0,
             command: 0,
@@ -125,6 +131,8 @@ impl CDROM {
             pending_irq: false,
             irq_latch: false,
             pending_res_fifo: VecDeque::new(),
+
+            events,
         }
     }
 
@@ -132,16 +140,23 @@ impl CDROM {
     pub fn insert_disc(&mut self, path: Option<&std::path::Path>) -> std::io::Result<()> {
         self.drive_status.insert(DriveStatus::ShellOpen);
         if let Some(path) = path {
-            let disc = Disc::new(path)?;
+            let mut disc = Disc::new(path, self.events.clone())?;
+            self.disc_serial = disc.read_serial();
             self.disc = Some(disc);
             self.read_region_string();
             self.current_loc = DriveLoc { minute: 0, second: 0, sector: 0 };
         } else {
-            self.disc = None
+            self.disc = None;
+            self.disc_serial = None;
         }
         Ok(())
     }
 
+    /// Get the serial of the inserted disc (e.g. "SLUS-00594"), if it could be found.
+    pub fn disc_serial(&self) -> Option<&str> {
+        self.disc_serial.as_deref()
+    }
+
     /// Clock the CD-ROM reader.
     /// 
     /// Returns an interrupt if it occurred.
@@ -184,11 +199,16 @@ impl CDROM {
     pub fn fetch_decoded_audio<'a> (&'a mut self) -> Option<&'a [Stereo<i16>]> {
         self.xa_audio.fetch_decoded_audio()
     }
+
+    /// The register bank currently selected by the index/status register.
+    pub fn port_index(&self) -> u8 {
+        self.index()
+    }
 }
 
 impl MemInterface for CDROM {
     fn read_byte(&mut self, addr: u32) -> u8 {
-        let data = match addr {
+        match addr {
             0x1F80_1800 => self.status.bits(),
             0x1F80_1801 => self.read_response(),
             0x1F80_1802 => self.read_data(),
@@ -198,13 +218,10 @@ impl MemInterface for CDROM {
                 _ => unreachable!()
             },
             _ => panic!("invalid CDROM addr {:X}", addr)
-        };
-        //println!("read cd {:X}.{}: {:X}", addr, self.index(), data);
-        data
+        }
     }
 
     fn write_byte(&mut self, addr: u32, data: u8) {
-        //println!("write cd {:X}.{}: {:X}", addr, self.index(), data);
         match addr {
             0x1F80_1800 => self.write_status(data),
             0x1F80_1801 => match self.index() {
@@ -500,7 +517,7 @@ impl CDROM {
     /// and as such we need to trigger interrupt 1.
     fn read_sector(&mut self) -> bool {
         // Check if we need to load from disc.
-        println!("CD read @ {}", self.current_loc);
+        self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("CD read @ {}", self.current_loc));
         if let Some(disc) = self.disc.as_mut() {
             disc.load_from_file(&self.current_loc);
             self.current_sector_header = SectorHeader::from_slice(disc.ref_sector_data(SECTOR_SYNC_BYTES, 8));
@@ -597,7 +614,7 @@ impl CDROM {
                 self.pending_irq = true;
             }
         }
-        println!("cd command: {:X}", self.command);
+        self.events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("cd command: {:X}", self.command));
         let res = match self.command {
             0x00 => self.sync(),
             0x01 => self.get_stat(),
@@ -815,7 +832,7 @@ impl CDROM {
 
     fn set_mode(&mut self) -> DriveResult<()> {
         let mode = self.read_parameter()?;
-        println!("Set mode: {:X}", mode);
+        self.events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("Set mode: {:X}", mode));
         let new_mode = DriveMode::from_bits_truncate(mode);
         if new_mode != self.mode {
             // There are a few cases of games modifying the mode
@@ -910,7 +927,7 @@ impl CDROM {
             second: from_bcd(second).ok_or(DriveError::InvalidParam)?,
             sector: from_bcd(sector).ok_or(DriveError::InvalidParam)?
         };
-        println!("Seek to {}", seek_loc);
+        self.events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("Seek to {}", seek_loc));
         self.pending_seek = Some(seek_loc);
         self.drive_status.remove(DriveStatus::ReadBits);
         self.read_data_counter = 0;
@@ -1004,7 +1021,7 @@ impl CDROM {
         self.playing = true;
         if !self.param_fifo.is_empty() {
             let track = from_bcd(self.read_parameter()?).ok_or(DriveError::InvalidParam)?;
-            println!("Play track {:02}", track);
+            self.events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("Play track {:02}", track));
             let disc = self.disc.as_ref().ok_or(DriveError::InvalidCmd)?;
             let track_seek_pos = disc.get_track_start_pos(track);
             self.current_loc = track_seek_pos;
@@ -1041,7 +1058,7 @@ impl CDROM {
     }
 
     fn get_stat(&mut self) -> DriveResult<()> {
-        println!("get stat: {:X}", self.drive_status.bits());
+        self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("get stat: {:X}", self.drive_status.bits()));
         self.send_response(&[self.drive_status.bits()], 3);
         self.drive_status.remove(DriveStatus::ShellOpen);
         self.command_complete()
@@ -1082,7 +1099,7 @@ impl CDROM {
         } else {
             0x01
         };
-        println!("get loc p | track {} | pos {} | glob {}", current_track, track_pos, self.current_loc);
+        self.events.log(LogLevel::Trace, Subsystem::CDROM, format_args!("get loc p | track {} | pos {} | glob {}", current_track, track_pos, self.current_loc));
         self.send_response(&[
             to_bcd(current_track).unwrap(),
             to_bcd(index).unwrap(),
@@ -1124,7 +1141,7 @@ impl CDROM {
             self.command_complete()
         } else if track <= track_count {
             let start_pos = disc.get_track_start_pos(track);
-            println!("Track {} pos: {}", track, start_pos);
+            self.events.log(LogLevel::Debug, Subsystem::CDROM, format_args!("Track {} pos: {}", track, start_pos));
             self.send_response(&[
                 self.drive_status.bits(),
                 to_bcd(start_pos.minute).unwrap(),
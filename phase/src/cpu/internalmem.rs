@@ -1,7 +1,8 @@
 use mips::{coproc::Coprocessor0, mem::{Data, Mem32}};
 
 use crate::{
-    AudioChannel, PlayStationConfig, cpu::cop0::SystemCoproc, io::BusIO, mem::{MemBus, ram::RAM}
+    AudioChannel, PlayStationConfig, cpu::cop0::SystemCoproc, io::BusIO, mem::{MemBus, ram::RAM, iolog::IOLogger},
+    profiler::Profiler
 };
 
 const I_CACHE_SIZE: u32 = 4 * 1024;
@@ -31,6 +32,18 @@ impl InternalMem {
     pub fn enable_audio(&mut self) -> AudioChannel {
         self.mem_bus.enable_audio()
     }
+
+    pub fn enable_profiler(&mut self, profiler: Profiler) {
+        self.mem_bus.enable_profiler(profiler);
+    }
+
+    pub fn enable_io_logger(&mut self, logger: IOLogger) {
+        self.mem_bus.enable_io_logger(logger);
+    }
+
+    pub fn set_current_pc(&mut self, pc: u32) {
+        self.mem_bus.set_current_pc(pc);
+    }
 }
 
 impl Mem32 for InternalMem {
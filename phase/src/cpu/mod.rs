@@ -5,13 +5,17 @@ use mips::{coproc::EmptyCoproc, cpu::{MIPSCore, MIPSICore, mips1::{MIPSI, MIPSII
 use internalmem::InternalMem;
 use crate::{gte::GTE, io::BusIO};
 use crate::{PlayStationConfig, AudioChannel};
+use crate::profiler::Profiler;
+use crate::mem::iolog::IOLogger;
 
 type MIPSCPU = MIPSI<InternalMem, EmptyCoproc, GTE, EmptyCoproc>;
 
 /// PlayStation CPU object.
 /// This drives the CPU and manages memory.
 pub struct CPU {
-    core: MIPSCPU
+    core: MIPSCPU,
+    /// Pass the PC to the memory bus before each step.
+    track_pc: bool,
 }
 
 impl CPU {
@@ -22,7 +26,8 @@ impl CPU {
             .build();
         core.reset();
         Self {
-            core
+            core,
+            track_pc: false,
         }
     }
 
@@ -30,13 +35,31 @@ impl CPU {
         self.core.mut_mem().enable_audio()
     }
 
+    pub fn enable_profiler(&mut self, profiler: Profiler) {
+        self.core.mut_mem().enable_profiler(profiler);
+        self.track_pc = true;
+    }
+
+    pub fn enable_io_logger(&mut self, logger: IOLogger) {
+        self.core.mut_mem().enable_io_logger(logger);
+        self.track_pc = true;
+    }
+
     /// Run the CPU. This will block until the end,
     /// so it should be called on its own thread.
     /// 
     /// The IO object is used for communication and sync.
     pub fn run(mut self) {
-        loop {
-            self.core.step();
+        if self.track_pc {
+            loop {
+                let pc = self.core.read_pc();
+                self.core.mut_mem().set_current_pc(pc);
+                self.core.step();
+            }
+        } else {
+            loop {
+                self.core.step();
+            }
         }
     }
 }
@@ -0,0 +1,214 @@
+// Log messages and notifications sent from the emulator to the frontend.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        Arc, atomic::{AtomicU8, Ordering}
+    }
+};
+
+use crossbeam_channel::{
+    Sender, Receiver, bounded
+};
+
+use crate::{Port, Slot};
+
+/// Events beyond this are dropped until the frontend catches up.
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// Severity of a log message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => LogLevel::Error,
+            1 => LogLevel::Warn,
+            2 => LogLevel::Info,
+            3 => LogLevel::Debug,
+            _ => LogLevel::Trace,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn"  => Ok(LogLevel::Warn),
+            "info"  => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!("unknown log level {}", s)),
+        }
+    }
+}
+
+/// The part of the system that produced a log message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    System,
+    CDROM,
+    GPU,
+    Peripheral,
+    MemCard,
+    Expansion,
+    Audio,
+}
+
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub level:      LogLevel,
+    pub subsystem:  Subsystem,
+    pub message:    String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:?}] {:?}: {}", self.level, self.subsystem, self.message)
+    }
+}
+
+/// Something the frontend may want to know about.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Log(LogRecord),
+    CDInserted {
+        path: PathBuf,
+    },
+    CDRemoved,
+    /// The disc could not be opened or read.
+    DiscReadError {
+        message: String,
+    },
+    MultitapConnected {
+        port: Port,
+    },
+    MultitapDisconnected {
+        port: Port,
+    },
+    ControllerConnected {
+        port: Port,
+        slot: Slot,
+    },
+    ControllerDisconnected {
+        port: Port,
+        slot: Slot,
+    },
+    MemCardInserted {
+        port: Port,
+        slot: Slot,
+        path: PathBuf,
+    },
+    MemCardRemoved {
+        port: Port,
+        slot: Slot,
+    },
+    /// The memory card contents were written back to the file.
+    MemCardWritten {
+        port: Port,
+        slot: Slot,
+    },
+    /// A memory card file was loaded with bad directory checksums.
+    /// Saves listed in these frames may be damaged.
+    MemCardChecksumMismatch {
+        path: PathBuf,
+        frames: Vec<usize>,
+    },
+    SerialLinkConnected,
+    SerialLinkDisconnected,
+    /// The game set new DualShock motor speeds.
+    /// The small motor is either off (0) or on (255).
+    RumbleChanged {
+        port: Port,
+        slot: Slot,
+        small: u8,
+        large: u8,
+    },
+    VideoModeChanged {
+        width: usize,
+        height: usize,
+        interlace: bool,
+        rgb24: bool,
+        pal: bool,
+    },
+}
+
+/// Create a connected sender and receiver.
+/// Messages above Info level are filtered by default.
+pub fn event_channel() -> (EventSender, EventReceiver) {
+    let (tx, rx) = bounded(EVENT_QUEUE_SIZE);
+    let level = Arc::new(AtomicU8::new(LogLevel::Info as u8));
+    let sender = EventSender {
+        tx,
+        level: level.clone(),
+    };
+    let receiver = EventReceiver {
+        rx,
+        level,
+    };
+    (sender, receiver)
+}
+
+/// Held by each part of the system that produces events.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: Sender<Event>,
+    level: Arc<AtomicU8>,
+}
+
+impl EventSender {
+    /// Check if messages of this level will be sent.
+    #[inline]
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        (level as u8) <= self.level.load(Ordering::Relaxed)
+    }
+
+    /// Send a log message, if its level is enabled.
+    /// The message is only formatted if it is going to be sent.
+    #[inline]
+    pub fn log(&self, level: LogLevel, subsystem: Subsystem, args: fmt::Arguments) {
+        if self.enabled(level) {
+            self.send(Event::Log(LogRecord {
+                level,
+                subsystem,
+                message: args.to_string(),
+            }));
+        }
+    }
+
+    /// Send an event. If the queue is full, the event is dropped.
+    pub fn send(&self, event: Event) {
+        let _ = self.tx.try_send(event);
+    }
+}
+
+/// Lives in PlayStation, and hands events to the frontend.
+pub struct EventReceiver {
+    rx: Receiver<Event>,
+    level: Arc<AtomicU8>,
+}
+
+impl EventReceiver {
+    pub fn set_log_level(&self, level: LogLevel) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    pub fn poll(&self) -> impl Iterator<Item = Event> + '_ {
+        self.rx.try_iter()
+    }
+}
@@ -1,6 +1,7 @@
 // Expansion port things
 
 use crate::utils::interface::MemInterface;
+use crate::event::{EventSender, LogLevel, Subsystem};
 
 pub struct ExpansionPort1 {
 
@@ -25,19 +26,23 @@ impl MemInterface for ExpansionPort1 {
 }
 
 pub struct ExpansionPort2 {
-    boot_status: u8
+    boot_status: u8,
+
+    events: EventSender,
 }
 
 impl ExpansionPort2 {
-    pub fn new() -> Self {
+    pub fn new(events: EventSender) -> Self {
         Self {
             boot_status: 0,
+
+            events,
         }
     }
 
     fn write_boot_status(&mut self, data: u8) {
         self.boot_status = data;
-        println!("BOOT STAT {:X}", data);
+        self.events.log(LogLevel::Debug, Subsystem::Expansion, format_args!("BOOT STAT {:X}", data));
     }
 }
 
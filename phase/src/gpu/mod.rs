@@ -1,5 +1,8 @@
 mod videostate;
 mod renderer;
+mod timing;
+#[cfg(test)]
+mod test;
 
 use std::sync::{
     Arc, Mutex
@@ -8,18 +11,20 @@ use std::sync::{
 use mips::mem::Data;
 
 use crossbeam_channel::{
-    Receiver, Sender, unbounded
+    Receiver, unbounded
 };
 
 use crate::{
     Frame,
+    event::{Event, EventSender},
     mem::DMADevice,
     utils::{bits::*, interface::MemInterface}
 };
 
 use renderer::*;
 use videostate::StateMachine;
-pub use videostate::GPUClockRes;
+use timing::DrawingArea;
+pub use videostate::{GPUClockRes, BeamPosition};
 
 /// Graphics processing unit
 pub struct GPU {
@@ -32,8 +37,11 @@ pub struct GPU {
     draw_area_top_left: u32,
     draw_area_bottom_right: u32,
     draw_offset: u32,
+    /// Set by GP1(09h): allows GP0(E1h) to disable textures.
+    allow_tex_disable: bool,
+    draw_tex_disable: bool,
 
-    renderer_tx: Sender<RendererCmd>,
+    commands: CommandSender,
     frame_rx: Receiver<()>,
     vram_rx: Receiver<u32>,
 
@@ -46,17 +54,25 @@ pub struct GPU {
     // DMA
     data_words: usize,
     block_count: usize,
+
+    // Timing
+    /// CPU cycles until the current commands finish drawing.
+    busy_cycles: usize,
+    /// Words sent while the GPU is busy.
+    fifo_words: usize,
+
+    events: EventSender,
 }
 
 impl GPU {
-    pub fn new(frame: Arc<Mutex<Frame>>) -> Self {
-        let (renderer_tx, renderer_rx) = unbounded(); // TODO: technically FIFO should be bounded...
+    pub fn new(frame: Arc<Mutex<Frame>>, events: EventSender) -> Self {
+        let (commands, command_rx) = command_ring();
         let init_status = GPUStatus::CommandReady | GPUStatus::DMARecvReady;
         let (frame_tx, frame_rx) = unbounded();
         let (vram_tx, vram_rx) = unbounded();
         // Start render thread.
         std::thread::spawn(|| {
-            let mut renderer = Renderer::new(renderer_rx, frame_tx, vram_tx, frame);
+            let mut renderer = Renderer::new(command_rx, frame_tx, vram_tx, frame);
             renderer.run();
         });
         Self {
@@ -68,8 +84,10 @@ impl GPU {
             draw_area_top_left: 0,
             draw_area_bottom_right: 0,
             draw_offset: 0,
+            allow_tex_disable: false,
+            draw_tex_disable: false,
 
-            renderer_tx,
+            commands,
             frame_rx,
             vram_rx,
 
@@ -80,30 +98,75 @@ impl GPU {
 
             data_words: 0,
             block_count: 0,
+
+            busy_cycles: 0,
+            fifo_words: 0,
+
+            events,
         }
     }
 
     pub fn clock(&mut self, cycles: usize) -> GPUClockRes {
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+        if self.busy_cycles == 0 {
+            self.fifo_words = 0;
+        }
         let res = self.state.clock(cycles);
         res
     }
 
+    /// Find the beam position for a point on the screen, for light guns.
+    pub fn beam_position(&self, x: f32, y: f32) -> Option<BeamPosition> {
+        self.state.beam_position(x, y)
+    }
+
+    /// Raise the lightpen interrupt each frame when the beam reaches the target.
+    pub fn set_lightpen_target(&mut self, target: Option<BeamPosition>) {
+        self.state.set_lightpen_target(target);
+    }
+
+    pub fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode) {
+        self.commands.send(RendererCmd::DeinterlaceMode(mode));
+    }
+
+    pub fn set_overscan_mode(&mut self, mode: OverscanMode) {
+        self.state.set_overscan_mode(mode);
+        self.commands.send(RendererCmd::OverscanMode(mode));
+    }
+
+    pub fn set_texture_cache(&mut self, enable: bool) {
+        self.commands.send(RendererCmd::TextureCache(enable));
+    }
+
+    pub fn set_resolution_scale(&mut self, scale: usize) {
+        self.commands.send(RendererCmd::ResolutionScale(scale));
+    }
+
+    pub fn set_render_threads(&mut self, threads: usize) {
+        self.commands.send(RendererCmd::RenderThreads(threads));
+    }
+
+    pub fn set_true_color(&mut self, enable: bool) {
+        self.commands.send(RendererCmd::TrueColor(enable));
+    }
+
     /// Check if DMA is ready.
     pub fn dma_ready(&mut self) -> bool {
+        self.update_dma_status();
         self.status.contains(GPUStatus::DMARequest)
     }
 
     pub fn dma_cmd_ready(&self) -> bool {
-        self.status.contains(GPUStatus::CommandReady)
+        self.ready_status().contains(GPUStatus::CommandReady)
     }
 
     /// This extracts a frame from the renderer. It needs to communicate across a thread.
     /// It should be called at the _start_ of each frame.
     pub fn get_frame(&mut self) {
         let interlace_state = self.state.get_interlace_state();
-        if self.renderer_tx.send(RendererCmd::GetFrame(interlace_state)).is_ok() {
-            let _ = self.frame_rx.recv();
-        }
+        self.commands.send(RendererCmd::GetFrame(interlace_state));
+        self.commands.flush();
+        let _ = self.frame_rx.recv();
     }
 }
 
@@ -135,13 +198,21 @@ impl DMADevice for GPU {
         self.send_gp0(data);
         1
     }
+
+    /// Hold DMA while the FIFO is full, until drawing finishes.
+    fn wait_cycles(&mut self) -> Option<usize> {
+        self.fifo_full().then_some(self.busy_cycles.max(1))
+    }
 }
 
 // Internal
 impl GPU {
     fn send_gp0(&mut self, data: u32) {
+        if self.busy_cycles > 0 {
+            self.fifo_words += 1;
+        }
         if self.data_words > 0 {
-            let _ = self.renderer_tx.send(RendererCmd::GP0Data(data));
+            self.commands.send(RendererCmd::GP0Data(data));
             self.status.remove(GPUStatus::DMARequest);
             self.data_words -= 1;
             self.block_count -= 1;
@@ -161,6 +232,8 @@ impl GPU {
 
     fn recv_response(&mut self) -> u32 {
         if self.data_words > 0 {
+            // The renderer needs the read command before it can reply.
+            self.commands.flush();
             self.read_reg = self.vram_rx.recv().unwrap_or_default();
             self.status.remove(GPUStatus::DMARequest);
             self.data_words -= 1;
@@ -180,7 +253,6 @@ impl GPU {
     }
 
     fn send_gp0_command(&mut self, data: u32) {
-        //println!("GP0 send: {:X}", data);
         if self.pending_command_words == 0 {
             //self.status.remove(GPUStatus::CommandReady);
             self.pending_command = (data >> 24) as u8;
@@ -238,15 +310,15 @@ impl GPU {
                 0x20 | 0x21 => Some(DrawTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x22 | 0x23 => Some(DrawTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
                 0x24 => Some(DrawTexBlendTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
-                0x25 => Some(DrawTexTri{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: false}),
+                0x25 => Some(DrawTexTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x26 => Some(DrawTexBlendTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
-                0x27 => Some(DrawTexTri{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: true}),
+                0x27 => Some(DrawTexTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
                 0x28 | 0x29 => Some(DrawQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x2A | 0x2B => Some(DrawQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
                 0x2C => Some(DrawTexBlendQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
-                0x2D => Some(DrawTexQuad{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: false}),
+                0x2D => Some(DrawTexQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x2E => Some(DrawTexBlendQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
-                0x2F => Some(DrawTexQuad{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: true}),
+                0x2F => Some(DrawTexQuad{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
 
                 0x30 | 0x31 => Some(DrawShadedTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x32 | 0x33 => Some(DrawShadedTri{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
@@ -274,27 +346,27 @@ impl GPU {
                 0x60 | 0x61 => Some(DrawRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x62 | 0x63 => Some(DrawRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
                 0x64 => Some(DrawTexBlendedRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
-                0x65 => Some(DrawTexRect{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: false}),
+                0x65 => Some(DrawTexRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: false}),
                 0x66 => Some(DrawTexBlendedRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
-                0x67 => Some(DrawTexRect{params: std::array::from_fn(|n| self.command_data[n+1]), transparent: true}),
+                0x67 => Some(DrawTexRect{params: std::array::from_fn(|n| self.command_data[n]), transparent: true}),
                 0x68 => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: false}),
                 0x6A => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: true}),
                 0x6C => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: false}),
-                0x6D => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 1, transparent: false}),
+                0x6D => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: false}),
                 0x6E => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: true}),
-                0x6F => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 1, transparent: true}),
+                0x6F => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 1, transparent: true}),
                 0x70 => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: false}),
                 0x72 => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: true}),
                 0x74 => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: false}),
-                0x75 => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 8, transparent: false}),
+                0x75 => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: false}),
                 0x76 => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: true}),
-                0x77 => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 8, transparent: true}),
+                0x77 => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 8, transparent: true}),
                 0x78 => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: false}),
                 0x7A => Some(DrawFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: true}),
                 0x7C => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: false}),
-                0x7D => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 16, transparent: false}),
+                0x7D => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: false}),
                 0x7E => Some(DrawTexBlendedFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: true}),
-                0x7F => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n+1]), size: 16, transparent: true}),
+                0x7F => Some(DrawTexFixedRect{params: std::array::from_fn(|n| self.command_data[n]), size: 16, transparent: true}),
 
                 0x80 => Some(BlitVRAMtoVRAM{params: std::array::from_fn(|n| self.command_data[n+1])}),
                 0xA0 => Some(self.blit_cpu_to_vram()),
@@ -311,13 +383,17 @@ impl GPU {
             };
             self.command_data.clear();
             if let Some(command) = gp0_command {
-                let _ = self.renderer_tx.send(RendererCmd::GP0(command));
+                let area = DrawingArea::new(self.draw_area_top_left, self.draw_area_bottom_right, self.draw_offset);
+                let check_mask = self.status.contains(GPUStatus::MaskDrawing);
+                // The GPU clock runs at 11/7 of the CPU clock.
+                let gpu_cycles = timing::command_cycles(&command, &area, check_mask);
+                self.busy_cycles += (gpu_cycles * 7).div_ceil(11);
+                self.commands.send(RendererCmd::GP0(command));
             }
         }
     }
 
     fn send_gp1_command(&mut self, data: u32) {
-        //println!("GP1 command: {:X}", data);
         let command = (data >> 24) as u8;
         match command {
             0x00 => self.reset(),
@@ -336,23 +412,40 @@ impl GPU {
     }
 
     fn read_status(&self) -> u32 {
+        let mut status = self.ready_status();
+        status.set(GPUStatus::InterlaceOdd, self.state.get_interlace_bit());
+        status.set(GPUStatus::InterlaceField, self.state.get_interlace_state() != InterlaceState::Even);
+        status.bits()
+    }
+
+    /// Status, with the GPU busy while drawing.
+    /// This only depends on emulated state, never on how far behind the render thread is.
+    /// So the command ring is left out: its occupancy changes with host timing,
+    /// and a game polling GPUSTAT would behave differently from run to run.
+    fn ready_status(&self) -> GPUStatus {
         let mut status = self.status;
-        // TODO: deal with this elsewhere...
-        if status.contains(GPUStatus::Interlace) {
-            if self.state.get_interlace_bit() {
-                status |= GPUStatus::InterlaceOdd;
-            }
+        if self.busy_cycles > 0 {
+            // Reads from VRAM wait for drawing to finish.
+            status.remove(GPUStatus::CommandReady | GPUStatus::VRAMSendReady);
         }
-        status.bits()
+        if self.fifo_full() {
+            status.remove(GPUStatus::DMARecvReady);
+        }
+        status
+    }
+
+    fn fifo_full(&self) -> bool {
+        const FIFO_SIZE: usize = 16;
+        self.fifo_words >= FIFO_SIZE
     }
 
     fn update_dma_status(&mut self) {
         let dma_mode = self.status.intersection(GPUStatus::DMAMode).bits() >> 29;
         match dma_mode {
             0b00 => self.status.remove(GPUStatus::DMARequest),
-            0b01 => self.status.insert(GPUStatus::DMARequest), // Just assume FIFO is never empty...
-            0b10 => self.status.set(GPUStatus::DMARequest, self.status.contains(GPUStatus::DMARecvReady)),
-            0b11 => self.status.set(GPUStatus::DMARequest, self.status.contains(GPUStatus::VRAMSendReady)),
+            0b01 => self.status.set(GPUStatus::DMARequest, !self.fifo_full()),
+            0b10 => self.status.set(GPUStatus::DMARequest, self.ready_status().contains(GPUStatus::DMARecvReady)),
+            0b11 => self.status.set(GPUStatus::DMARequest, self.ready_status().contains(GPUStatus::VRAMSendReady)),
             _ => unreachable!("invalid DMA mode")
         }
     }
@@ -368,13 +461,16 @@ impl GPU {
         self.display_vram_offset(0);
         let reset_x = 0x200 | ((0x200 + 256 * 10) << 12);
         self.display_range_x(reset_x);
-        let reset_y = 0x010 | ((0x010 + 240) << 12);
+        let reset_y = 0x010 | ((0x010 + 240) << 10);
         self.display_range_y(reset_y);
         self.display_mode(0);
     }
 
     fn reset_command_buf(&mut self) {
         // TODO: flush tx.
+        // Anything waiting in the FIFO is dropped.
+        self.busy_cycles = 0;
+        self.fifo_words = 0;
     }
 
     fn acknowledge_irq(&mut self) {
@@ -384,7 +480,7 @@ impl GPU {
     fn display_enable(&mut self, param: u32) {
         let enable = !test_bit!(param, 0);
         self.status.set(GPUStatus::DisplayEnable, enable);
-        let _ = self.renderer_tx.send(RendererCmd::DisplayEnable(enable));
+        self.commands.send(RendererCmd::DisplayEnable(enable));
     }
 
     fn data_request(&mut self, param: u32) {
@@ -395,33 +491,42 @@ impl GPU {
     }
 
     fn display_vram_offset(&mut self, param: u32) {
-        let _ = self.renderer_tx.send(RendererCmd::DisplayVRAMOffset(param));
+        self.commands.send(RendererCmd::DisplayVRAMOffset(param));
     }
 
     fn display_range_x(&mut self, param: u32) {
-        let _ = self.renderer_tx.send(RendererCmd::DisplayXRange(param));
+        self.state.set_display_range_x(param & 0xFFF, (param >> 12) & 0xFFF);
+        self.commands.send(RendererCmd::DisplayXRange(param));
     }
 
     fn display_range_y(&mut self, param: u32) {
-        let _ = self.renderer_tx.send(RendererCmd::DisplayYRange(param));
+        self.state.set_display_range_y(param & 0x3FF, (param >> 10) & 0x3FF);
+        self.commands.send(RendererCmd::DisplayYRange(param));
     }
 
     fn display_mode(&mut self, param: u32) {
+        let prev_mode = self.status.intersection(GPUStatus::DispModeFlags);
         self.status.remove(GPUStatus::DispModeFlags);
         self.status.insert(GPUStatus::from_bits_truncate((param & 0x3F) << 17));
         self.status.insert(GPUStatus::from_bits_truncate((param & 0x40) << 10)); // HRes low bit
         self.status.insert(GPUStatus::from_bits_truncate((param & 0x80) << 7)); // Reverseflag
         let h_res = self.status.h_res();
         let v_res = self.status.v_res();
-        if self.status.contains(GPUStatus::PALMode) {
-            panic!("PAL mode unsupported!");
-        } else {
-            self.state.set_h_res_ntsc(h_res);
-        }
+        let pal = self.status.contains(GPUStatus::PALMode);
+        self.state.set_display_mode(h_res, v_res, pal);
         let interlace = self.status.contains(GPUStatus::Interlace);
         self.state.set_interlace(interlace);
         let rgb24 = self.status.contains(GPUStatus::ColorDepth);
-        let _ = self.renderer_tx.send(RendererCmd::DisplayMode{h_res, v_res, interlace, rgb24});
+        self.commands.send(RendererCmd::DisplayMode{h_res, v_res, interlace, rgb24, pal});
+        if self.status.intersection(GPUStatus::DispModeFlags).bits() != prev_mode.bits() {
+            self.events.send(Event::VideoModeChanged {
+                width: h_res,
+                height: v_res,
+                interlace,
+                rgb24,
+                pal,
+            });
+        }
     }
 
     fn get_gpu_info(&mut self, param: u32) {
@@ -436,9 +541,10 @@ impl GPU {
     }
 
     fn tex_disable(&mut self, param: u32) {
-        let disable = test_bit!(param, 0);
+        self.allow_tex_disable = test_bit!(param, 0);
+        let disable = self.draw_tex_disable && self.allow_tex_disable;
         self.status.set(GPUStatus::TexDisable, disable);
-        let _ = self.renderer_tx.send(RendererCmd::TexDisable(disable));
+        self.commands.send(RendererCmd::TexDisable(disable));
     }
 }
 
@@ -457,13 +563,13 @@ impl GPU {
         let low_bits = param & 0x7FF;
         self.status.remove(GPUStatus::DrawModeFlags);
         self.status.insert(GPUStatus::from_bits_truncate(low_bits));
-        self.status.set(GPUStatus::TexDisable, test_bit!(param, 11));
-
-        // TODO: x-flip and y-flip
-        let _x_flip = test_bit!(param, 12);
-        let _y_flip = test_bit!(param, 13);
-
-        GP0Command::DrawMode(self.status)
+        self.draw_tex_disable = test_bit!(param, 11);
+        self.status.set(GPUStatus::TexDisable, self.draw_tex_disable && self.allow_tex_disable);
+        GP0Command::DrawMode {
+            status: self.status,
+            x_flip: test_bit!(param, 12),
+            y_flip: test_bit!(param, 13),
+        }
     }
 
     fn texture_window_setting(&mut self, param: u32) -> GP0Command {
@@ -590,6 +696,31 @@ impl InterlaceState {
     }
 }
 
+/// How 480-line interlaced video is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeinterlaceMode {
+    /// Combine the lines of the last two fields.
+    /// Sharp, but moving objects show combing.
+    #[default]
+    Weave,
+    /// Show only the lines of the latest field, each doubled.
+    Bob,
+    /// Weave still parts of the picture, and bob the parts that move.
+    Adaptive,
+}
+
+/// How much of the picture around the game's display area is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverscanMode {
+    /// Everything a TV could show, including the borders.
+    Full,
+    /// The area every TV shows, cropping the edges.
+    SafeArea,
+    /// Exactly the area set by the game.
+    #[default]
+    GameArea,
+}
+
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug)]
     pub struct GPUStatus: u32 {
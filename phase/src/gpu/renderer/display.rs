@@ -0,0 +1,95 @@
+// Works out which part of the video signal ends up in the frame.
+
+use super::Size;
+use crate::gpu::OverscanMode;
+
+/// Whole picture a TV could show, in GPU cycles after h-sync.
+/// This is also the width of a 4:3 picture.
+const FULL_X: (u32, u32) = (488, 3288);
+/// Area that is visible on any TV.
+const SAFE_X: (u32, u32) = (608, 3168);
+
+/// Whole picture a TV could show, in lines after v-sync.
+const NTSC_FULL_Y: (u32, u32) = (16, 256);
+const NTSC_SAFE_Y: (u32, u32) = (24, 248);
+const PAL_FULL_Y: (u32, u32) = (20, 308);
+const PAL_SAFE_Y: (u32, u32) = (28, 300);
+
+/// Position of the displayed VRAM area inside the frame.
+#[derive(Clone, Copy)]
+pub struct DisplayArea {
+    /// Size of the frame, in pixels.
+    pub frame_size: Size,
+    /// Frame position of the first displayed VRAM pixel.
+    /// Negative if the left or top of the display is cropped.
+    pub x: i32,
+    pub y: i32,
+    /// Size of the display, in VRAM pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Width of a pixel divided by its height.
+    pub pixel_aspect_ratio: f32,
+
+    /// Start of the display, in GPU cycles after h-sync and lines after v-sync.
+    range_start: (u32, u32),
+    cycles_per_dot: u32,
+    line_scale: u32,
+}
+
+impl DisplayArea {
+    /// Find the display area for the display range registers.
+    /// Ranges are in GPU cycles (X) and lines (Y), as set by GP1(06h) and GP1(07h).
+    pub fn new(mode: OverscanMode, resolution: Size, pal: bool, range_x: (u32, u32), range_y: (u32, u32)) -> Self {
+        let cycles_per_dot = match resolution.width {
+            256 => 10,
+            320 => 8,
+            368 => 7,
+            512 => 5,
+            _ => 4,
+        };
+        let line_scale = if resolution.height > 240 {2} else {1};
+        // Hardware rounds the width to a multiple of 4 dots.
+        let width = ((range_x.1.saturating_sub(range_x.0) / cycles_per_dot + 2) & !3).min(resolution.width as u32);
+        let height = range_y.1.saturating_sub(range_y.0) * line_scale;
+        let (full_y, safe_y) = if pal {(PAL_FULL_Y, PAL_SAFE_Y)} else {(NTSC_FULL_Y, NTSC_SAFE_Y)};
+        let (window_x, window_y) = match mode {
+            OverscanMode::Full => (FULL_X, full_y),
+            OverscanMode::SafeArea => (SAFE_X, safe_y),
+            OverscanMode::GameArea => (range_x, range_y),
+        };
+        let frame_width = window_x.1.saturating_sub(window_x.0) / cycles_per_dot;
+        let frame_height = window_y.1.saturating_sub(window_y.0) * line_scale;
+        let full_width = (FULL_X.1 - FULL_X.0) as f32 / cycles_per_dot as f32;
+        let full_height = ((full_y.1 - full_y.0) * line_scale) as f32;
+        Self {
+            frame_size: Size {
+                width: if mode == OverscanMode::GameArea {width} else {frame_width}.max(1) as u16,
+                height: frame_height.max(1) as u16,
+            },
+            x: (range_x.0 as i32 - window_x.0 as i32) / cycles_per_dot as i32,
+            y: (range_y.0 as i32 - window_y.0 as i32) * line_scale as i32,
+            width, height,
+            pixel_aspect_ratio: (4.0 / 3.0) * full_height / full_width,
+
+            range_start: (range_x.0, range_y.0),
+            cycles_per_dot,
+            line_scale,
+        }
+    }
+
+    /// Find where the beam is when a point of the frame is drawn:
+    /// GPU cycles after h-sync, and lines after v-sync.
+    ///
+    /// X and Y should be between 0 and 1, covering the frame.
+    /// Returns None if the point is outside the displayed area.
+    pub fn beam_position(&self, x: f32, y: f32) -> Option<(f64, usize)> {
+        let dot = x * self.frame_size.width as f32 - self.x as f32;
+        let row = y * self.frame_size.height as f32 - self.y as f32;
+        if dot < 0.0 || dot >= self.width as f32 || row < 0.0 || row >= self.height as f32 {
+            return None;
+        }
+        let h_cycles = self.range_start.0 as f64 + dot as f64 * self.cycles_per_dot as f64;
+        let line = self.range_start.1 as usize + row as usize / self.line_scale as usize;
+        Some((h_cycles, line))
+    }
+}
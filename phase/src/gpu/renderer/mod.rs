@@ -1,21 +1,34 @@
 mod software;
+mod display;
+mod texcache;
+mod raster;
+mod shadow;
+mod vram;
+mod threaded;
+mod transport;
+mod truecolor;
+#[cfg(test)]
+mod test;
 
 use std::sync::{
     Arc, Mutex
 };
 
 use crossbeam_channel::{
-    Sender, Receiver
+    Sender
 };
 
 use crate::{
     Frame
 };
 use super::{
-    InterlaceState, GPUStatus
+    InterlaceState, DeinterlaceMode, OverscanMode, GPUStatus
 };
 
 use software::SoftwareRenderer;
+use threaded::ThreadedRenderer;
+pub use transport::{command_ring, CommandSender, CommandReceiver};
+pub use display::DisplayArea;
 
 const VRAM_SIZE: usize = 1024 * 1024;
 
@@ -43,8 +56,17 @@ pub enum RendererCmd {
         v_res: usize,
         interlace: bool,
         rgb24: bool,
+        pal: bool,
     },
     TexDisable(bool),
+
+    // Settings:
+    DeinterlaceMode(DeinterlaceMode),
+    OverscanMode(OverscanMode),
+    TextureCache(bool),
+    ResolutionScale(usize),
+    RenderThreads(usize),
+    TrueColor(bool),
 }
 
 #[derive(Debug)]
@@ -53,13 +75,13 @@ pub enum GP0Command {
     FillRectangle([u32; 3]),
 
     DrawTri{params: [u32; 4], transparent: bool},
-    DrawTexTri{params: [u32; 6], transparent: bool},
+    DrawTexTri{params: [u32; 7], transparent: bool},
     DrawTexBlendTri{params: [u32; 7], transparent: bool},
     DrawShadedTri{params: [u32; 6], transparent: bool},
     DrawTexShadedTri{params: [u32; 9], transparent: bool, use_shading: bool},
 
     DrawQuad{params: [u32; 5], transparent: bool},
-    DrawTexQuad{params: [u32; 8], transparent: bool},
+    DrawTexQuad{params: [u32; 9], transparent: bool},
     DrawTexBlendQuad{params: [u32; 9], transparent: bool},
     DrawShadedQuad{params: [u32; 8], transparent: bool},
     DrawTexShadedQuad{params: [u32; 12], transparent: bool, use_shading: bool},
@@ -68,17 +90,17 @@ pub enum GP0Command {
     DrawShadedLine{params: [u32; 4], transparent: bool},
 
     DrawRect{params: [u32; 3], transparent: bool},
-    DrawTexRect{params: [u32; 3], transparent: bool},
+    DrawTexRect{params: [u32; 4], transparent: bool},
     DrawTexBlendedRect{params: [u32; 4], transparent: bool},
     DrawFixedRect{params: [u32; 2], size: u16, transparent: bool},
-    DrawTexFixedRect{params: [u32; 2], size: u16, transparent: bool},
+    DrawTexFixedRect{params: [u32; 3], size: u16, transparent: bool},
     DrawTexBlendedFixedRect{params: [u32; 3], size: u16, transparent: bool},
 
     BlitVRAMtoVRAM{params: [u32; 3]},
     BlitCPUtoVRAM{params: [u32; 2]},
     BlitVRAMtoCPU{params: [u32; 2]},
 
-    DrawMode(GPUStatus),
+    DrawMode{status: GPUStatus, x_flip: bool, y_flip: bool},
     TexWindow(u32),
     DrawAreaTopLeft(u32),
     DrawAreaBottomRight(u32),
@@ -94,7 +116,7 @@ pub enum GP0Command {
 /// It also manages VRAM.
 pub struct Renderer {
     // Comms
-    command_rx: Receiver<RendererCmd>,
+    command_rx: CommandReceiver,
     frame_tx: Sender<()>,
     vram_tx: Sender<u32>,
 
@@ -107,8 +129,8 @@ pub struct Renderer {
 }
 
 impl Renderer {
-    pub fn new(command_rx: Receiver<RendererCmd>, frame_tx: Sender<()>, vram_tx: Sender<u32>, frame: Arc<Mutex<Frame>>) -> Self {
-        let renderer = Box::new(SoftwareRenderer::new());
+    pub fn new(command_rx: CommandReceiver, frame_tx: Sender<()>, vram_tx: Sender<u32>, frame: Arc<Mutex<Frame>>) -> Self {
+        let renderer = Box::new(ThreadedRenderer::new(SoftwareRenderer::new()));
         frame.lock().unwrap().resize((320, 240));
         Self {
             command_rx,
@@ -125,7 +147,7 @@ impl Renderer {
 
     /// Run in a separate thread.
     pub fn run(&mut self) {
-        while let Ok(cmd) = self.command_rx.recv() {
+        while let Some(cmd) = self.command_rx.recv() {
             self.handle_command(cmd);
         }
     }
@@ -168,7 +190,7 @@ impl Renderer {
             GP0(BlitCPUtoVRAM{params})  => self.blit_cpu_to_vram(&params),
             GP0(BlitVRAMtoCPU{params})  => self.blit_vram_to_cpu(&params),
 
-            GP0(DrawMode(status))               => self.set_draw_mode(status),
+            GP0(DrawMode{status, x_flip, y_flip}) => self.set_draw_mode(status, x_flip, y_flip),
             GP0(TexWindow(param))               => self.texture_window_setting(param),
             GP0(DrawAreaTopLeft(param))         => self.set_draw_area_top_left(param),
             GP0(DrawAreaBottomRight(param))     => self.set_draw_area_bottom_right(param),
@@ -179,14 +201,21 @@ impl Renderer {
             DisplayVRAMOffset(offset)   => self.display_vram_offset(offset),
             DisplayXRange(range)        => self.display_range_x(range),
             DisplayYRange(range)        => self.display_range_y(range),
-            DisplayMode{h_res, v_res, interlace, rgb24}  => self.display_mode(h_res, v_res, interlace, rgb24),
+            DisplayMode{h_res, v_res, interlace, rgb24, pal}  => self.display_mode(h_res, v_res, interlace, rgb24, pal),
             TexDisable(disable)         => self.tex_disable(disable),
+
+            DeinterlaceMode(mode)       => self.renderer.set_deinterlace_mode(mode),
+            OverscanMode(mode)          => self.renderer.set_overscan_mode(mode),
+            TextureCache(enable)        => self.renderer.set_texture_cache(enable),
+            ResolutionScale(scale)      => self.renderer.set_resolution_scale(scale),
+            RenderThreads(threads)      => self.renderer.set_render_threads(threads),
+            TrueColor(enable)           => self.renderer.set_true_color(enable),
         }
         None
     }
 
     fn get_data(&mut self) -> u32 {
-        while let Ok(cmd) = self.command_rx.recv() {
+        while let Some(cmd) = self.command_rx.recv() {
             if let Some(data) = self.handle_command(cmd) {
                 return data;
             }
@@ -230,25 +259,20 @@ impl Renderer {
         self.renderer.set_display_range_y(begin, end);
     }
 
-    fn display_mode(&mut self, h_res: usize, v_res: usize, interlace: bool, rgb24: bool) {
-        if DEBUG_MODE {
-            self.frame.lock().unwrap().resize((1024, 512));
-        } else {
-            self.frame.lock().unwrap().resize((h_res, v_res));
-        }
-        self.renderer.set_display_resolution(Size { width: h_res as u16, height: v_res as u16 }, interlace);
+    fn display_mode(&mut self, h_res: usize, v_res: usize, interlace: bool, rgb24: bool, pal: bool) {
+        self.renderer.set_display_resolution(Size { width: h_res as u16, height: v_res as u16 }, interlace, pal);
         self.renderer.set_color_depth(rgb24);
     }
 
-    fn tex_disable(&mut self, _disable: bool) {
-        // TODO.
+    fn tex_disable(&mut self, disable: bool) {
+        self.renderer.set_tex_disable(disable);
     }
 }
 
 // GP0.
 impl Renderer {
     fn clear_cache(&mut self) {
-        // TODO.
+        self.renderer.clear_texture_cache();
     }
 
     /// Fill a rectangle.
@@ -273,17 +297,19 @@ impl Renderer {
         self.renderer.draw_triangle_flat(&vertices, color, transparent);
     }
 
-    fn draw_textured_tri(&mut self, params: &[u32; 6], transparent: bool) {
-        let vertex_1 = params[0];
-        let texcoord_1 = params[1];
-        let vertex_2 = params[2];
-        let texcoord_2 = params[3];
-        let vertex_3 = params[4];
-        let texcoord_3 = params[5];
+    fn draw_textured_tri(&mut self, params: &[u32; 7], transparent: bool) {
+        // The colour is only used if textures are disabled.
+        let rgb = params[0];
+        let vertex_1 = params[1];
+        let texcoord_1 = params[2];
+        let vertex_2 = params[3];
+        let texcoord_2 = params[4];
+        let vertex_3 = params[5];
+        let texcoord_3 = params[6];
         let vertices = [
-            Vertex::from_xy(vertex_1).set_tex(texcoord_1),
-            Vertex::from_xy(vertex_2).set_tex(texcoord_2),
-            Vertex::from_xy(vertex_3).set_tex(texcoord_3),
+            Vertex::from_xy(vertex_1).set_col(rgb).set_tex(texcoord_1),
+            Vertex::from_xy(vertex_2).set_col(rgb).set_tex(texcoord_2),
+            Vertex::from_xy(vertex_3).set_col(rgb).set_tex(texcoord_3),
         ];
         let tex_info = TexInfo::from_data(texcoord_1, texcoord_2);
         self.renderer.draw_triangle_tex(&vertices, &tex_info, transparent);
@@ -357,20 +383,22 @@ impl Renderer {
         self.renderer.draw_triangle_flat(&vertices[1..4], color, transparent);
     }
 
-    fn draw_textured_quad(&mut self, params: &[u32; 8], transparent: bool) {
-        let vertex_1 = params[0];
-        let texcoord_1 = params[1];
-        let vertex_2 = params[2];
-        let texcoord_2 = params[3];
-        let vertex_3 = params[4];
-        let texcoord_3 = params[5];
-        let vertex_4 = params[6];
-        let texcoord_4 = params[7];
+    fn draw_textured_quad(&mut self, params: &[u32; 9], transparent: bool) {
+        // The colour is only used if textures are disabled.
+        let rgb = params[0];
+        let vertex_1 = params[1];
+        let texcoord_1 = params[2];
+        let vertex_2 = params[3];
+        let texcoord_2 = params[4];
+        let vertex_3 = params[5];
+        let texcoord_3 = params[6];
+        let vertex_4 = params[7];
+        let texcoord_4 = params[8];
         let vertices = [
-            Vertex::from_xy(vertex_1).set_tex(texcoord_1),
-            Vertex::from_xy(vertex_2).set_tex(texcoord_2),
-            Vertex::from_xy(vertex_3).set_tex(texcoord_3),
-            Vertex::from_xy(vertex_4).set_tex(texcoord_4),
+            Vertex::from_xy(vertex_1).set_col(rgb).set_tex(texcoord_1),
+            Vertex::from_xy(vertex_2).set_col(rgb).set_tex(texcoord_2),
+            Vertex::from_xy(vertex_3).set_col(rgb).set_tex(texcoord_3),
+            Vertex::from_xy(vertex_4).set_col(rgb).set_tex(texcoord_4),
         ];
         let tex_info = TexInfo::from_data(texcoord_1, texcoord_2);
         self.renderer.draw_triangle_tex(&vertices[0..3], &tex_info, transparent);
@@ -476,11 +504,11 @@ impl Renderer {
         self.renderer.draw_rectangle(color, top_left, size, transparent);
     }
 
-    fn draw_tex_rectangle(&mut self, params: &[u32; 3], transparent: bool) {
-        let color = Color::default();
-        let top_left = Coord::from_xy(params[0]);
-        let tex_data = params[1];
-        let size = Size::from_xy(params[2]);
+    fn draw_tex_rectangle(&mut self, params: &[u32; 4], transparent: bool) {
+        let color = Color::from_rgb24(params[0]);
+        let top_left = Coord::from_xy(params[1]);
+        let tex_data = params[2];
+        let size = Size::from_xy(params[3]);
         let palette = PaletteCoord::from_data(tex_data);
         let tex_info = TexInfo::from_draw_mode(self.tex_mode, palette);
         let tex_coord = TexCoord::from_16(tex_data as u16);
@@ -495,13 +523,13 @@ impl Renderer {
         let palette = PaletteCoord::from_data(tex_data);
         let tex_info = TexInfo::from_draw_mode(self.tex_mode, palette);
         let tex_coord = TexCoord::from_16(tex_data as u16);
-        self.renderer.draw_rectangle_tex(color, tex_coord, &tex_info, top_left, size, transparent);
+        self.renderer.draw_rectangle_tex_blended(color, tex_coord, &tex_info, top_left, size, transparent);
     }
 
-    fn draw_tex_fixed_rectangle(&mut self, params: &[u32; 2], transparent: bool, size: u16) {
-        let color = Color::default();
-        let top_left = Coord::from_xy(params[0]);
-        let tex_data = params[1];
+    fn draw_tex_fixed_rectangle(&mut self, params: &[u32; 3], transparent: bool, size: u16) {
+        let color = Color::from_rgb24(params[0]);
+        let top_left = Coord::from_xy(params[1]);
+        let tex_data = params[2];
         let size = Size { width: size, height: size };
         let palette = PaletteCoord::from_data(tex_data);
         let tex_info = TexInfo::from_draw_mode(self.tex_mode, palette);
@@ -517,7 +545,7 @@ impl Renderer {
         let palette = PaletteCoord::from_data(tex_data);
         let tex_info = TexInfo::from_draw_mode(self.tex_mode, palette);
         let tex_coord = TexCoord::from_16(tex_data as u16);
-        self.renderer.draw_rectangle_tex(color, tex_coord, &tex_info, top_left, size, transparent);
+        self.renderer.draw_rectangle_tex_blended(color, tex_coord, &tex_info, top_left, size, transparent);
     }
 
     // Data copy
@@ -561,7 +589,7 @@ impl Renderer {
 
     // Settings
 
-    fn set_draw_mode(&mut self, status: GPUStatus) {
+    fn set_draw_mode(&mut self, status: GPUStatus, x_flip: bool, y_flip: bool) {
         self.tex_mode = status.intersection(GPUStatus::DrawModeFlags).bits() as u16;
         let trans_mode = match (status.intersection(GPUStatus::SemiTrans)).bits() >> 5 {
             0b00 => TransparencyMode::Average,
@@ -572,6 +600,8 @@ impl Renderer {
         };
         let dither = status.contains(GPUStatus::Dither);
         self.renderer.set_draw_mode(trans_mode, dither);
+        self.renderer.set_tex_disable(status.contains(GPUStatus::TexDisable));
+        self.renderer.set_rect_flip(x_flip, y_flip);
     }
 
     fn texture_window_setting(&mut self, param: u32) {
@@ -606,11 +636,12 @@ impl Renderer {
 /// The code responsible for doing actual drawing
 /// should implement this trait.
 trait RendererImpl {
-    /// The frame provided should be of the correct resolution.
+    /// The frame is resized to fit the display area.
     /// It is of format BGRA U8.
     /// 
     /// "Debug" setting will draw the entire VRAM on-screen.
     fn get_frame(&mut self, frame: &mut Frame, interlace: InterlaceState, debug: bool);
+    fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode);
 
     fn write_vram_block(&mut self, data_in: &[u16], to: Coord, size: Size);
     fn read_vram_block(&mut self, data_out: &mut [u16], from: Coord, size: Size);
@@ -620,7 +651,9 @@ trait RendererImpl {
     fn set_display_offset(&mut self, offset: Coord);
     fn set_display_range_x(&mut self, begin: u32, end: u32);
     fn set_display_range_y(&mut self, begin: u32, end: u32);
-    fn set_display_resolution(&mut self, res: Size, interlace: bool);
+    /// Set the display mode. PAL shows more lines than NTSC.
+    fn set_display_resolution(&mut self, res: Size, interlace: bool, pal: bool);
+    fn set_overscan_mode(&mut self, mode: OverscanMode);
     fn set_color_depth(&mut self, rgb24: bool);
 
     fn set_draw_mode(&mut self, trans_mode: TransparencyMode, dither: bool);
@@ -629,6 +662,18 @@ trait RendererImpl {
     fn set_draw_area_bottom_right(&mut self, right: i16, bottom: i16);
     fn set_draw_area_offset(&mut self, x: i16, y: i16);
     fn set_mask_settings(&mut self, set_mask_bit: bool, check_mask_bit: bool);
+    fn set_texture_cache(&mut self, enable: bool);
+    /// Draw textured primitives with only their colour.
+    fn set_tex_disable(&mut self, disable: bool);
+    /// Mirror the texture of textured rectangles.
+    fn set_rect_flip(&mut self, x_flip: bool, y_flip: bool);
+    fn clear_texture_cache(&mut self);
+    /// Draw polygons at a multiple of the native resolution.
+    fn set_resolution_scale(&mut self, scale: usize);
+    /// Split drawing between threads.
+    fn set_render_threads(&mut self, threads: usize);
+    /// Shade and blend in 24-bit colour, without dithering.
+    fn set_true_color(&mut self, enable: bool);
 
     fn fill_rectangle(&mut self, color: Color, top_left: Coord, size: Size);
     fn draw_triangle_flat(&mut self, vertices: &[Vertex], color: Color, transparent: bool);
@@ -636,7 +681,9 @@ trait RendererImpl {
     fn draw_triangle_tex(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool);
     fn draw_triangle_tex_blended(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool);
     fn draw_rectangle(&mut self, color: Color, top_left: Coord, size: Size, transparent: bool);
+    /// The colour is only used if textures are disabled.
     fn draw_rectangle_tex(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool);
+    fn draw_rectangle_tex_blended(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool);
     fn draw_line(&mut self, vertex_a: &Vertex, vertex_b: &Vertex, transparent: bool);
 }
 
@@ -684,6 +731,10 @@ pub struct Size {
 }
 
 impl Size {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self { width, height }
+    }
+
     #[inline(always)]
     pub fn from_xy(xy: u32) -> Self {
         Self {
@@ -826,6 +877,7 @@ impl Vertex {
     }
 }
 
+#[derive(Clone)]
 struct PaletteCoord {
     x: usize,
     y: usize,
@@ -841,6 +893,7 @@ impl PaletteCoord {
     }
 }
 
+#[derive(Clone)]
 struct TexInfo {
     u_base: usize,
     v_base: usize,
@@ -879,6 +932,7 @@ impl TexInfo {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum TexMode {
     /// 4 bpp with palette
     Palette4,
@@ -0,0 +1,223 @@
+// Fixed-point maths used by the GPU to walk polygon edges
+// and interpolate colours and texture coordinates.
+
+use super::{Color, TexCoord, Vertex};
+
+/// Fractional bits of interpolated attributes.
+const ATTRIB_FRAC_BITS: u32 = 12;
+
+/// Number of attributes: R, G, B, U, V.
+const ATTRIBS: usize = 5;
+
+/// Largest primitive the GPU will draw. Anything bigger is dropped.
+pub const MAX_WIDTH: i16 = 1023;
+pub const MAX_HEIGHT: i16 = 511;
+
+/// Colour and texture coordinates at a pixel.
+#[derive(Clone, Copy)]
+pub struct Attribs([i64; ATTRIBS]);
+
+impl Attribs {
+    fn from_vertex(vertex: &Vertex) -> Self {
+        Self([
+            vertex.col.r as i64,
+            vertex.col.g as i64,
+            vertex.col.b as i64,
+            vertex.tex.u as i64,
+            vertex.tex.v as i64,
+        ])
+    }
+
+    /// Attributes at the start of a line k pixels long, and their change per pixel.
+    pub fn line(start: &Vertex, end: &Vertex, k: i16) -> (Self, Self) {
+        let (a, b) = (Self::from_vertex(start), Self::from_vertex(end));
+        let mut value = Self([0; ATTRIBS]);
+        let mut step = Self([0; ATTRIBS]);
+        for n in 0..ATTRIBS {
+            value.0[n] = (a.0[n] << ATTRIB_FRAC_BITS) + (1 << (ATTRIB_FRAC_BITS - 1));
+            if k > 0 {
+                step.0[n] = ((b.0[n] - a.0[n]) << ATTRIB_FRAC_BITS) / k as i64;
+            }
+        }
+        (value, step)
+    }
+
+    pub fn color(&self) -> Color {
+        let channel = |n: usize| (self.0[n] >> ATTRIB_FRAC_BITS).clamp(0, 0xFF) as u8;
+        Color {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
+            mask: 0,
+        }
+    }
+
+    /// Texture coordinates wrap around, as on hardware.
+    pub fn tex_coords(&self) -> TexCoord {
+        TexCoord {
+            u: (self.0[3] >> ATTRIB_FRAC_BITS) as u8,
+            v: (self.0[4] >> ATTRIB_FRAC_BITS) as u8,
+        }
+    }
+
+    /// Step n pixels along a gradient.
+    #[inline(always)]
+    pub fn step(&mut self, gradient: &Attribs, n: i64) {
+        for (value, delta) in self.0.iter_mut().zip(&gradient.0) {
+            *value += delta * n;
+        }
+    }
+}
+
+/// Change of each attribute across a triangle.
+pub struct Gradients {
+    /// Values at screen position (0, 0).
+    origin: Attribs,
+    pub dx: Attribs,
+    pub dy: Attribs,
+}
+
+impl Gradients {
+    /// Solve the plane of each attribute. Vertices should already be offset.
+    /// Returns None for triangles with no area.
+    pub fn new(vertices: &[Vertex]) -> Option<Self> {
+        let [a, b, c] = [&vertices[0], &vertices[1], &vertices[2]];
+        let (ax, ay) = (a.coord.x as i64, a.coord.y as i64);
+        let (bx, by) = (b.coord.x as i64, b.coord.y as i64);
+        let (cx, cy) = (c.coord.x as i64, c.coord.y as i64);
+        let denom = (bx - ax) * (cy - by) - (cx - bx) * (by - ay);
+        if denom == 0 {
+            return None;
+        }
+        let (a_attr, b_attr, c_attr) = (Attribs::from_vertex(a), Attribs::from_vertex(b), Attribs::from_vertex(c));
+        let mut origin = Attribs([0; ATTRIBS]);
+        let mut dx = Attribs([0; ATTRIBS]);
+        let mut dy = Attribs([0; ATTRIBS]);
+        for n in 0..ATTRIBS {
+            let (a_n, b_n, c_n) = (a_attr.0[n], b_attr.0[n], c_attr.0[n]);
+            // Division truncates, like the hardware.
+            dx.0[n] = (((b_n - a_n) * (cy - by) - (c_n - b_n) * (by - ay)) << ATTRIB_FRAC_BITS) / denom;
+            dy.0[n] = (((bx - ax) * (c_n - b_n) - (cx - bx) * (b_n - a_n)) << ATTRIB_FRAC_BITS) / denom;
+            // Start half way into the first value, so it rounds to nearest.
+            origin.0[n] = (a_n << ATTRIB_FRAC_BITS) + (1 << (ATTRIB_FRAC_BITS - 1)) - ax * dx.0[n] - ay * dy.0[n];
+        }
+        Some(Self { origin, dx, dy })
+    }
+
+    /// Attributes at a screen position.
+    pub fn at(&self, x: i16, y: i16) -> Attribs {
+        let mut attribs = self.origin;
+        attribs.step(&self.dx, x as i64);
+        attribs.step(&self.dy, y as i64);
+        attribs
+    }
+}
+
+/// X coordinate of a polygon edge, as 32.32 fixed-point.
+/// Starts just under the next whole pixel, so that the integer part
+/// is the first pixel whose centre is on or right of the edge.
+pub fn edge_x(x: i16) -> i64 {
+    ((x as i64) << 32) + (1 << 32) - (1 << 11)
+}
+
+/// Change in X per line of a polygon edge.
+/// Rounded away from zero.
+pub fn edge_step(dx: i16, dy: i16) -> i64 {
+    let dy = dy as i64;
+    let mut dx = (dx as i64) << 32;
+    if dx < 0 {
+        dx -= dy - 1;
+    } else if dx > 0 {
+        dx += dy - 1;
+    }
+    dx / dy
+}
+
+/// Integer pixel of an edge position.
+pub fn edge_pixel(x: i64) -> i16 {
+    (x >> 32) as i16
+}
+
+/// Start of a line, as 32.32 fixed-point, in the middle of the pixel.
+/// Nudged down slightly if requested, so that steps landing exactly
+/// between two pixels pick the lower one. The GPU always does this
+/// for X, but for Y only when the line moves up.
+pub fn line_start(x: i16, nudge: bool) -> i64 {
+    let start = ((x as i64) << 32) + (1 << 31);
+    if nudge {start - 1024} else {start}
+}
+
+/// Change of a line per pixel along its major axis, which is k pixels long.
+pub fn line_step(delta: i16, k: i16) -> i64 {
+    if k == 0 {
+        return 0;
+    }
+    edge_step(delta, k)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Coord;
+
+    fn vertex(x: i16, y: i16, r: u8, g: u8) -> Vertex {
+        Vertex { coord: Coord { x, y }, col: Color { r, g, b: 0, mask: 0 }, tex: TexCoord { u: x as u8, v: y as u8 } }
+    }
+
+    #[test]
+    fn edge_starts_on_pixel() {
+        assert_eq!(edge_pixel(edge_x(5)), 5);
+        assert_eq!(edge_pixel(edge_x(-5)), -5);
+        // Any step to the right reaches the next pixel.
+        assert_eq!(edge_pixel(edge_x(5) + (1 << 11)), 6);
+    }
+
+    #[test]
+    fn edge_step_rounds_away_from_zero() {
+        assert_eq!(edge_step(4, 2), 2 << 32);
+        assert_eq!(edge_step(0, 3), 0);
+        assert!(edge_step(1, 3) * 3 > 1 << 32);
+        assert!(edge_step(-1, 3) * 3 < -(1 << 32));
+        assert_eq!(edge_step(-1, 3), -edge_step(1, 3));
+    }
+
+    #[test]
+    fn nudged_line_picks_lower_pixel() {
+        // Half way between pixels 9 and 10.
+        let step = line_step(-1, 2);
+        assert_eq!(edge_pixel(line_start(10, false) + step), 10);
+        assert_eq!(edge_pixel(line_start(10, true) + step), 9);
+        let step = line_step(1, 2);
+        assert_eq!(edge_pixel(line_start(10, false) + step), 11);
+        assert_eq!(edge_pixel(line_start(10, true) + step), 10);
+        // End points are reached either way.
+        assert_eq!(edge_pixel(line_start(10, true) + step * 2), 11);
+        assert_eq!(line_step(5, 0), 0);
+    }
+
+    #[test]
+    fn gradients() {
+        // Red increases by 4 per pixel right, green by 8 per pixel down.
+        let vertices = [vertex(0, 0, 0, 0), vertex(16, 0, 64, 0), vertex(0, 16, 0, 128)];
+        let gradients = Gradients::new(&vertices).unwrap();
+        for v in &vertices {
+            let color = gradients.at(v.coord.x, v.coord.y).color();
+            assert_eq!((color.r, color.g), (v.col.r, v.col.g));
+        }
+        let attribs = gradients.at(5, 3);
+        let color = attribs.color();
+        assert_eq!((color.r, color.g), (20, 24));
+        let tex = attribs.tex_coords();
+        assert_eq!((tex.u, tex.v), (5, 3));
+        // Stepping matches solving at the new position.
+        let mut stepped = gradients.at(0, 3);
+        stepped.step(&gradients.dx, 5);
+        assert_eq!(stepped.color().r, color.r);
+    }
+
+    #[test]
+    fn flat_triangle_has_no_gradients() {
+        let vertices = [vertex(0, 0, 0, 0), vertex(8, 8, 0, 0), vertex(16, 16, 0, 0)];
+        assert!(Gradients::new(&vertices).is_none());
+    }
+}
@@ -0,0 +1,77 @@
+// Upscaled copy of VRAM, for drawing at a higher internal resolution.
+
+use super::{Coord, Size, vram::Vram, truecolor::TrueColorBuffer};
+use crate::utils::bits::*;
+
+/// VRAM at a multiple of the native resolution. Clones share the same memory.
+///
+/// Polygons are drawn into both this and native VRAM. Native VRAM is still
+/// used for textures and reads by the CPU. Transfers into VRAM are scaled up
+/// into this copy, and frames are output from it.
+#[derive(Clone)]
+pub struct ShadowVram {
+    pub scale: usize,
+    pub data: Vram,
+}
+
+impl ShadowVram {
+    /// Make a shadow copy of native VRAM.
+    pub fn new(scale: usize, vram: &Vram) -> Self {
+        let mut shadow = Self {
+            scale,
+            data: Vram::new(super::VRAM_SIZE / 2 * scale * scale),
+        };
+        shadow.upscale_block(vram, Coord { x: 0, y: 0 }, Size { width: 1024, height: 512 }, None);
+        shadow
+    }
+
+    /// Halfwords per line.
+    #[inline(always)]
+    pub fn stride(&self) -> usize {
+        1024 * self.scale
+    }
+
+    /// Address of a pixel. Coordinates wrap around VRAM.
+    #[inline(always)]
+    pub fn addr(&self, x: usize, y: usize) -> usize {
+        (y & (512 * self.scale - 1)) * self.stride() + (x & (self.stride() - 1))
+    }
+
+    /// Copy an area of native VRAM, with each pixel becoming a block.
+    /// Anything drawn at the higher resolution in the area is lost,
+    /// including true colour.
+    pub fn upscale_block(&mut self, vram: &Vram, to: Coord, size: Size, true_color: Option<&TrueColorBuffer>) {
+        let scale = self.scale;
+        let (to_x, to_y) = (to.x as usize, to.y as usize);
+        for y in 0..(size.height as usize * scale) {
+            let native_line = ((to_y + y / scale) & 0x1FF) * 1024;
+            for x in 0..(size.width as usize * scale) {
+                let addr = self.addr(to_x * scale + x, to_y * scale + y);
+                self.data.set(addr, vram.get(native_line + ((to_x + x / scale) & 0x3FF)));
+                if let Some(true_color) = true_color {
+                    true_color.invalidate(addr);
+                }
+            }
+        }
+    }
+
+    /// Copy an area, keeping anything drawn at the higher resolution,
+    /// and its true colour. Masking works the same as for native VRAM.
+    pub fn copy_block(&mut self, from: Coord, to: Coord, size: Size, set_mask_bit: bool, check_mask_bit: bool, true_color: Option<&TrueColorBuffer>) {
+        let scale = self.scale;
+        let mask = if set_mask_bit {0x8000} else {0};
+        for y in 0..(size.height as usize * scale) {
+            for x in 0..(size.width as usize * scale) {
+                let read_addr = self.addr(from.x as usize * scale + x, from.y as usize * scale + y);
+                let write_addr = self.addr(to.x as usize * scale + x, to.y as usize * scale + y);
+                let data = self.data.get(read_addr);
+                if !check_mask_bit || !test_bit!(data, 15) {
+                    self.data.set(write_addr, data | mask);
+                    if let Some(true_color) = true_color {
+                        true_color.copy(read_addr, write_addr);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use super::{
-    RendererImpl, Coord, Size, Color, Vertex, TexInfo, TexMode, TexCoord, TransparencyMode
+    RendererImpl, Coord, Size, Color, Vertex, TexInfo, TexMode, TexCoord, TransparencyMode,
+    display::DisplayArea, texcache::TextureCache, shadow::ShadowVram, vram::Vram, truecolor::TrueColorBuffer,
+    raster::{self, Attribs, Gradients}
 };
 
 use crate::{
-    Frame, gpu::InterlaceState, utils::bits::*
+    Frame, gpu::{InterlaceState, DeinterlaceMode, OverscanMode}, utils::bits::*
 };
 
+/// Summed RGB difference above which a pixel is treated as moving.
+const MOTION_THRESHOLD: u32 = 48;
+
+/// Lines of VRAM in each band, when drawing is split between threads.
+const BAND_LINES: i16 = 8;
+
+#[derive(Clone)]
 struct DrawingArea {
     top: i16,
     bottom: i16,
@@ -13,6 +22,7 @@ struct DrawingArea {
     right: i16,
 }
 
+#[derive(Clone)]
 struct TextureWindow {
     mask_u: u8,
     mask_v: u8,
@@ -22,104 +32,167 @@ struct TextureWindow {
 
 /// Software implementation of rendering functions
 /// for the PlayStation GPU.
+#[derive(Clone)]
 pub struct SoftwareRenderer {
-    vram: Vec<u16>,
+    vram: Vram,
 
     // Settings
     enable_display: bool,
     resolution: Size,
+    pal: bool,
     frame_pos: Coord,
     drawing_area: DrawingArea,
     draw_offset: Coord,
     tex_window: TextureWindow,
-    interlace: bool,
-    display_height: u16,
+    display_range_x: (u32, u32),
+    display_range_y: (u32, u32),
+    overscan_mode: OverscanMode,
+    display_area: DisplayArea,
+    deinterlace_mode: DeinterlaceMode,
+    /// Pixels of the last field that changed since the field before.
+    motion: Vec<bool>,
+    /// Last lines output for each field, before any interpolation.
+    field_lines: Vec<u8>,
 
     trans_mode: TransparencyMode,
     dither: bool,
     set_mask_bit: bool,
     check_mask_bit: bool,
     rgb24: bool,
+    /// Only used in accurate texture cache mode.
+    tex_cache: Option<Box<TextureCache>>,
+    tex_disable: bool,
+    rect_x_flip: bool,
+    rect_y_flip: bool,
+
+    /// Only used when drawing above native resolution.
+    shadow: Option<ShadowVram>,
+    /// Scale of the VRAM being drawn to: 1 for native VRAM.
+    target_scale: usize,
+    /// Only used in true colour mode. Matches the VRAM frames are output from.
+    true_color: Option<TrueColorBuffer>,
+    /// Which band of lines is drawn, out of how many.
+    /// Only set for renderers on worker threads.
+    band: Option<(i16, i16)>,
 }
 
 impl SoftwareRenderer {
     pub fn new() -> Self {
         Self {
-            vram: vec![0; super::VRAM_SIZE / 2],
+            vram: Vram::new(super::VRAM_SIZE / 2),
 
             enable_display: false,
             resolution: Size { width: 320, height: 240 },
+            pal: false,
             frame_pos: Coord { x: 0, y: 0 },
             drawing_area: DrawingArea { top: 0, bottom: 0, left: 0, right: 0 },
             draw_offset: Coord { x: 0, y: 0 },
             tex_window: TextureWindow { mask_u: 0, mask_v: 0, offset_u: 0, offset_v: 0 },
-            interlace: false,
-            display_height: 240,
+            display_range_x: (0x260, 0xC60),
+            display_range_y: (0x10, 0x100),
+            overscan_mode: OverscanMode::default(),
+            display_area: DisplayArea::new(OverscanMode::default(), Size { width: 320, height: 240 }, false, (0x260, 0xC60), (0x10, 0x100)),
+            deinterlace_mode: DeinterlaceMode::default(),
+            motion: Vec::new(),
+            field_lines: Vec::new(),
 
             trans_mode: TransparencyMode::Average,
             dither: false,
             set_mask_bit: false,
             check_mask_bit: false,
             rgb24: false,
+            tex_cache: None,
+            tex_disable: false,
+            rect_x_flip: false,
+            rect_y_flip: false,
+
+            shadow: None,
+            target_scale: 1,
+            true_color: None,
+            band: None,
+        }
+    }
+
+    /// Make a renderer that shares this one's VRAM,
+    /// and only draws to every nth band of lines.
+    pub fn for_band(&self, band: usize, bands: usize) -> Self {
+        Self {
+            band: Some((band as i16, bands as i16)),
+            motion: Vec::new(),
+            field_lines: Vec::new(),
+            ..self.clone()
         }
     }
+
+    /// Area that drawing is clipped to: left, top, right and bottom.
+    pub fn drawing_bounds(&self) -> (i16, i16, i16, i16) {
+        (self.drawing_area.left, self.drawing_area.top, self.drawing_area.right, self.drawing_area.bottom)
+    }
 }
 
 impl RendererImpl for SoftwareRenderer {
-    fn get_frame(&mut self, frame: &mut Frame, _interlace_state: InterlaceState, debug: bool) {
-        let width = if debug {1024} else {self.resolution.width as usize};
-        let height = if debug {512} else {
-            let display_height = if self.interlace {self.display_height * 2} else {self.display_height};
-            self.resolution.height.min(display_height) as usize
+    fn get_frame(&mut self, frame: &mut Frame, interlace_state: InterlaceState, debug: bool) {
+        if debug {
+            frame.resize((1024, 512));
+            frame.pixel_aspect_ratio = 1.0;
+            for (pixel, out) in self.vram.iter().zip(frame.frame_buffer.chunks_exact_mut(4)) {
+                let col = Color::from_rgb15(pixel);
+                out[0] = col.r;
+                out[1] = col.g;
+                out[2] = col.b;
+            }
+            return;
+        }
+        self.display_area = DisplayArea::new(self.overscan_mode, self.resolution, self.pal, self.display_range_x, self.display_range_y);
+        let scale = self.output_scale();
+        frame.resize((self.display_area.frame_size.width as usize * scale, self.display_area.frame_size.height as usize * scale));
+        frame.pixel_aspect_ratio = self.display_area.pixel_aspect_ratio;
+        if !self.enable_display {
+            frame.frame_buffer.fill(0);
+            return;
+        }
+        let width = frame.size.0;
+        let height = frame.size.1;
+        let field = match interlace_state {
+            InterlaceState::Even => 0,
+            InterlaceState::Odd => 1,
+            InterlaceState::Off => {
+                for y in 0..height {
+                    self.output_row(frame, y);
+                }
+                return;
+            },
         };
-        if self.enable_display {
-            let line_size = width * 4;
+        if self.resolution.height <= 240 {
+            // Both fields show the same lines.
             for y in 0..height {
-                // TODO: interlaced rendering makes a cool effect, but overflows memory sometimes
-                // for as of yet unknown reasons.
-                /*let y = match interlace_state {
-                    InterlaceState::Off => y,
-                    InterlaceState::Even => y * 2,
-                    InterlaceState::Odd => y * 2 + 1,
-                };*/
-                let mut frame_idx = y * line_size;
-                let mut vram_addr = Coord {x: self.frame_pos.x, y: self.frame_pos.y + (y as i16)}.get_vram_idx();
-                if self.rgb24 {
-                    if debug {
-                        vram_addr = y * (width / 2);
-                    }
-                    for _ in 0..(width / 2) {
-                        let h0 = self.vram[vram_addr + 0];
-                        let h1 = self.vram[vram_addr + 1];
-                        let h2 = self.vram[vram_addr + 2];
-                        frame.frame_buffer[frame_idx + 0] = h0 as u8;
-                        frame.frame_buffer[frame_idx + 1] = (h0 >> 8) as u8;
-                        frame.frame_buffer[frame_idx + 2] = h1 as u8;
-                        frame.frame_buffer[frame_idx + 4] = (h1 >> 8) as u8;
-                        frame.frame_buffer[frame_idx + 5] = h2 as u8;
-                        frame.frame_buffer[frame_idx + 6] = (h2 >> 8) as u8;
-                        vram_addr += 3;
-                        frame_idx += 8;
-                    }
-                } else {
-                    if debug {
-                        vram_addr = y * width;
-                    }
-                    for _ in 0..width {
-                        let pixel = self.vram[vram_addr];
-                        let col = Color::from_rgb15(pixel);
-                        frame.frame_buffer[frame_idx + 0] = col.r;
-                        frame.frame_buffer[frame_idx + 1] = col.g;
-                        frame.frame_buffer[frame_idx + 2] = col.b;
-                        vram_addr += 1;
-                        frame_idx += 4;
-                    }
-                }
+                self.output_row(frame, y);
             }
-        } else {
-            frame.frame_buffer.fill(0);
+            return;
         }
+        match self.deinterlace_mode {
+            DeinterlaceMode::Weave => for y in field_rows(field, height, scale) {
+                self.output_row(frame, y);
+            },
+            DeinterlaceMode::Bob => {
+                for y in field_rows(field, height, scale) {
+                    self.output_row(frame, y);
+                }
+                // Each line of the field covers the line below it too.
+                let line_size = width * 4;
+                for y in field_rows(1 - field, height, scale) {
+                    let src = if y < scale {y + scale} else {y - scale};
+                    frame.frame_buffer.copy_within((src * line_size)..((src + 1) * line_size), y * line_size);
+                }
+            },
+            DeinterlaceMode::Adaptive => self.deinterlace_adaptive(frame, field, width, height, scale),
+        }
+    }
+
+    fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode) {
+        self.deinterlace_mode = mode;
     }
+
     fn write_vram_block(&mut self, data_in: &[u16], to: Coord, size: Size) {
         let mask = if self.set_mask_bit {0x8000} else {0};
         for y in 0..size.height {
@@ -130,11 +203,17 @@ impl RendererImpl for SoftwareRenderer {
             for (x, pixel) in data_in[src_begin..src_end].iter().cloned().enumerate() {
                 let x_addr = ((to.x as usize) + x) % 1024;
                 let addr = addr_base + x_addr;
-                if !self.check_mask_bit || !test_bit!(self.vram[addr], 15) {
-                    self.vram[addr] = pixel | mask;
+                if !self.check_mask_bit || !test_bit!(self.vram.get(addr), 15) {
+                    self.vram.set(addr, pixel | mask);
+                    if let Some(true_color) = self.native_true_color() {
+                        true_color.invalidate(addr);
+                    }
                 }
             }
         }
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.upscale_block(&self.vram, to, size, self.true_color.as_ref());
+        }
     }
     fn read_vram_block(&mut self, data_out: &mut [u16], from: Coord, size: Size) {
         if from.x + size.width as i16 > 1024 {
@@ -144,9 +223,10 @@ impl RendererImpl for SoftwareRenderer {
             let dst_begin = y as usize * size.width as usize;
             let dst_end = dst_begin + (size.width as usize);
             let src_begin = Coord {x: from.x, y: from.y + y as i16}.get_vram_idx();
-            let src_end = src_begin + (size.width as usize);
             let dest = &mut data_out[dst_begin..dst_end];
-            dest.copy_from_slice(&self.vram[src_begin..src_end]);
+            for (n, out) in dest.iter_mut().enumerate() {
+                *out = self.vram.get(src_begin + n);
+            }
         }
     }
     fn copy_vram_block(&mut self, from: Coord, to: Coord, size: Size) {
@@ -159,12 +239,18 @@ impl RendererImpl for SoftwareRenderer {
                 let write_x_addr = ((to.x + x as i16) as usize) % 1024;
                 let read_addr = read_addr_base + read_x_addr;
                 let write_addr = write_addr_base + write_x_addr;
-                let data = self.vram[read_addr];
+                let data = self.vram.get(read_addr);
                 if !self.check_mask_bit || !test_bit!(data, 15) {
-                    self.vram[write_addr] = data | mask;
+                    self.vram.set(write_addr, data | mask);
+                    if let Some(true_color) = self.native_true_color() {
+                        true_color.copy(read_addr, write_addr);
+                    }
                 }
             }
         }
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.copy_block(from, to, size, self.set_mask_bit, self.check_mask_bit, self.true_color.as_ref());
+        }
     }
 
     fn enable_display(&mut self, enable: bool) {
@@ -173,16 +259,18 @@ impl RendererImpl for SoftwareRenderer {
     fn set_display_offset(&mut self, offset: Coord) {
         self.frame_pos = offset;
     }
-    fn set_display_range_x(&mut self, _begin: u32, _end: u32) {
-        //println!("Display X: {} => {}", begin, end);
+    fn set_display_range_x(&mut self, begin: u32, end: u32) {
+        self.display_range_x = (begin, end);
     }
     fn set_display_range_y(&mut self, begin: u32, end: u32) {
-        //println!("Display Y: {} => {}", begin, end);
-        self.display_height = (end - begin) as u16;
+        self.display_range_y = (begin, end);
     }
-    fn set_display_resolution(&mut self, res: Size, interlace: bool) {
+    fn set_display_resolution(&mut self, res: Size, _interlace: bool, pal: bool) {
         self.resolution = res;
-        self.interlace = interlace;
+        self.pal = pal;
+    }
+    fn set_overscan_mode(&mut self, mode: OverscanMode) {
+        self.overscan_mode = mode;
     }
     fn set_color_depth(&mut self, rgb24: bool) {
         self.rgb24 = rgb24;
@@ -219,21 +307,56 @@ impl RendererImpl for SoftwareRenderer {
         self.set_mask_bit = set_mask_bit;
         self.check_mask_bit = check_mask_bit;
     }
+    fn set_texture_cache(&mut self, enable: bool) {
+        self.tex_cache = enable.then(|| Box::new(TextureCache::new()));
+    }
+    fn clear_texture_cache(&mut self) {
+        if let Some(cache) = self.tex_cache.as_mut() {
+            cache.clear();
+        }
+    }
+    fn set_tex_disable(&mut self, disable: bool) {
+        self.tex_disable = disable;
+    }
+    fn set_rect_flip(&mut self, x_flip: bool, y_flip: bool) {
+        self.rect_x_flip = x_flip;
+        self.rect_y_flip = y_flip;
+    }
+    fn set_resolution_scale(&mut self, scale: usize) {
+        let scale = scale.clamp(1, 8).next_power_of_two();
+        self.shadow = (scale > 1).then(|| ShadowVram::new(scale, &self.vram));
+        if self.true_color.is_some() {
+            self.set_true_color(true);
+        }
+    }
+    fn set_render_threads(&mut self, _threads: usize) {
+        // Always draws on this thread. See ThreadedRenderer.
+    }
+    fn set_true_color(&mut self, enable: bool) {
+        let scale = self.output_scale();
+        self.true_color = enable.then(|| TrueColorBuffer::new(super::VRAM_SIZE / 2 * scale * scale));
+    }
 
     fn fill_rectangle(&mut self, color: Color, top_left: Coord, size: Size) {
         let rgb15 = color.to_rgb15();
         for y in 0..size.height {
             let dst_begin = Coord {x: top_left.x, y: top_left.y + y as i16}.get_vram_idx();
-            let dst_end = dst_begin + (size.width as usize);
-            let dest = &mut self.vram[dst_begin..dst_end];
-            dest.fill(rgb15);
+            for addr in dst_begin..(dst_begin + size.width as usize) {
+                self.vram.set(addr, rgb15);
+                if let Some(true_color) = self.native_true_color() {
+                    true_color.invalidate(addr);
+                }
+            }
+        }
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.upscale_block(&self.vram, top_left, size, self.true_color.as_ref());
         }
     }
 
     fn draw_triangle_flat(&mut self, vertices: &[Vertex], color: Color, transparent: bool) {
         if !super::WIREFRAME_MODE {
             let trans_mode = if transparent {Some(self.trans_mode)} else {None};
-            self.rasterize_triangle(vertices, |renderer: &mut Self, _: &Line, addr: usize| {
+            self.rasterize_triangle(vertices, |renderer: &mut Self, _: &Attribs, addr: usize| {
                 renderer.write_pixel(addr, &color, trans_mode);
             });
         } else {
@@ -244,8 +367,8 @@ impl RendererImpl for SoftwareRenderer {
     fn draw_triangle_shaded(&mut self, vertices: &[Vertex], transparent: bool) {
         if !super::WIREFRAME_MODE {
             let trans_mode = if transparent {Some(self.trans_mode)} else {None};
-            self.rasterize_triangle(vertices, |renderer: &mut Self, line: &Line, addr: usize| {
-                let color = line.get_color();
+            self.rasterize_triangle(vertices, |renderer: &mut Self, attribs: &Attribs, addr: usize| {
+                let color = attribs.color();
                 renderer.write_dithered_pixel(addr, &color, trans_mode);
             });
         } else {
@@ -254,9 +377,13 @@ impl RendererImpl for SoftwareRenderer {
     }
 
     fn draw_triangle_tex(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool) {
+        if self.tex_disable {
+            return self.draw_triangle_flat(vertices, vertices[0].col, transparent);
+        }
+        self.set_tex_cache_page(tex_info);
         if !super::WIREFRAME_MODE {
-            self.rasterize_triangle(vertices, |renderer: &mut Self, line: &Line, addr: usize| {
-                let tex_color = renderer.tex_lookup(&line.get_tex_coords(), tex_info);
+            self.rasterize_triangle(vertices, |renderer: &mut Self, attribs: &Attribs, addr: usize| {
+                let tex_color = renderer.tex_lookup(&attribs.tex_coords(), tex_info);
                 if tex_color != 0 {
                     let transparent = transparent && test_bit!(tex_color, 15);
                     let trans_mode = if transparent {Some(tex_info.trans_mode)} else {None};
@@ -269,11 +396,15 @@ impl RendererImpl for SoftwareRenderer {
     }
 
     fn draw_triangle_tex_blended(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool) {
+        if self.tex_disable {
+            return self.draw_triangle_shaded(vertices, transparent);
+        }
+        self.set_tex_cache_page(tex_info);
         if !super::WIREFRAME_MODE {
-            self.rasterize_triangle(vertices, |renderer: &mut Self, line: &Line, addr: usize| {
-                let tex_color = renderer.tex_lookup(&line.get_tex_coords(), tex_info);
+            self.rasterize_triangle(vertices, |renderer: &mut Self, attribs: &Attribs, addr: usize| {
+                let tex_color = renderer.tex_lookup(&attribs.tex_coords(), tex_info);
                 if tex_color != 0 {
-                    let color = line.get_color();
+                    let color = attribs.color();
                     let frag_color = color.blend(&Color::from_rgb15(tex_color), !renderer.set_mask_bit);
                     let transparent = transparent && test_bit!(tex_color, 15);
                     let trans_mode = if transparent {Some(tex_info.trans_mode)} else {None};
@@ -295,16 +426,82 @@ impl RendererImpl for SoftwareRenderer {
         let y_max = bottom.min(self.drawing_area.bottom);
         let x_min = left.max(self.drawing_area.left);
         let x_max = right.min(self.drawing_area.right);
-        for y in y_min..y_max {
-            let line_addr = (y as usize) * 1024;
-            for x in x_min..x_max {
-                let addr = line_addr + (x as usize);
-                self.write_pixel(addr, &color, trans_mode);
+        self.draw_scaled(|renderer| {
+            let scale = renderer.target_scale as i16;
+            for y in (y_min * scale)..(y_max * scale) {
+                if !renderer.owns_line(y / scale) {
+                    continue;
+                }
+                let line_addr = (y as usize) * renderer.stride();
+                for x in (x_min * scale)..(x_max * scale) {
+                    let addr = line_addr + (x as usize);
+                    renderer.write_pixel(addr, &color, trans_mode);
+                }
             }
-        }
+        });
     }
 
     fn draw_rectangle_tex(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool) {
+        if self.tex_disable {
+            return self.draw_rectangle(color, top_left, size, transparent);
+        }
+        self.draw_textured_rectangle(Color::default(), tex_coord, tex_info, top_left, size, transparent);
+    }
+
+    fn draw_rectangle_tex_blended(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool) {
+        if self.tex_disable {
+            return self.draw_rectangle(color, top_left, size, transparent);
+        }
+        self.draw_textured_rectangle(color, tex_coord, tex_info, top_left, size, transparent);
+    }
+
+    fn draw_line(&mut self, vertex_a: &Vertex, vertex_b: &Vertex, transparent: bool) {
+        let trans_mode = if transparent {Some(self.trans_mode)} else {None};
+        let start = self.offset_vertex(vertex_a);
+        let end = self.offset_vertex(vertex_b);
+        let dx = end.coord.x - start.coord.x;
+        let dy = end.coord.y - start.coord.y;
+        if dx.abs() > raster::MAX_WIDTH || dy.abs() > raster::MAX_HEIGHT {
+            return;
+        }
+        // Both end points are drawn.
+        let k = dx.abs().max(dy.abs());
+        let step_x = raster::line_step(dx, k);
+        let step_y = raster::line_step(dy, k);
+        let (attrib_start, attrib_step) = Attribs::line(&start, &end, k);
+        self.draw_scaled(|renderer| {
+            // Lines keep their native thickness when upscaling.
+            let scale = renderer.target_scale;
+            // X is nudged whichever way the line goes.
+            let mut x = raster::line_start(start.coord.x, true);
+            let mut y = raster::line_start(start.coord.y, step_y < 0);
+            let mut attribs = attrib_start;
+            for _ in 0..=k {
+                let (px, py) = (raster::edge_pixel(x), raster::edge_pixel(y));
+                if px >= renderer.drawing_area.left && px < renderer.drawing_area.right &&
+                    py >= renderer.drawing_area.top && py < renderer.drawing_area.bottom &&
+                    renderer.owns_line(py) {
+                    let color = attribs.color();
+                    for sub_y in 0..scale {
+                        let line_addr = (py as usize * scale + sub_y) * renderer.stride();
+                        for sub_x in 0..scale {
+                            renderer.write_dithered_pixel(line_addr + px as usize * scale + sub_x, &color, trans_mode);
+                        }
+                    }
+                }
+                x += step_x;
+                y += step_y;
+                attribs.step(&attrib_step, 1);
+            }
+        });
+    }
+}
+
+// Internal
+impl SoftwareRenderer {
+    /// Draw a rectangle with the texture blended by a colour.
+    fn draw_textured_rectangle(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool) {
+        self.set_tex_cache_page(tex_info);
         let top = top_left.y + self.draw_offset.y;
         let bottom = top + size.height as i16;
         let left = top_left.x + self.draw_offset.x;
@@ -313,102 +510,136 @@ impl RendererImpl for SoftwareRenderer {
         let y_max = bottom.min(self.drawing_area.bottom);
         let x_min = left.max(self.drawing_area.left);
         let x_max = right.min(self.drawing_area.right);
+        // Flipped rectangles step backwards through the texture.
+        let step_u: u8 = if self.rect_x_flip {0xFF} else {1};
+        let step_v: u8 = if self.rect_y_flip {0xFF} else {1};
         let start_coord = TexCoord {
             u: if left < self.drawing_area.left {
-                let offset = self.drawing_area.left - left;
-                ((tex_coord.u as u16 as i16) + offset) as u8
+                let offset = (self.drawing_area.left - left) as u8;
+                tex_coord.u.wrapping_add(offset.wrapping_mul(step_u))
             } else {
                 tex_coord.u
             },
             v: if top < self.drawing_area.top {
-                let offset = self.drawing_area.top - top;
-                ((tex_coord.v as u16 as i16) + offset) as u8
+                let offset = (self.drawing_area.top - top) as u8;
+                tex_coord.v.wrapping_add(offset.wrapping_mul(step_v))
             } else {
                 tex_coord.v
             },
         };
-        let mut current_tex_coord = start_coord;
-        for y in y_min..y_max {
-            let line_addr = (y as usize) * 1024;
-            for x in x_min..x_max {
-                let tex_color = self.tex_lookup(&current_tex_coord, tex_info);
-                if tex_color != 0 {
-                    let addr = line_addr + (x as usize);
-                    let frag_color = color.blend(&Color::from_rgb15(tex_color), !self.set_mask_bit);
-                    let transparent = transparent && test_bit!(tex_color, 15);
-                    let trans_mode = if transparent {Some(tex_info.trans_mode)} else {None};
-                    self.write_pixel(addr, &frag_color, trans_mode);
+        self.draw_scaled(|renderer| {
+            // Each texel covers a block of pixels when upscaling.
+            let scale = renderer.target_scale as i16;
+            for y in (y_min * scale)..(y_max * scale) {
+                if !renderer.owns_line(y / scale) {
+                    continue;
+                }
+                let line_addr = (y as usize) * renderer.stride();
+                let v = start_coord.v.wrapping_add(((y / scale - y_min) as u8).wrapping_mul(step_v));
+                for x in (x_min * scale)..(x_max * scale) {
+                    let u = start_coord.u.wrapping_add(((x / scale - x_min) as u8).wrapping_mul(step_u));
+                    let tex_color = renderer.tex_lookup(&TexCoord { u, v }, tex_info);
+                    if tex_color != 0 {
+                        let addr = line_addr + (x as usize);
+                        let frag_color = color.blend(&Color::from_rgb15(tex_color), !renderer.set_mask_bit);
+                        let transparent = transparent && test_bit!(tex_color, 15);
+                        let trans_mode = if transparent {Some(tex_info.trans_mode)} else {None};
+                        renderer.write_pixel(addr, &frag_color, trans_mode);
+                    }
                 }
-                current_tex_coord.u += 1;
             }
-            current_tex_coord.v += 1;
-            current_tex_coord.u = start_coord.u;
+        });
+    }
+
+    /// Fill a row of the frame from the displayed area of VRAM.
+    /// Rows are counted in frame pixels, which are scaled when upscaling.
+    fn output_row(&self, frame: &mut Frame, row: usize) {
+        let area = self.display_area;
+        let scale = self.output_scale();
+        let frame_width = frame.size.0;
+        let out = &mut frame.frame_buffer[(row * frame_width * 4)..((row + 1) * frame_width * 4)];
+        out.fill(0);
+        let (area_x, area_y) = (area.x * scale as i32, area.y * scale as i32);
+        let line = row as i32 - area_y;
+        if line < 0 || line >= (area.height as usize * scale) as i32 {
+            return;
+        }
+        let line = line as usize;
+        let line_addr = ((self.frame_pos.y as usize + line / scale) & 0x1FF) * 1024;
+        let start_x = self.frame_pos.x as usize;
+        let first = (-area_x).max(0) as usize;
+        let last = (area.width as usize * scale).min((frame_width as i32 - area_x).max(0) as usize);
+        for n in first..last {
+            let idx = (area_x + n as i32) as usize * 4;
+            if self.rgb24 {
+                // Pixels are 3 bytes, packed across halfwords.
+                // They are never drawn by the GPU, so only native VRAM is used.
+                let byte = |b: usize| {
+                    let halfword = self.vram.get(line_addr + ((start_x + b / 2) & 0x3FF));
+                    (halfword >> ((b & 1) * 8)) as u8
+                };
+                let b = (n / scale) * 3;
+                out[idx] = byte(b);
+                out[idx + 1] = byte(b + 1);
+                out[idx + 2] = byte(b + 2);
+            } else {
+                let (vram, addr) = match self.shadow.as_ref() {
+                    Some(shadow) => (&shadow.data, shadow.addr(start_x * scale + n, self.frame_pos.y as usize * scale + line)),
+                    None => (&self.vram, line_addr + ((start_x + n) & 0x3FF)),
+                };
+                let pixel = vram.get(addr);
+                let col = match self.true_color.as_ref() {
+                    Some(true_color) => true_color.get(addr, pixel),
+                    None => Color::from_rgb15(pixel),
+                };
+                out[idx] = col.r;
+                out[idx + 1] = col.g;
+                out[idx + 2] = col.b;
+            }
         }
     }
 
-    fn draw_line(&mut self, vertex_a: &Vertex, vertex_b: &Vertex, transparent: bool) {
-        let trans_mode = if transparent {Some(self.trans_mode)} else {None};
-        // X or Y major line:
-        let dx = (vertex_a.coord.x - vertex_b.coord.x).abs();
-        let dy = (vertex_a.coord.y - vertex_b.coord.y).abs();
-        if dx > dy {
-            let (left, right) = if vertex_a.coord.x < vertex_b.coord.x {
-                (vertex_a, vertex_b)
-            } else {
-                (vertex_b, vertex_a)
-            };
-            let mut color = InterpolatedColor::from_x_major(left, right);
-            let y_step = if left.coord.y < right.coord.y {1} else {-1};
-            let x_begin = left.coord.x + self.draw_offset.x;
-            let x_end = (right.coord.x + self.draw_offset.x).min(self.drawing_area.right - 1);
-            let mut y = left.coord.y + self.draw_offset.y;
-            let mut delta = 2 * dy - dx;
-            for x in x_begin..=x_end {
-                if x >= self.drawing_area.left &&
-                    y >= self.drawing_area.top && y < self.drawing_area.bottom {
-                    let addr = (y as usize) * 1024 + (x as usize);
-                    self.write_dithered_pixel(addr, &color.get(), trans_mode);
-                }
-                if delta > 0 {
-                    y += y_step;
-                    delta += 2 * (dy - dx);
-                } else {
-                    delta += 2 * dy;
-                }
-                color.inc();
+    /// Weave where the picture is still, and interpolate between
+    /// the lines of the new field where it has moved.
+    fn deinterlace_adaptive(&mut self, frame: &mut Frame, field: usize, width: usize, height: usize, scale: usize) {
+        let line_size = width * 4;
+        self.motion.resize(width * height, false);
+        self.field_lines.resize(line_size * height, 0);
+        for y in field_rows(field, height, scale) {
+            let row = (y * line_size)..((y + 1) * line_size);
+            self.output_row(frame, y);
+            // The frame row may have been interpolated since, so compare
+            // against the last field of the same parity as it was output.
+            let new_line = &frame.frame_buffer[row.clone()];
+            for (x, (old, new)) in self.field_lines[row.clone()].chunks_exact(4).zip(new_line.chunks_exact(4)).enumerate() {
+                let diff = (0..3).map(|i| old[i].abs_diff(new[i]) as u32).sum::<u32>();
+                self.motion[y * width + x] = diff > MOTION_THRESHOLD;
             }
-        } else {
-            let (top, bottom) = if vertex_a.coord.y < vertex_b.coord.y {
-                (vertex_a, vertex_b)
-            } else {
-                (vertex_b, vertex_a)
-            };
-            let mut color = InterpolatedColor::from_y_major(top, bottom);
-            let x_step = if top.coord.x < bottom.coord.x {1} else {-1};
-            let y_begin = top.coord.y + self.draw_offset.y;
-            let y_end = (bottom.coord.y + self.draw_offset.y).min(self.drawing_area.bottom - 1);
-            let mut x = top.coord.x + self.draw_offset.x;
-            let mut delta = 2 * dx - dy;
-            for y in y_begin..=y_end {
-                if y >= self.drawing_area.top &&
-                    x >= self.drawing_area.left && x < self.drawing_area.right {
-                    let addr = (y as usize) * 1024 + (x as usize);
-                    self.write_dithered_pixel(addr, &color.get(), trans_mode);
+            self.field_lines[row].copy_from_slice(new_line);
+        }
+        for y in field_rows(1 - field, height, scale) {
+            let above = y.checked_sub(scale);
+            let below = Some(y + scale).filter(|b| *b < height);
+            for x in 0..width {
+                let moving = [above, below].iter().flatten().any(|n| self.motion[n * width + x]);
+                let idx = y * line_size + x * 4;
+                if !moving {
+                    frame.frame_buffer[idx..(idx + 3)].copy_from_slice(&self.field_lines[idx..(idx + 3)]);
+                    continue;
                 }
-                if delta > 0 {
-                    x += x_step;
-                    delta += 2 * (dx - dy);
-                } else {
-                    delta += 2 * dx;
+                for i in 0..3 {
+                    let a = above.map(|n| frame.frame_buffer[n * line_size + x * 4 + i]);
+                    let b = below.map(|n| frame.frame_buffer[n * line_size + x * 4 + i]);
+                    frame.frame_buffer[idx + i] = match (a, b) {
+                        (Some(a), Some(b)) => ((a as u16 + b as u16) / 2) as u8,
+                        (Some(v), None) | (None, Some(v)) => v,
+                        (None, None) => frame.frame_buffer[idx + i],
+                    };
                 }
-                color.inc();
             }
         }
     }
-}
 
-// Internal
-impl SoftwareRenderer {
     fn draw_wireframe_triangle(&mut self, vertices: &[Vertex], transparent: bool) {
         self.draw_line(&vertices[0], &vertices[1], transparent);
         self.draw_line(&vertices[1], &vertices[2], transparent);
@@ -417,142 +648,193 @@ impl SoftwareRenderer {
 
     #[inline(always)]
     fn write_dithered_pixel(&mut self, addr: usize, color: &Color, trans_mode: Option<TransparencyMode>) {
-        let dithered_color = if self.dither {
-            let x = addr % 4;
-            let y = (addr / 1024) % 4;
-            let dither_addr = (y << 2) | x;
-            color.dither(DITHER_LUT[dither_addr])
-        } else {
-            color.clone()
-        };
-        self.write_pixel(addr, &dithered_color, trans_mode);
+        if !self.dither {
+            return self.write_pixel(addr, color, trans_mode);
+        }
+        // The pattern stays at native size when upscaling.
+        let stride = self.stride();
+        let x = ((addr % stride) / self.target_scale) % 4;
+        let y = ((addr / stride) / self.target_scale) % 4;
+        let dither_addr = (y << 2) | x;
+        let dithered_color = color.dither(DITHER_LUT[dither_addr]);
+        // True colour has no need to hide banding.
+        self.write_colors(addr, &dithered_color, color, trans_mode);
     }
 
     #[inline(always)]
     fn write_pixel(&mut self, addr: usize, color: &Color, trans_mode: Option<TransparencyMode>) {
-        if !self.check_mask_bit || !test_bit!(self.vram[addr], 15) {
-            let color = if let Some(trans_mode) = trans_mode {
-                let base_color = Color::from_rgb15(self.vram[addr]);
-                trans_mode.blend(&base_color, color)
-            } else {
-                *color
+        self.write_colors(addr, color, color, trans_mode);
+    }
+
+    /// Write a pixel to VRAM, and to the true colour buffer if there is one.
+    /// VRAM is blended in 15 bits, as the GPU does.
+    #[inline(always)]
+    fn write_colors(&mut self, addr: usize, color: &Color, full_color: &Color, trans_mode: Option<TransparencyMode>) {
+        let target = self.target();
+        let base = target.get(addr);
+        if !self.check_mask_bit || !test_bit!(base, 15) {
+            let blend = |color: &Color, base_color: Color| match trans_mode {
+                Some(trans_mode) => trans_mode.blend(&base_color, color),
+                None => *color,
             };
-            self.vram[addr] = color.to_rgb15();
+            target.set(addr, blend(color, Color::from_rgb15(base)).to_rgb15());
+            if let Some(true_color) = self.true_color_target() {
+                true_color.set(addr, &blend(full_color, true_color.get(addr, base)));
+            }
         }
     }
 
-    fn rasterize_triangle<F: Fn(&mut Self, &Line, usize)>(&mut self, vertices: &[Vertex], raster_f: F) {
-        let mut min_y = std::i16::MAX;
-        let mut max_y = std::i16::MIN;
-        //println!("Draw triangle: offset: ({}, {}) bounds: ({},{}) => ({},{})", self.draw_offset.x, self.draw_offset.y, self.drawing_area.left, self.drawing_area.top, self.drawing_area.right, self.drawing_area.bottom);
-        for v in vertices {
-            //println!("  {}, {} TEX: {}, {}", v.coord.x, v.coord.y, v.tex.s, v.tex.t);
-            min_y = min_y.min(v.coord.y);
-            max_y = max_y.max(v.coord.y);
+    /// Scale of the frame output.
+    fn output_scale(&self) -> usize {
+        self.shadow.as_ref().map_or(1, |shadow| shadow.scale)
+    }
+
+    /// Draw into the shadow VRAM if upscaling, then into native VRAM.
+    /// Textures are read from native VRAM, so the shadow is drawn first
+    /// to see the same texels when a primitive draws over its own texture.
+    fn draw_scaled<F: Fn(&mut Self)>(&mut self, draw: F) {
+        if let Some(scale) = self.shadow.as_ref().map(|shadow| shadow.scale) {
+            // The texture cache should only see what native drawing loaded.
+            let tex_cache = self.tex_cache.clone();
+            self.target_scale = scale;
+            draw(self);
+            self.target_scale = 1;
+            self.tex_cache = tex_cache;
         }
-        //min_y = min_y.max(self.drawing_area.top);
-        //max_y = max_y.min(self.drawing_area.bottom);
-        let Some(mut lines) = Self::get_intersection_points(vertices, min_y) else {
-            //println!("No intersection points found");
-            return;
-        };
-        //println!("Line 0 (tex) {:X},{:X} => {:X},{:X}", lines.left.tex_s, lines.left.tex_t, lines.right.tex_s, lines.right.tex_t);
-        //println!("Line 0 (rgb) {:X},{:X},{:X}", lines.left.r_gradient, lines.left.b_gradient, lines.left.g_gradient);
-        // TODO: clip at view bounds
-        self.draw_lines(min_y, &mut lines, &raster_f);
-        min_y = lines.max_y;
-        /*if min_y > self.drawing_area.bottom {
-            return;
-        }*/
-        // TODO: validate that we get more if min_y < max_y
-        // Also: we kind of want to _replace_ one of our lines (either left or right?)
-        if let Some(mut lines) = Self::get_intersection_points(vertices, min_y) {
-            // Continue drawing.
-            self.draw_lines(min_y, &mut lines, &raster_f);
-        };
+        draw(self);
     }
 
-    fn draw_lines<F: Fn(&mut Self, &Line, usize)>(&mut self, min_y: i16, lines: &mut Lines, raster_f: &F) {
-        let base_y = min_y + self.draw_offset.y;
-        if base_y < self.drawing_area.top {
-            lines.left.mul((self.drawing_area.top - base_y) as i32);
-            lines.right.mul((self.drawing_area.top - base_y) as i32);
-        }
-        let min_y = base_y.max(self.drawing_area.top);
-        let max_y = (lines.max_y + self.draw_offset.y).min(self.drawing_area.bottom);
-        for y in min_y..max_y {
-            let left = lines.left.get_x() + self.draw_offset.x;
-            let right = lines.right.get_x() + self.draw_offset.x;
-            let min_x = left.max(self.drawing_area.left);
-            let max_x = right.min(self.drawing_area.right);
-            if lines.left.get_x() != lines.right.get_x() {
-                let line_addr = (y as usize) * 1024;
-                let mut line = Line::from_lines(&lines.left, &lines.right, lines.left.get_x());
-                if left < self.drawing_area.left {
-                    line.mul((self.drawing_area.left - left) as i32);
-                }
-                for x in min_x..max_x {
-                    let addr = line_addr + (x as usize);
-                    raster_f(self, &line, addr);
-                    line.inc();
-                }
-            }
-            lines.left.inc();
-            lines.right.inc();
+    /// VRAM being drawn to.
+    #[inline(always)]
+    fn target(&self) -> &Vram {
+        match self.shadow.as_ref() {
+            Some(shadow) if self.target_scale > 1 => &shadow.data,
+            _ => &self.vram,
         }
     }
 
-    fn get_intersection_points(vertices: &[Vertex], line: i16) -> Option<Lines> {
-        let mut left: Option<Line> = None;
-        let mut right: Option<Line> = None;
-        let mut max_y = i16::MAX;
-        for i in 0..3 {
-            let vertex_a = &vertices[i];
-            let vertex_b = &vertices[(i + 1) % 3];
-            // (0,0) is TOP-LEFT.
-            let (top, bottom) = if vertex_a.coord.y > vertex_b.coord.y {
-                (vertex_b, vertex_a)
-            } else {
-                (vertex_a, vertex_b)
-            };
-            if top.coord.y == bottom.coord.y || top.coord.y > line || bottom.coord.y <= line {
-                continue;
-            }
-            
-            max_y = max_y.min(bottom.coord.y);
-            let line = Line::from_vertices(top, bottom, line);
-            if let Some(other_line) = left.take() {
-                if line.x.val < other_line.x.val {
-                    right = Some(other_line);
-                    left = Some(line);
-                } else if line.x.val > other_line.x.val {
-                    left = Some(other_line);
-                    right = Some(line);
-                } else { // equal
-                    if line.x.gradient < other_line.x.gradient {
-                        right = Some(other_line);
-                        left = Some(line);
-                    } else {
-                        left = Some(other_line);
-                        right = Some(line);
-                    }
-                }
-            } else {
-                left = Some(line);
-            }
+    /// Colour buffer for the VRAM being drawn to.
+    /// Drawing to native VRAM while upscaling only needs 15 bits.
+    #[inline(always)]
+    fn true_color_target(&self) -> Option<&TrueColorBuffer> {
+        self.true_color.as_ref().filter(|_| self.target_scale == self.output_scale())
+    }
+
+    /// Colour buffer for native VRAM, if frames are output from it.
+    #[inline(always)]
+    fn native_true_color(&self) -> Option<&TrueColorBuffer> {
+        self.true_color.as_ref().filter(|_| self.shadow.is_none())
+    }
+
+    /// Check if a native line is drawn by this renderer.
+    #[inline(always)]
+    fn owns_line(&self, y: i16) -> bool {
+        self.band.is_none_or(|(band, bands)| (y / BAND_LINES) % bands == band)
+    }
+
+    /// Halfwords per line of the VRAM being drawn to.
+    #[inline(always)]
+    fn stride(&self) -> usize {
+        1024 * self.target_scale
+    }
+
+    /// Apply the drawing offset to a vertex.
+    fn offset_vertex(&self, vertex: &Vertex) -> Vertex {
+        let mut vertex = vertex.clone();
+        vertex.coord = Coord {
+            x: vertex.coord.x + self.draw_offset.x,
+            y: vertex.coord.y + self.draw_offset.y,
+        }.vertex_clip();
+        vertex
+    }
+
+    fn rasterize_triangle<F: Fn(&mut Self, &Attribs, usize)>(&mut self, vertices: &[Vertex], raster_f: F) {
+        let mut vertices = [0, 1, 2].map(|n| self.offset_vertex(&vertices[n]));
+        let min_x = vertices.iter().map(|v| v.coord.x).min().unwrap();
+        let max_x = vertices.iter().map(|v| v.coord.x).max().unwrap();
+        vertices.sort_by_key(|v| v.coord.y);
+        let [top, bottom] = [vertices[0].coord, vertices[2].coord];
+        if max_x - min_x > raster::MAX_WIDTH || bottom.y - top.y > raster::MAX_HEIGHT || top.y == bottom.y {
+            return;
         }
-        if left.is_some() && right.is_some() {
-            Some(Lines {
-                left: left.unwrap(),
-                right: right.unwrap(),
-                max_y,
-            })
+        self.draw_scaled(|renderer| {
+            let scale = renderer.target_scale as i16;
+            let scaled = vertices.clone().map(|mut v| {
+                v.coord = Coord { x: v.coord.x * scale, y: v.coord.y * scale };
+                v
+            });
+            renderer.rasterize_sorted_triangle(&scaled, &raster_f);
+        });
+    }
+
+    /// Walk the edges of a triangle with vertices sorted by Y,
+    /// in pixels of the VRAM being drawn to.
+    fn rasterize_sorted_triangle<F: Fn(&mut Self, &Attribs, usize)>(&mut self, vertices: &[Vertex; 3], raster_f: &F) {
+        let [top, mid, bottom] = vertices.each_ref().map(|v| v.coord);
+        let Some(gradients) = Gradients::new(vertices) else {
+            return;
+        };
+        // The long edge runs from top to bottom. The middle vertex is
+        // on one side of it, and the two short edges are on that side.
+        let base_step = raster::edge_step(bottom.x - top.x, bottom.y - top.y);
+        let (upper_step, right_facing) = if mid.y == top.y {
+            (0, mid.x > top.x)
         } else {
-            None
+            let step = raster::edge_step(mid.x - top.x, mid.y - top.y);
+            (step, step > base_step)
+        };
+        let lower_step = if bottom.y == mid.y {0} else {
+            raster::edge_step(bottom.x - mid.x, bottom.y - mid.y)
+        };
+        let short = right_facing as usize;
+        let long = 1 - short;
+
+        let mut edges = [raster::edge_x(top.x); 2];
+        let mut steps = [base_step; 2];
+        steps[short] = upper_step;
+        self.draw_spans(top.y, mid.y, edges, steps, &gradients, raster_f);
+
+        edges[long] += base_step * (mid.y - top.y) as i64;
+        edges[short] = raster::edge_x(mid.x);
+        steps[short] = lower_step;
+        self.draw_spans(mid.y, bottom.y, edges, steps, &gradients, raster_f);
+    }
+
+    /// Fill the lines from y_start up to y_end, between a left and right edge.
+    /// Pixels on the left edge are drawn, and pixels on the right edge are not.
+    fn draw_spans<F: Fn(&mut Self, &Attribs, usize)>(&mut self, y_start: i16, y_end: i16, mut edges: [i64; 2], steps: [i64; 2], gradients: &Gradients, raster_f: &F) {
+        let scale = self.target_scale as i16;
+        let area = DrawingArea {
+            top: self.drawing_area.top * scale,
+            bottom: self.drawing_area.bottom * scale,
+            left: self.drawing_area.left * scale,
+            right: self.drawing_area.right * scale,
+        };
+        let first = y_start.max(area.top);
+        let last = y_end.min(area.bottom);
+        if first >= last {
+            return;
+        }
+        let skip = (first - y_start) as i64;
+        edges[0] += steps[0] * skip;
+        edges[1] += steps[1] * skip;
+        for y in first..last {
+            let left = raster::edge_pixel(edges[0]).max(area.left);
+            let right = raster::edge_pixel(edges[1]).min(area.right);
+            if left < right && self.owns_line(y / scale) {
+                let line_addr = (y as usize) * self.stride();
+                let mut attribs = gradients.at(left, y);
+                for x in left..right {
+                    raster_f(self, &attribs, line_addr + (x as usize));
+                    attribs.step(&gradients.dx, 1);
+                }
+            }
+            edges[0] += steps[0];
+            edges[1] += steps[1];
         }
     }
 
-    fn tex_lookup(&self, tex_coords: &TexCoord, tex_info: &TexInfo) -> u16 {
+    fn tex_lookup(&mut self, tex_coords: &TexCoord, tex_info: &TexInfo) -> u16 {
         let tex_u = (tex_coords.u & !self.tex_window.mask_u) | (self.tex_window.mask_u & self.tex_window.offset_u);
         let tex_v = (tex_coords.v & !self.tex_window.mask_v) | (self.tex_window.mask_v & self.tex_window.offset_v);
         let v = tex_v as usize + tex_info.v_base;
@@ -560,198 +842,54 @@ impl SoftwareRenderer {
             TexMode::Palette4 => {
                 let u = (tex_u as usize / 4) + tex_info.u_base;
                 let tex_addr = v * 1024 + u;
-                let data = self.vram[tex_addr];
+                let data = self.read_texture(tex_addr);
                 let palette_shift = (tex_u & 0x3) * 4;
                 let palette_idx = ((data >> palette_shift) & 0xF) as usize;
                 //println!("s: {:X} t: {:X} addr: {:X} idx: {:X}", line.tex_s, line.tex_t, tex_addr, palette_idx);
-                let palette_addr = tex_info.palette_coord.y * 1024 + tex_info.palette_coord.x + palette_idx;
-                self.vram[palette_addr]
+                self.read_palette(tex_info, 16, palette_idx)
             },
             TexMode::Palette8 => {
                 let u = (tex_u as usize / 2) + tex_info.u_base;
                 let tex_addr = v * 1024 + u;
-                let data = self.vram[tex_addr];
+                let data = self.read_texture(tex_addr);
                 let palette_shift = (tex_u & 0x1) * 8;
                 let palette_idx = ((data >> palette_shift) & 0xFF) as usize;
-                let palette_addr = tex_info.palette_coord.y * 1024 + tex_info.palette_coord.x + palette_idx;
-                self.vram[palette_addr]
+                self.read_palette(tex_info, 256, palette_idx)
             },
             TexMode::Direct => {
                 let u = tex_u as usize + tex_info.u_base;
                 let tex_addr = v * 1024 + u;
-                self.vram[tex_addr]
+                self.read_texture(tex_addr)
             }
         }
     }
-}
-
-#[derive(Default)]
-struct InterpolatedValue {
-    // Gradient is a fixed-point factor.
-    // 16 i bits and 16 f bits.
-    gradient: i32,
-    val: i32,
-}
 
-impl InterpolatedValue {
-    fn new(gradient: i32, a: i32, b: i32, i: i32) -> Self {
-        let gradient = gradient * (b - a);
-        Self {
-            gradient,
-            val: (a << 16) + i * gradient,
+    fn read_texture(&mut self, addr: usize) -> u16 {
+        match self.tex_cache.as_mut() {
+            Some(cache) => cache.read_texture(&self.vram, addr),
+            None => self.vram.get(addr),
         }
     }
 
-    fn new_shifted(gradient: i32, a: i32, b: i32, i: i32) -> Self {
-        let gradient = gradient * ((b - a) >> 16);
-        Self {
-            gradient,
-            val: a + i * gradient,
-        }
-    }
-
-    fn inc(&mut self) {
-        self.val += self.gradient;
-    }
-
-    /// Inc i times.
-    fn mul(&mut self, i: i32) {
-        self.val += i * self.gradient;
-    }
-}
-
-struct Line {
-    x: InterpolatedValue,
-    r: InterpolatedValue,
-    g: InterpolatedValue,
-    b: InterpolatedValue,
-    tex_u: InterpolatedValue,
-    tex_v: InterpolatedValue,
-}
-
-impl Line {
-    fn from_vertices(top: &Vertex, bottom: &Vertex, line: i16) -> Self {
-        let span_size = (bottom.coord.y - top.coord.y) as i32;
-        let gradient = ((1 << 16) / span_size) + 1;
-        let i = (line - top.coord.y) as i32;
-        let top_tex_u = if top.tex.u == 255 {256} else {top.tex.u as i32};
-        let top_tex_v = if top.tex.v == 255 {256} else {top.tex.v as i32};
-        let bottom_tex_u = if bottom.tex.u == 255 {256} else {bottom.tex.u as i32};
-        let bottom_tex_v = if bottom.tex.v == 255 {256} else {bottom.tex.v as i32};
-        Self {
-            x: InterpolatedValue::new(gradient, top.coord.x.into(), bottom.coord.x.into(), i),
-            r: InterpolatedValue::new(gradient, top.col.r.into(), bottom.col.r.into(), i),
-            g: InterpolatedValue::new(gradient, top.col.g.into(), bottom.col.g.into(), i),
-            b: InterpolatedValue::new(gradient, top.col.b.into(), bottom.col.b.into(), i),
-            tex_u: InterpolatedValue::new(gradient, top_tex_u, bottom_tex_u, i),
-            tex_v: InterpolatedValue::new(gradient, top_tex_v, bottom_tex_v, i),
-        }
-    }
-
-    fn from_lines(left: &Line, right: &Line, x: i16) -> Self {
-        let left_x = left.get_x();
-        let right_x = right.get_x();
-        let span_size = (right_x - left_x) as i32;
-        let gradient = ((1 << 16) / span_size) + 1;
-        let i = (x - left_x) as i32;
-        let left_tex_u = if (left.tex_u.val >> 16) == 255 {256} else {left.tex_u.val >> 16};
-        let left_tex_v = if (left.tex_v.val >> 16) == 255 {256} else {left.tex_v.val >> 16};
-        let right_tex_u = if (right.tex_u.val >> 16) == 255 {256} else {right.tex_u.val >> 16};
-        let right_tex_v = if (right.tex_v.val >> 16) == 255 {256} else {right.tex_v.val >> 16};
-        Self {
-            x: InterpolatedValue::default(),
-            r: InterpolatedValue::new_shifted(gradient, left.r.val, right.r.val, i),
-            g: InterpolatedValue::new_shifted(gradient, left.g.val, right.g.val, i),
-            b: InterpolatedValue::new_shifted(gradient, left.b.val, right.b.val, i),
-            tex_u: InterpolatedValue::new(gradient, left_tex_u, right_tex_u, i),
-            tex_v: InterpolatedValue::new(gradient, left_tex_v, right_tex_v, i),
+    fn read_palette(&mut self, tex_info: &TexInfo, size: usize, index: usize) -> u16 {
+        let palette_addr = tex_info.palette_coord.y * 1024 + tex_info.palette_coord.x;
+        match self.tex_cache.as_mut() {
+            Some(cache) => cache.read_clut(&self.vram, palette_addr, size, index),
+            None => self.vram.get(palette_addr + index),
         }
     }
 
-    fn get_x(&self) -> i16 {
-        (self.x.val >> 16) as i16
-    }
-    fn get_color(&self) -> Color {
-        Color {
-            r: (self.r.val >> 16).clamp(0, 0xFF) as u8,
-            g: (self.g.val >> 16).clamp(0, 0xFF) as u8,
-            b: (self.b.val >> 16).clamp(0, 0xFF) as u8,
-            mask: 0,
+    fn set_tex_cache_page(&mut self, tex_info: &TexInfo) {
+        if let Some(cache) = self.tex_cache.as_mut() {
+            cache.set_page((tex_info.u_base, tex_info.v_base, tex_info.tex_mode));
         }
     }
-    fn get_tex_coords(&self) -> TexCoord {
-        let u = (self.tex_u.val >> 16).clamp(0, 0xFF) as u8;
-        let v = (self.tex_v.val >> 16).clamp(0, 0xFF) as u8;
-        TexCoord { u, v }
-    }
-
-    /// Advance internal state.
-    fn inc(&mut self) {
-        self.x.inc();
-        self.r.inc();
-        self.g.inc();
-        self.b.inc();
-        self.tex_u.inc();
-        self.tex_v.inc();
-    }
-
-    /// Inc i times.
-    fn mul(&mut self, i: i32) {
-        self.x.mul(i);
-        self.r.mul(i);
-        self.g.mul(i);
-        self.b.mul(i);
-        self.tex_u.mul(i);
-        self.tex_v.mul(i);
-    }
-}
-
-struct Lines {
-    left: Line,
-    right: Line,
-    max_y: i16,
 }
 
-struct InterpolatedColor {
-    r: InterpolatedValue,
-    g: InterpolatedValue,
-    b: InterpolatedValue,
-}
-
-impl InterpolatedColor {
-    fn from_x_major(left: &Vertex, right: &Vertex) -> Self {
-        let gradient = (1_i32 << 16).checked_div((right.coord.x - left.coord.x) as i32).unwrap_or(0);
-        Self {
-            r: InterpolatedValue::new(gradient, left.col.r.into(), right.col.r.into(), 0),
-            g: InterpolatedValue::new(gradient, left.col.g.into(), right.col.g.into(), 0),
-            b: InterpolatedValue::new(gradient, left.col.b.into(), right.col.b.into(), 0),
-        }
-    }
-
-    fn from_y_major(top: &Vertex, bottom: &Vertex) -> Self {
-        let gradient = (1_i32 << 16).checked_div((bottom.coord.y - top.coord.y) as i32).unwrap_or(0);
-        Self {
-            r: InterpolatedValue::new(gradient, top.col.r.into(), bottom.col.r.into(), 0),
-            g: InterpolatedValue::new(gradient, top.col.g.into(), bottom.col.g.into(), 0),
-            b: InterpolatedValue::new(gradient, top.col.b.into(), bottom.col.b.into(), 0),
-        }
-    }
-
-    fn get(&self) -> Color {
-        Color {
-            r: ((self.r.val + 0x8000) >> 16).clamp(0, 0xFF) as u8,
-            g: ((self.g.val + 0x8000) >> 16).clamp(0, 0xFF) as u8,
-            b: ((self.b.val + 0x8000) >> 16).clamp(0, 0xFF) as u8,
-            mask: 0,
-        }
-    }
-
-    /// Advance internal state.
-    fn inc(&mut self) {
-        self.r.inc();
-        self.g.inc();
-        self.b.inc();
-    }
+/// Rows of a frame that show one field.
+/// Each line is a block of rows when upscaling.
+fn field_rows(field: usize, height: usize, scale: usize) -> impl Iterator<Item = usize> {
+    (0..height).filter(move |y| (y / scale) & 1 == field)
 }
 
 const DITHER_LUT: [i8; 16] = [
@@ -759,4 +897,4 @@ const DITHER_LUT: [i8; 16] = [
      2, -2,  3, -1,
     -3,  1, -4,  0,
      3, -1,  2, -2
-];
\ No newline at end of file
+];
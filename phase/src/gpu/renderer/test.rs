@@ -0,0 +1,276 @@
+use super::*;
+
+fn vertex(x: i16, y: i16, col: u32) -> Vertex {
+    Vertex { coord: Coord { x, y }, col: Color::from_rgb24(col), tex: TexCoord { u: 0, v: 0 } }
+}
+
+/// 15-bit texture page at the top left of VRAM.
+fn direct_texture() -> TexInfo {
+    TexInfo::from_draw_mode(0x100, PaletteCoord { x: 0, y: 0 })
+}
+
+/// Make a renderer that can draw anywhere in VRAM.
+fn threaded_renderer(threads: usize) -> ThreadedRenderer {
+    let mut renderer = ThreadedRenderer::new(SoftwareRenderer::new());
+    renderer.set_render_threads(threads);
+    renderer.set_draw_area_top_left(0, 0);
+    renderer.set_draw_area_bottom_right(1023, 511);
+    renderer
+}
+
+fn read_vram(renderer: &mut impl RendererImpl) -> Vec<u16> {
+    let mut data = vec![0; VRAM_SIZE / 2];
+    renderer.read_vram_block(&mut data, Coord { x: 0, y: 0 }, Size { width: 1024, height: 512 });
+    data
+}
+
+#[test]
+fn threaded_render_to_texture() {
+    let draw = |renderer: &mut ThreadedRenderer| {
+        let texture = (0..(256 * 256)).map(|n| n as u16 | 1).collect::<Vec<_>>();
+        renderer.write_vram_block(&texture, Coord { x: 0, y: 0 }, Size { width: 256, height: 256 });
+        for n in 0..32 {
+            let x = 512 + n * 4;
+            // Queued in a drawing area away from the texture page...
+            renderer.set_draw_area_top_left(512, 0);
+            renderer.draw_triangle_shaded(&[vertex(x, 0, 0x0000FF), vertex(x + 64, 0, 0x00FF00), vertex(x, 96, 0xFF0000)], false);
+            // ...then drawn over by a primitive that can draw to its own texture page.
+            renderer.set_draw_area_top_left(0, 0);
+            renderer.draw_rectangle_tex(Color::default(), TexCoord { u: 8, v: 8 }, &direct_texture(), Coord { x, y: 4 }, Size { width: 48, height: 48 }, false);
+        }
+    };
+    let mut single = threaded_renderer(1);
+    let mut threaded = threaded_renderer(4);
+    draw(&mut single);
+    draw(&mut threaded);
+    assert!(read_vram(&mut single) == read_vram(&mut threaded));
+}
+
+fn output_frame(renderer: &mut impl RendererImpl) -> Frame {
+    let mut frame = Frame::new();
+    renderer.get_frame(&mut frame, InterlaceState::Off, false);
+    frame
+}
+
+#[test]
+fn upscaled_render_to_texture() {
+    let draw = |scale: usize| {
+        let mut renderer = threaded_renderer(1);
+        renderer.set_resolution_scale(scale);
+        renderer.enable_display(true);
+        let texture = (0..(256 * 256)).map(|n| n as u16 | 1).collect::<Vec<_>>();
+        renderer.write_vram_block(&texture, Coord { x: 0, y: 0 }, Size { width: 256, height: 256 });
+        // Each pixel reads a texel the same rectangle draws over later.
+        renderer.draw_rectangle_tex(Color::default(), TexCoord { u: 8, v: 8 }, &direct_texture(), Coord { x: 4, y: 4 }, Size { width: 48, height: 48 }, false);
+        output_frame(&mut renderer)
+    };
+    let native = draw(1);
+    let upscaled = draw(2);
+    assert_eq!(upscaled.size, (native.size.0 * 2, native.size.1 * 2));
+    for y in 0..native.size.1 {
+        for x in 0..native.size.0 {
+            let native_idx = (y * native.size.0 + x) * 4;
+            let upscaled_idx = (y * 2 * upscaled.size.0 + x * 2) * 4;
+            assert_eq!(native.frame_buffer[native_idx..(native_idx + 4)], upscaled.frame_buffer[upscaled_idx..(upscaled_idx + 4)], "pixel {x}, {y}");
+        }
+    }
+}
+
+#[test]
+fn adaptive_deinterlace_weaves_still_picture() {
+    let mut renderer = threaded_renderer(1);
+    renderer.enable_display(true);
+    renderer.set_deinterlace_mode(DeinterlaceMode::Adaptive);
+    renderer.set_overscan_mode(OverscanMode::GameArea);
+    renderer.set_display_resolution(Size { width: 640, height: 480 }, true, false);
+    renderer.set_display_range_y(0x10, 0x100);
+    // Even lines white, odd lines black.
+    let picture = (0..(640 * 480)).map(|n| if (n / 640) % 2 == 0 {0x7FFF} else {0}).collect::<Vec<_>>();
+    renderer.write_vram_block(&picture, Coord { x: 0, y: 0 }, Size { width: 640, height: 480 });
+    let mut frame = Frame::new();
+    for _ in 0..3 {
+        renderer.get_frame(&mut frame, InterlaceState::Even, false);
+        renderer.get_frame(&mut frame, InterlaceState::Odd, false);
+    }
+    let line_size = frame.size.0 * 4;
+    for y in 0..frame.size.1 {
+        let expected = if y % 2 == 0 {0xFF} else {0};
+        assert!(frame.frame_buffer[(y * line_size)..((y + 1) * line_size)].chunks_exact(4).all(|p| p[0] == expected), "line {y}");
+    }
+}
+
+#[test]
+fn true_color_follows_vram() {
+    let mut renderer = threaded_renderer(1);
+    renderer.enable_display(true);
+    renderer.set_overscan_mode(OverscanMode::GameArea);
+    renderer.set_display_resolution(Size { width: 320, height: 240 }, false, false);
+    renderer.set_display_range_y(0x10, 0x100);
+    renderer.set_true_color(true);
+    renderer.set_draw_mode(TransparencyMode::Average, true);
+    // A shade between two 15-bit colours.
+    let shade = 0x0F0F0F;
+    renderer.draw_triangle_shaded(&[vertex(0, 0, shade), vertex(256, 0, shade), vertex(0, 64, shade)], false);
+    renderer.copy_vram_block(Coord { x: 0, y: 0 }, Coord { x: 100, y: 100 }, Size { width: 16, height: 16 });
+    renderer.fill_rectangle(Color::from_rgb24(0xFFFFFF), Coord { x: 0, y: 8 }, Size { width: 16, height: 8 });
+    renderer.write_vram_block(&[0x001F; 16], Coord { x: 200, y: 0 }, Size { width: 4, height: 4 });
+
+    // VRAM is dithered as usual...
+    let vram = read_vram(&mut renderer);
+    let reds = vram[0..16].iter().map(|p| p & 0x1F).collect::<Vec<_>>();
+    assert!(reds.contains(&1) && reds.contains(&2), "{reds:?}");
+    // ...but the output is not.
+    let frame = output_frame(&mut renderer);
+    let pixel = |x: usize, y: usize| {
+        let idx = (y * frame.size.0 + x) * 4;
+        (frame.frame_buffer[idx], frame.frame_buffer[idx + 1], frame.frame_buffer[idx + 2])
+    };
+    assert_eq!(pixel(4, 4), (0x0F, 0x0F, 0x0F));
+    assert_eq!(pixel(104, 104), (0x0F, 0x0F, 0x0F));
+    assert_eq!(pixel(4, 12), (0xFF, 0xFF, 0xFF));
+    assert_eq!(pixel(201, 1), (0xFF, 0, 0));
+}
+
+fn drawn_pixels(renderer: &mut impl RendererImpl) -> usize {
+    read_vram(renderer).iter().filter(|p| **p != 0).count()
+}
+
+#[test]
+fn triangle_edge_rules() {
+    let mut renderer = threaded_renderer(1);
+    renderer.set_draw_mode(TransparencyMode::Add, false);
+    let color = Color::from_rgb24(0x080808);
+    // Two triangles making an 8x8 square share an edge.
+    renderer.draw_triangle_flat(&[vertex(2, 2, 0), vertex(10, 2, 0), vertex(2, 10, 0)], color, true);
+    renderer.draw_triangle_flat(&[vertex(10, 2, 0), vertex(2, 10, 0), vertex(10, 10, 0)], color, true);
+    let vram = read_vram(&mut renderer);
+    for y in 0..16 {
+        for x in 0..16 {
+            // Left and top edges are drawn, right and bottom edges are not.
+            let inside = (2..10).contains(&x) && (2..10).contains(&y);
+            assert_eq!(vram[y * 1024 + x], if inside {0x0421} else {0}, "pixel {x}, {y}");
+        }
+    }
+}
+
+#[test]
+fn oversized_primitives_are_dropped() {
+    let color = Color::from_rgb24(0x080808);
+    let draw_triangle = |vertices: [Vertex; 3]| {
+        let mut renderer = threaded_renderer(1);
+        renderer.draw_triangle_flat(&vertices, color, false);
+        drawn_pixels(&mut renderer)
+    };
+    assert!(draw_triangle([vertex(-100, 0, 0), vertex(923, 0, 0), vertex(0, 8, 0)]) > 0);
+    assert_eq!(draw_triangle([vertex(-100, 0, 0), vertex(924, 0, 0), vertex(0, 8, 0)]), 0);
+    assert!(draw_triangle([vertex(0, -100, 0), vertex(8, 0, 0), vertex(0, 411, 0)]) > 0);
+    assert_eq!(draw_triangle([vertex(0, -100, 0), vertex(8, 0, 0), vertex(0, 412, 0)]), 0);
+
+    let draw_line = |a: Vertex, b: Vertex| {
+        let mut renderer = threaded_renderer(1);
+        renderer.draw_line(&a, &b, false);
+        drawn_pixels(&mut renderer)
+    };
+    assert_eq!(draw_line(vertex(-100, 0, 0xFFFFFF), vertex(923, 0, 0xFFFFFF)), 924);
+    assert_eq!(draw_line(vertex(-100, 0, 0xFFFFFF), vertex(924, 0, 0xFFFFFF)), 0);
+    assert_eq!(draw_line(vertex(0, -100, 0xFFFFFF), vertex(0, 412, 0xFFFFFF)), 0);
+}
+
+#[test]
+fn overscan_windows() {
+    use display::DisplayArea;
+    // Ranges set by the BIOS for each video standard.
+    let ntsc = |mode| DisplayArea::new(mode, Size { width: 320, height: 240 }, false, (0x260, 0xC60), (0x10, 0x100));
+    let pal = |mode| DisplayArea::new(mode, Size { width: 320, height: 240 }, true, (0x260, 0xC60), (0x23, 0x123));
+    // Video standard, overscan mode, lines in frame, first displayed line.
+    let cases = [
+        (ntsc(OverscanMode::Full), 240, 0),
+        (ntsc(OverscanMode::SafeArea), 224, -8),
+        (ntsc(OverscanMode::GameArea), 240, 0),
+        (pal(OverscanMode::Full), 288, 15),
+        (pal(OverscanMode::SafeArea), 272, 7),
+        (pal(OverscanMode::GameArea), 256, 0),
+    ];
+    for (n, (area, lines, y)) in cases.into_iter().enumerate() {
+        assert_eq!(area.frame_size.height, lines, "case {n}");
+        assert_eq!(area.y, y, "case {n}");
+        assert_eq!(area.height, if n < 3 {240} else {256}, "case {n}");
+    }
+    // The whole picture fits in the frame.
+    let area = pal(OverscanMode::Full);
+    assert!(area.y + area.height as i32 <= area.frame_size.height as i32);
+}
+
+#[test]
+fn beam_position_follows_display_range() {
+    use display::DisplayArea;
+    let ntsc = DisplayArea::new(OverscanMode::GameArea, Size { width: 320, height: 240 }, false, (0x260, 0xC60), (0x10, 0x100));
+    assert_eq!(ntsc.beam_position(0.0, 0.0), Some((0x260 as f64, 0x10)));
+    assert_eq!(ntsc.beam_position(0.5, 0.5), Some((0x260 as f64 + 160.0 * 8.0, 0x10 + 120)));
+
+    let pal = DisplayArea::new(OverscanMode::Full, Size { width: 320, height: 240 }, true, (0x260, 0xC60), (0x23, 0x123));
+    // The border around the display isn't drawn by the beam.
+    assert_eq!(pal.beam_position(0.0, 0.0), None);
+    let (x, y) = (pal.x as f32 / pal.frame_size.width as f32, pal.y as f32 / pal.frame_size.height as f32);
+    assert_eq!(pal.beam_position(x, y), Some((0x260 as f64, 0x23)));
+    // Lines near the bottom of a PAL picture.
+    let bottom = (pal.y as f32 + 255.5) / pal.frame_size.height as f32;
+    assert_eq!(pal.beam_position(x, bottom).map(|(_, line)| line), Some(0x122));
+}
+
+/// Texture where each texel holds 1 + u + 16v.
+fn numbered_texture(renderer: &mut impl RendererImpl) {
+    let texture = (0..(16 * 16)).map(|n| n as u16 + 1).collect::<Vec<_>>();
+    renderer.write_vram_block(&texture, Coord { x: 0, y: 0 }, Size { width: 16, height: 16 });
+}
+
+#[test]
+fn rectangle_flip() {
+    let mut renderer = threaded_renderer(1);
+    numbered_texture(&mut renderer);
+    renderer.set_rect_flip(true, false);
+    renderer.draw_rectangle_tex(Color::default(), TexCoord { u: 7, v: 2 }, &direct_texture(), Coord { x: 512, y: 0 }, Size { width: 8, height: 2 }, false);
+    renderer.set_rect_flip(false, true);
+    renderer.draw_rectangle_tex(Color::default(), TexCoord { u: 0, v: 7 }, &direct_texture(), Coord { x: 512, y: 4 }, Size { width: 2, height: 8 }, false);
+    let vram = read_vram(&mut renderer);
+    // X flip steps back through U, and V goes forward.
+    assert_eq!(vram[512..520], [40, 39, 38, 37, 36, 35, 34, 33]);
+    assert_eq!(vram[(1024 + 512)..(1024 + 520)], [56, 55, 54, 53, 52, 51, 50, 49]);
+    // Y flip steps back through V.
+    let column = (4..12).map(|y| vram[y * 1024 + 512]).collect::<Vec<_>>();
+    assert_eq!(column, [113, 97, 81, 65, 49, 33, 17, 1]);
+    assert_eq!(vram[4 * 1024 + 513], 114);
+}
+
+#[test]
+fn flipped_rectangle_clipped_by_drawing_area() {
+    let mut renderer = threaded_renderer(1);
+    numbered_texture(&mut renderer);
+    renderer.set_rect_flip(true, true);
+    // Two pixels are cut off the left and three off the top.
+    renderer.set_draw_area_top_left(514, 3);
+    renderer.draw_rectangle_tex(Color::default(), TexCoord { u: 7, v: 7 }, &direct_texture(), Coord { x: 512, y: 0 }, Size { width: 8, height: 8 }, false);
+    let vram = read_vram(&mut renderer);
+    assert_eq!(vram[3 * 1024 + 513], 0);
+    // The first drawn pixel is two texels back in U and three back in V: (5, 4).
+    assert_eq!(vram[3 * 1024 + 514], 70);
+    assert_eq!(vram[3 * 1024 + 519], 65);
+    assert_eq!(vram[7 * 1024 + 519], 1);
+}
+
+#[test]
+fn raw_texture_disabled_uses_command_color() {
+    let mut renderer = threaded_renderer(1);
+    numbered_texture(&mut renderer);
+    let red = Color::from_rgb24(0x0000FF);
+    renderer.draw_rectangle_tex(red, TexCoord { u: 0, v: 0 }, &direct_texture(), Coord { x: 512, y: 0 }, Size { width: 4, height: 1 }, false);
+    renderer.set_tex_disable(true);
+    renderer.draw_rectangle_tex(red, TexCoord { u: 0, v: 0 }, &direct_texture(), Coord { x: 512, y: 1 }, Size { width: 4, height: 1 }, false);
+    renderer.draw_triangle_tex(&[vertex(512, 2, 0x0000FF), vertex(528, 2, 0x0000FF), vertex(512, 18, 0x0000FF)], &direct_texture(), false);
+    let vram = read_vram(&mut renderer);
+    // The colour is ignored while textures are enabled...
+    assert_eq!(vram[512..516], [1, 2, 3, 4]);
+    // ...and used when they are disabled.
+    assert_eq!(vram[(1024 + 512)..(1024 + 516)], [0x1F; 4]);
+    assert_eq!(vram[2 * 1024 + 512], 0x1F);
+}
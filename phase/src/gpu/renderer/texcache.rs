@@ -0,0 +1,133 @@
+// Emulation of the GPU's texture and CLUT caches.
+
+use super::{TexMode, vram::Vram};
+
+const CACHE_LINES: usize = 256;
+
+/// Texture page a cache was filled from: base U, base V and colour depth.
+pub type TexPage = (usize, usize, TexMode);
+
+/// The 2KB texture cache, which holds 256 lines of 4 halfwords,
+/// and the CLUT cache, which holds the palette last used.
+///
+/// Texels are read through the cache, so VRAM writes to texture data
+/// that is already cached are not seen until the cache is flushed.
+#[derive(Clone)]
+pub struct TextureCache {
+    tags: [Option<usize>; CACHE_LINES],
+    lines: [[u16; 4]; CACHE_LINES],
+    page: Option<TexPage>,
+
+    /// VRAM address and size of the cached palette.
+    clut_tag: Option<(usize, usize)>,
+    clut: [u16; 256],
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self {
+            tags: [None; CACHE_LINES],
+            lines: [[0; 4]; CACHE_LINES],
+            page: None,
+
+            clut_tag: None,
+            clut: [0; 256],
+        }
+    }
+
+    /// Flush everything, as with GP0(01h).
+    pub fn clear(&mut self) {
+        self.tags = [None; CACHE_LINES];
+        self.clut_tag = None;
+    }
+
+    /// Call before drawing a textured primitive.
+    /// Texture data is flushed if the page changed.
+    pub fn set_page(&mut self, page: TexPage) {
+        if self.page != Some(page) {
+            self.tags = [None; CACHE_LINES];
+            self.page = Some(page);
+        }
+    }
+
+    /// Read a halfword of texture data at a VRAM address.
+    pub fn read_texture(&mut self, vram: &Vram, addr: usize) -> u16 {
+        let line = self.line_index(addr);
+        let tag = addr & !0x3;
+        if self.tags[line] != Some(tag) {
+            self.lines[line] = [0, 1, 2, 3].map(|n| vram.get(tag + n));
+            self.tags[line] = Some(tag);
+        }
+        self.lines[line][addr & 0x3]
+    }
+
+    /// Cache line for a VRAM address. 4 and 8-bit pages are cached in
+    /// blocks of 16x64 halfwords (64x64 or 32x64 texels), and 15-bit
+    /// pages in blocks of 32x32 halfwords.
+    fn line_index(&self, addr: usize) -> usize {
+        match self.page {
+            // Bits 2-4 of X and bits 0-4 of Y.
+            Some((_, _, TexMode::Direct)) => ((addr >> 2) & 0x7) | ((addr >> 7) & 0xF8),
+            // Bits 2-3 of X and bits 0-5 of Y.
+            _ => ((addr >> 2) & 0x3) | ((addr >> 8) & 0xFC),
+        }
+    }
+
+    /// Read a palette entry. The whole palette is loaded
+    /// when a different one is used.
+    pub fn read_clut(&mut self, vram: &Vram, palette_addr: usize, size: usize, index: usize) -> u16 {
+        if self.clut_tag != Some((palette_addr, size)) {
+            let line_addr = palette_addr & !0x3FF;
+            for (n, entry) in self.clut[..size].iter_mut().enumerate() {
+                *entry = vram.get(line_addr | ((palette_addr + n) & 0x3FF));
+            }
+            self.clut_tag = Some((palette_addr, size));
+        }
+        self.clut[index]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Fill a block through the cache, then change VRAM under it.
+    /// Returns how many halfwords of the block were still cached.
+    fn cached_halfwords(mode: TexMode, width: usize, height: usize) -> usize {
+        let vram = Vram::new(1024 * 512);
+        let mut cache = TextureCache::new();
+        cache.set_page((0, 0, mode));
+        let addrs = (0..height).flat_map(|y| (0..width).map(move |x| y * 1024 + x)).collect::<Vec<_>>();
+        for addr in &addrs {
+            cache.read_texture(&vram, *addr);
+            vram.set(*addr, 1);
+        }
+        addrs.iter().filter(|addr| cache.read_texture(&vram, **addr) == 0).count()
+    }
+
+    #[test]
+    fn palette_block_shape() {
+        assert_eq!(cached_halfwords(TexMode::Palette4, 16, 64), 16 * 64);
+        assert_eq!(cached_halfwords(TexMode::Palette8, 16, 64), 16 * 64);
+        // Wider blocks wrap around onto the same lines.
+        assert_eq!(cached_halfwords(TexMode::Palette4, 32, 32), 0);
+    }
+
+    #[test]
+    fn direct_block_shape() {
+        assert_eq!(cached_halfwords(TexMode::Direct, 32, 32), 32 * 32);
+        assert_eq!(cached_halfwords(TexMode::Direct, 16, 64), 0);
+    }
+
+    #[test]
+    fn new_page_flushes() {
+        let vram = Vram::new(1024 * 512);
+        let mut cache = TextureCache::new();
+        cache.set_page((0, 0, TexMode::Direct));
+        cache.read_texture(&vram, 0);
+        vram.set(0, 1);
+        assert_eq!(cache.read_texture(&vram, 0), 0);
+        cache.set_page((0, 0, TexMode::Palette4));
+        assert_eq!(cache.read_texture(&vram, 0), 1);
+    }
+}
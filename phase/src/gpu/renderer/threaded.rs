@@ -0,0 +1,342 @@
+// Splits software rendering between worker threads.
+
+use std::{
+    sync::Arc,
+    thread::JoinHandle
+};
+
+use crossbeam_channel::{
+    Sender, Receiver, unbounded
+};
+
+use super::{
+    RendererImpl, Coord, Size, Color, Vertex, TexInfo, TexMode, TexCoord, TransparencyMode,
+    software::SoftwareRenderer
+};
+
+use crate::{
+    Frame, gpu::{InterlaceState, DeinterlaceMode, OverscanMode}
+};
+
+/// Commands to collect before sending them to the workers.
+const BATCH_SIZE: usize = 64;
+
+type DrawCmd = Box<dyn Fn(&mut SoftwareRenderer) + Send + Sync>;
+type Batch = Arc<Vec<DrawCmd>>;
+
+/// Area of VRAM: left, top, right and bottom. Right and bottom are exclusive.
+type Area = (i16, i16, i16, i16);
+
+struct Worker {
+    batch_tx: Sender<Batch>,
+    thread: JoinHandle<()>,
+}
+
+/// Draws with a software renderer on each of a pool of threads.
+///
+/// VRAM is split into bands of lines, shared out between the workers.
+/// Every worker runs every drawing command in order, but only touches
+/// its own bands. So each pixel is written in the same order as with
+/// one thread, and blending and mask checks see the same values.
+///
+/// Before anything reads VRAM that queued commands may still be drawing
+/// to, the workers are waited on. This covers transfers, frame output
+/// and textures read from the drawing area.
+pub struct ThreadedRenderer {
+    /// Handles transfers and frame output.
+    /// Draws too, if there are no workers.
+    main: SoftwareRenderer,
+    workers: Vec<Worker>,
+    done_tx: Sender<()>,
+    done_rx: Receiver<()>,
+
+    batch: Vec<DrawCmd>,
+    /// Batches sent to workers and not yet finished, counted per worker.
+    in_flight: usize,
+    /// Area that queued commands could draw to.
+    dirty: Option<Area>,
+    /// Areas that queued commands read textures from.
+    reads: Vec<Area>,
+    /// The texture cache depends on the order of every texel read,
+    /// so drawing can't be split while it is used.
+    tex_cache: bool,
+}
+
+impl ThreadedRenderer {
+    pub fn new(main: SoftwareRenderer) -> Self {
+        let (done_tx, done_rx) = unbounded();
+        Self {
+            main,
+            workers: Vec::new(),
+            done_tx,
+            done_rx,
+
+            batch: Vec::new(),
+            in_flight: 0,
+            dirty: None,
+            reads: Vec::new(),
+            tex_cache: false,
+        }
+    }
+}
+
+impl RendererImpl for ThreadedRenderer {
+    fn get_frame(&mut self, frame: &mut Frame, interlace: InterlaceState, debug: bool) {
+        self.sync();
+        self.main.get_frame(frame, interlace, debug);
+    }
+    fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode) {
+        self.main.set_deinterlace_mode(mode);
+    }
+
+    fn write_vram_block(&mut self, data_in: &[u16], to: Coord, size: Size) {
+        self.sync();
+        self.main.write_vram_block(data_in, to, size);
+    }
+    fn read_vram_block(&mut self, data_out: &mut [u16], from: Coord, size: Size) {
+        self.sync();
+        self.main.read_vram_block(data_out, from, size);
+    }
+    fn copy_vram_block(&mut self, from: Coord, to: Coord, size: Size) {
+        self.sync();
+        self.main.copy_vram_block(from, to, size);
+    }
+
+    fn enable_display(&mut self, enable: bool) {
+        self.main.enable_display(enable);
+    }
+    fn set_display_offset(&mut self, offset: Coord) {
+        self.main.set_display_offset(offset);
+    }
+    fn set_display_range_x(&mut self, begin: u32, end: u32) {
+        self.main.set_display_range_x(begin, end);
+    }
+    fn set_display_range_y(&mut self, begin: u32, end: u32) {
+        self.main.set_display_range_y(begin, end);
+    }
+    fn set_display_resolution(&mut self, res: Size, interlace: bool, pal: bool) {
+        self.main.set_display_resolution(res, interlace, pal);
+    }
+    fn set_overscan_mode(&mut self, mode: OverscanMode) {
+        self.main.set_overscan_mode(mode);
+    }
+    fn set_color_depth(&mut self, rgb24: bool) {
+        self.main.set_color_depth(rgb24);
+    }
+
+    fn set_draw_mode(&mut self, trans_mode: TransparencyMode, dither: bool) {
+        self.set(move |r| r.set_draw_mode(trans_mode, dither));
+    }
+    fn set_texture_window(&mut self, mask_u: u8, mask_v: u8, offset_u: u8, offset_v: u8) {
+        self.set(move |r| r.set_texture_window(mask_u, mask_v, offset_u, offset_v));
+    }
+    fn set_draw_area_top_left(&mut self, left: i16, top: i16) {
+        self.set(move |r| r.set_draw_area_top_left(left, top));
+    }
+    fn set_draw_area_bottom_right(&mut self, right: i16, bottom: i16) {
+        self.set(move |r| r.set_draw_area_bottom_right(right, bottom));
+    }
+    fn set_draw_area_offset(&mut self, x: i16, y: i16) {
+        self.set(move |r| r.set_draw_area_offset(x, y));
+    }
+    fn set_mask_settings(&mut self, set_mask_bit: bool, check_mask_bit: bool) {
+        self.set(move |r| r.set_mask_settings(set_mask_bit, check_mask_bit));
+    }
+    fn set_texture_cache(&mut self, enable: bool) {
+        self.sync();
+        self.tex_cache = enable;
+        self.main.set_texture_cache(enable);
+    }
+    fn set_tex_disable(&mut self, disable: bool) {
+        self.set(move |r| r.set_tex_disable(disable));
+    }
+    fn set_rect_flip(&mut self, x_flip: bool, y_flip: bool) {
+        self.set(move |r| r.set_rect_flip(x_flip, y_flip));
+    }
+    fn clear_texture_cache(&mut self) {
+        self.main.clear_texture_cache();
+    }
+    fn set_resolution_scale(&mut self, scale: usize) {
+        self.sync();
+        self.main.set_resolution_scale(scale);
+        // Workers need to share the new shadow VRAM.
+        let threads = self.workers.len();
+        self.set_render_threads(threads);
+    }
+    fn set_render_threads(&mut self, threads: usize) {
+        self.sync();
+        for worker in std::mem::take(&mut self.workers) {
+            drop(worker.batch_tx);
+            let _ = worker.thread.join();
+        }
+        if threads > 1 {
+            self.workers = (0..threads).map(|band| self.spawn_worker(band, threads)).collect();
+        }
+    }
+    fn set_true_color(&mut self, enable: bool) {
+        self.sync();
+        self.main.set_true_color(enable);
+        // Workers need to share the new colour buffer.
+        let threads = self.workers.len();
+        self.set_render_threads(threads);
+    }
+
+    fn fill_rectangle(&mut self, color: Color, top_left: Coord, size: Size) {
+        self.sync();
+        self.main.fill_rectangle(color, top_left, size);
+    }
+    fn draw_triangle_flat(&mut self, vertices: &[Vertex], color: Color, transparent: bool) {
+        let vertices = vertices.to_vec();
+        self.draw(None, move |r| r.draw_triangle_flat(&vertices, color, transparent));
+    }
+    fn draw_triangle_shaded(&mut self, vertices: &[Vertex], transparent: bool) {
+        let vertices = vertices.to_vec();
+        self.draw(None, move |r| r.draw_triangle_shaded(&vertices, transparent));
+    }
+    fn draw_triangle_tex(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool) {
+        let (vertices, info) = (vertices.to_vec(), tex_info.clone());
+        self.draw(Some(tex_info), move |r| r.draw_triangle_tex(&vertices, &info, transparent));
+    }
+    fn draw_triangle_tex_blended(&mut self, vertices: &[Vertex], tex_info: &TexInfo, transparent: bool) {
+        let (vertices, info) = (vertices.to_vec(), tex_info.clone());
+        self.draw(Some(tex_info), move |r| r.draw_triangle_tex_blended(&vertices, &info, transparent));
+    }
+    fn draw_rectangle(&mut self, color: Color, top_left: Coord, size: Size, transparent: bool) {
+        self.draw(None, move |r| r.draw_rectangle(color, top_left, size, transparent));
+    }
+    fn draw_rectangle_tex(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool) {
+        let info = tex_info.clone();
+        self.draw(Some(tex_info), move |r| r.draw_rectangle_tex(color, tex_coord, &info, top_left, size, transparent));
+    }
+    fn draw_rectangle_tex_blended(&mut self, color: Color, tex_coord: TexCoord, tex_info: &TexInfo, top_left: Coord, size: Size, transparent: bool) {
+        let info = tex_info.clone();
+        self.draw(Some(tex_info), move |r| r.draw_rectangle_tex_blended(color, tex_coord, &info, top_left, size, transparent));
+    }
+    fn draw_line(&mut self, vertex_a: &Vertex, vertex_b: &Vertex, transparent: bool) {
+        let (vertex_a, vertex_b) = (vertex_a.clone(), vertex_b.clone());
+        self.draw(None, move |r| r.draw_line(&vertex_a, &vertex_b, transparent));
+    }
+}
+
+// Internal
+impl ThreadedRenderer {
+    fn spawn_worker(&self, band: usize, bands: usize) -> Worker {
+        let (batch_tx, batch_rx) = unbounded::<Batch>();
+        let done_tx = self.done_tx.clone();
+        let mut renderer = self.main.for_band(band, bands);
+        let thread = std::thread::spawn(move || {
+            for batch in batch_rx.iter() {
+                for cmd in batch.iter() {
+                    cmd(&mut renderer);
+                }
+                if done_tx.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+        Worker { batch_tx, thread }
+    }
+
+    /// Change a setting of the main renderer and the workers.
+    fn set(&mut self, cmd: impl Fn(&mut SoftwareRenderer) + Send + Sync + 'static) {
+        cmd(&mut self.main);
+        if !self.workers.is_empty() {
+            self.queue(Box::new(cmd));
+        }
+    }
+
+    /// Draw on the workers, or on this thread if drawing can't be split.
+    fn draw(&mut self, tex_info: Option<&TexInfo>, cmd: impl Fn(&mut SoftwareRenderer) + Send + Sync + 'static) {
+        if self.workers.is_empty() || self.tex_cache {
+            cmd(&mut self.main);
+            return;
+        }
+        let bounds = self.main.drawing_bounds();
+        let reads = tex_info.map_or_else(Vec::new, texture_areas);
+        // Texels must be read after queued commands draw them,
+        // and before later commands draw over them.
+        let read_hazard = self.dirty.is_some_and(|dirty| reads.iter().any(|area| overlaps(*area, dirty)));
+        let write_hazard = self.reads.iter().any(|area| overlaps(*area, bounds));
+        if read_hazard || write_hazard {
+            self.sync();
+        }
+        if reads.iter().any(|area| overlaps(*area, bounds)) {
+            // Reads texels it draws itself, which depends on the order of lines.
+            // Queued commands must finish first, as they may draw to the same pixels.
+            self.sync();
+            cmd(&mut self.main);
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => (dirty.0.min(bounds.0), dirty.1.min(bounds.1), dirty.2.max(bounds.2), dirty.3.max(bounds.3)),
+            None => bounds,
+        });
+        for area in reads {
+            if !self.reads.contains(&area) {
+                self.reads.push(area);
+            }
+        }
+        self.queue(Box::new(cmd));
+    }
+
+    fn queue(&mut self, cmd: DrawCmd) {
+        self.batch.push(cmd);
+        if self.batch.len() >= BATCH_SIZE {
+            self.submit();
+        }
+    }
+
+    /// Send the current batch to the workers.
+    fn submit(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = Arc::new(std::mem::take(&mut self.batch));
+        for worker in &self.workers {
+            worker.batch_tx.send(batch.clone()).expect("render worker stopped");
+            self.in_flight += 1;
+        }
+    }
+
+    /// Wait for the workers to finish everything queued.
+    fn sync(&mut self) {
+        self.submit();
+        while self.in_flight > 0 {
+            self.done_rx.recv().expect("render worker stopped");
+            self.in_flight -= 1;
+        }
+        self.dirty = None;
+        self.reads.clear();
+    }
+}
+
+impl Drop for ThreadedRenderer {
+    fn drop(&mut self) {
+        self.set_render_threads(0);
+    }
+}
+
+/// Areas of VRAM a textured primitive could read: its texture page, and palette.
+fn texture_areas(tex_info: &TexInfo) -> Vec<Area> {
+    let (u, v) = (tex_info.u_base, tex_info.v_base);
+    let (palette_x, palette_y) = (tex_info.palette_coord.x, tex_info.palette_coord.y);
+    match tex_info.tex_mode {
+        TexMode::Palette4 => vec![vram_area(u, v, 64, 256), vram_area(palette_x, palette_y, 16, 1)],
+        TexMode::Palette8 => vec![vram_area(u, v, 128, 256), vram_area(palette_x, palette_y, 256, 1)],
+        TexMode::Direct => vec![vram_area(u, v, 256, 256)],
+    }
+}
+
+/// Area of VRAM read from a block.
+/// Reads past the right edge continue on the next line, so those take up whole lines.
+fn vram_area(x: usize, y: usize, width: usize, height: usize) -> Area {
+    if x + width > 1024 {
+        (0, y as i16, 1024, (y + height + 1) as i16)
+    } else {
+        (x as i16, y as i16, (x + width) as i16, (y + height) as i16)
+    }
+}
+
+fn overlaps(a: Area, b: Area) -> bool {
+    a.0 < b.2 && b.0 < a.2 && a.1 < b.3 && b.1 < a.3
+}
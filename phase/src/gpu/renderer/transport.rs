@@ -0,0 +1,159 @@
+// Carries commands from the GPU to the render thread.
+
+use std::collections::VecDeque;
+
+use crossbeam_channel::{
+    Sender, Receiver, bounded, unbounded
+};
+
+use super::RendererCmd;
+
+/// Commands in a batch before it is sent.
+const BATCH_SIZE: usize = 256;
+/// Batches that can be waiting for the renderer.
+/// When the ring is full, sending blocks until the renderer catches up.
+const RING_SIZE: usize = 8;
+
+type Batch = VecDeque<RendererCmd>;
+
+/// Make the two ends of a command ring.
+pub fn command_ring() -> (CommandSender, CommandReceiver) {
+    let (batch_tx, batch_rx) = bounded(RING_SIZE);
+    let (free_tx, free_rx) = unbounded();
+    let sender = CommandSender {
+        batch: Batch::with_capacity(BATCH_SIZE),
+        batch_tx,
+        free_rx,
+    };
+    let receiver = CommandReceiver {
+        batch: Batch::new(),
+        batch_rx,
+        free_tx,
+    };
+    (sender, receiver)
+}
+
+/// GPU end of the ring.
+///
+/// Commands are collected into batches, so the render thread is only
+/// woken once per batch. A batch is sent when it is full, or when the GPU
+/// needs a reply: at vblank, and when reading VRAM back.
+pub struct CommandSender {
+    batch: Batch,
+    batch_tx: Sender<Batch>,
+    /// Batches the renderer has finished with, to be reused.
+    free_rx: Receiver<Batch>,
+}
+
+impl CommandSender {
+    /// Add a command to the current batch.
+    /// Once the batch is full it is sent, which blocks while the ring is full.
+    pub fn send(&mut self, command: RendererCmd) {
+        self.batch.push_back(command);
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    /// Send the current batch.
+    /// Blocks if the ring is full, until the renderer catches up.
+    pub fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let next = self.free_rx.try_recv().unwrap_or_else(|_| Batch::with_capacity(BATCH_SIZE));
+        let batch = std::mem::replace(&mut self.batch, next);
+        let _ = self.batch_tx.send(batch);
+    }
+}
+
+/// Render thread end of the ring.
+pub struct CommandReceiver {
+    batch: Batch,
+    batch_rx: Receiver<Batch>,
+    free_tx: Sender<Batch>,
+}
+
+impl CommandReceiver {
+    /// Take the next command, waiting for a batch if needed.
+    /// Returns None once the GPU is gone.
+    pub fn recv(&mut self) -> Option<RendererCmd> {
+        if self.batch.is_empty() {
+            let next = self.batch_rx.recv().ok()?;
+            let done = std::mem::replace(&mut self.batch, next);
+            let _ = self.free_tx.send(done);
+        }
+        self.batch.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn data(command: Option<RendererCmd>) -> Option<u32> {
+        match command {
+            Some(RendererCmd::GP0Data(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn batches_are_sent_when_full() {
+        let (mut sender, mut receiver) = command_ring();
+        for n in 0..(BATCH_SIZE as u32 - 1) {
+            sender.send(RendererCmd::GP0Data(n));
+        }
+        assert!(receiver.batch_rx.is_empty());
+        sender.send(RendererCmd::GP0Data(BATCH_SIZE as u32 - 1));
+        assert_eq!(receiver.batch_rx.len(), 1);
+        for n in 0..(BATCH_SIZE as u32) {
+            assert_eq!(data(receiver.recv()), Some(n));
+        }
+    }
+
+    #[test]
+    fn flush_sends_partial_batch() {
+        let (mut sender, mut receiver) = command_ring();
+        sender.send(RendererCmd::GP0Data(1));
+        sender.send(RendererCmd::GP0Data(2));
+        assert!(receiver.batch_rx.is_empty());
+        // The GPU flushes before waiting for a reply.
+        sender.flush();
+        assert_eq!(receiver.batch_rx.len(), 1);
+        // Nothing is sent when there is nothing to send.
+        sender.flush();
+        assert_eq!(receiver.batch_rx.len(), 1);
+        assert_eq!(data(receiver.recv()), Some(1));
+        assert_eq!(data(receiver.recv()), Some(2));
+    }
+
+    #[test]
+    fn freed_batches_are_reused() {
+        let (mut sender, mut receiver) = command_ring();
+        sender.send(RendererCmd::GP0Data(1));
+        sender.flush();
+        receiver.recv();
+        // The receiver's starting batch is handed back.
+        assert_eq!(sender.free_rx.len(), 1);
+        sender.send(RendererCmd::GP0Data(2));
+        sender.flush();
+        assert!(sender.free_rx.is_empty());
+        // Taking the second batch frees the first.
+        receiver.recv();
+        assert_eq!(sender.free_rx.len(), 1);
+        let freed = sender.free_rx.try_recv().unwrap();
+        assert!(freed.is_empty());
+        assert!(freed.capacity() >= BATCH_SIZE);
+    }
+
+    #[test]
+    fn receiver_stops_when_sender_is_gone() {
+        let (mut sender, mut receiver) = command_ring();
+        sender.send(RendererCmd::GP0Data(1));
+        sender.flush();
+        drop(sender);
+        assert_eq!(data(receiver.recv()), Some(1));
+        assert!(receiver.recv().is_none());
+    }
+}
@@ -0,0 +1,96 @@
+// Full precision colours of drawn pixels, kept alongside VRAM.
+
+use std::sync::{
+    Arc, atomic::{AtomicU32, Ordering}
+};
+
+use super::Color;
+
+/// Set for entries that hold the colour of the pixel in VRAM.
+const VALID: u32 = 1 << 24;
+
+/// 24-bit colour of each pixel of the VRAM that frames are output from.
+/// Clones share the same memory.
+///
+/// Drawing sets entries, and copies within VRAM move them along with
+/// the pixels. Anything else that writes to VRAM, such as a transfer
+/// from the CPU, invalidates them, so the 15-bit colour is used instead.
+#[derive(Clone)]
+pub struct TrueColorBuffer(Arc<[AtomicU32]>);
+
+impl TrueColorBuffer {
+    /// Make a buffer with every entry invalid.
+    pub fn new(size: usize) -> Self {
+        Self((0..size).map(|_| AtomicU32::new(0)).collect())
+    }
+
+    /// Colour of a pixel, given what VRAM holds there.
+    #[inline(always)]
+    pub fn get(&self, addr: usize, pixel: u16) -> Color {
+        let entry = self.0[addr].load(Ordering::Relaxed);
+        if entry & VALID != 0 {
+            Color { mask: pixel & 0x8000, ..Color::from_rgb24(entry) }
+        } else {
+            Color::from_rgb15(pixel)
+        }
+    }
+
+    #[inline(always)]
+    pub fn set(&self, addr: usize, color: &Color) {
+        let rgb = (color.r as u32) | ((color.g as u32) << 8) | ((color.b as u32) << 16);
+        self.0[addr].store(rgb | VALID, Ordering::Relaxed);
+    }
+
+    /// Use the 15-bit colour in VRAM for a pixel.
+    #[inline(always)]
+    pub fn invalidate(&self, addr: usize) {
+        self.0[addr].store(0, Ordering::Relaxed);
+    }
+
+    /// Copy an entry, when VRAM is copied.
+    #[inline(always)]
+    pub fn copy(&self, from: usize, to: usize) {
+        let entry = self.0[from].load(Ordering::Relaxed);
+        self.0[to].store(entry, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let buffer = TrueColorBuffer::new(4);
+        let color = Color::from_rgb24(0x0F8137);
+        buffer.set(1, &color);
+        let pixel = color.to_rgb15() | 0x8000;
+        let stored = buffer.get(1, pixel);
+        assert_eq!((stored.r, stored.g, stored.b), (0x37, 0x81, 0x0F));
+        // The mask bit only lives in VRAM.
+        assert_eq!(stored.mask, 0x8000);
+        assert_eq!(buffer.get(1, color.to_rgb15()).mask, 0);
+    }
+
+    #[test]
+    fn falls_back_to_vram() {
+        let buffer = TrueColorBuffer::new(4);
+        let pixel = 0x7C1F;
+        let color = buffer.get(0, pixel);
+        assert_eq!((color.r, color.g, color.b), (0xFF, 0, 0xFF));
+        buffer.set(0, &Color::from_rgb24(0x0F0F0F));
+        buffer.invalidate(0);
+        assert_eq!(buffer.get(0, pixel).to_rgb15(), pixel);
+    }
+
+    #[test]
+    fn copy_entry() {
+        let buffer = TrueColorBuffer::new(4);
+        buffer.set(0, &Color::from_rgb24(0x0F0F0F));
+        buffer.copy(0, 3);
+        assert_eq!(buffer.get(3, 0x0421).r, 0x0F);
+        // Copying an invalid entry invalidates the destination.
+        buffer.copy(1, 3);
+        assert_eq!(buffer.get(3, 0x0421).r, 0x08);
+    }
+}
@@ -0,0 +1,33 @@
+// VRAM that can be shared by rasterizer threads.
+
+use std::sync::{
+    Arc, atomic::{AtomicU16, Ordering}
+};
+
+/// Halfwords of VRAM. Clones share the same memory.
+///
+/// Threads drawing at the same time must write to different pixels.
+/// Accesses are relaxed: threads synchronise by waiting on each other
+/// before one reads what another drew.
+#[derive(Clone)]
+pub struct Vram(Arc<[AtomicU16]>);
+
+impl Vram {
+    pub fn new(size: usize) -> Self {
+        Self((0..size).map(|_| AtomicU16::new(0)).collect())
+    }
+
+    #[inline(always)]
+    pub fn get(&self, addr: usize) -> u16 {
+        self.0[addr].load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn set(&self, addr: usize, data: u16) {
+        self.0[addr].store(data, Ordering::Relaxed);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.0.iter().map(|halfword| halfword.load(Ordering::Relaxed))
+    }
+}
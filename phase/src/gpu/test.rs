@@ -0,0 +1,59 @@
+use super::*;
+use crate::event::event_channel;
+
+fn gpu() -> GPU {
+    let (events, _) = event_channel();
+    GPU::new(Arc::new(Mutex::new(Frame::new())), events)
+}
+
+fn gp0(gpu: &mut GPU, words: &[u32]) {
+    for word in words {
+        gpu.write_word(0x1F80_1810, *word);
+    }
+}
+
+#[test]
+fn readback_flushes_commands() {
+    let mut gpu = gpu();
+    // Write two pixels, then read them back.
+    // The write is far short of a full batch, so this hangs unless the read flushes it.
+    gp0(&mut gpu, &[0xA000_0000, 0x0000_0000, 0x0001_0002, 0x7FFF_001F]);
+    gp0(&mut gpu, &[0xC000_0000, 0x0000_0000, 0x0001_0002]);
+    assert_eq!(gpu.read_word(0x1F80_1810), 0x7FFF_001F);
+}
+
+fn tex_disabled(gpu: &mut GPU) -> bool {
+    test_bit!(gpu.read_word(0x1F80_1814), 15)
+}
+
+#[test]
+fn tex_disable_needs_gp1_09() {
+    let mut gpu = gpu();
+    // GP0(E1h) bit 11 alone does nothing.
+    gp0(&mut gpu, &[0xE100_0800]);
+    assert!(!tex_disabled(&mut gpu));
+    gpu.write_word(0x1F80_1814, 0x0900_0001);
+    assert!(tex_disabled(&mut gpu));
+    gp0(&mut gpu, &[0xE100_0000]);
+    assert!(!tex_disabled(&mut gpu));
+    gp0(&mut gpu, &[0xE100_0800]);
+    assert!(tex_disabled(&mut gpu));
+    // Clearing GP1(09h) turns it off again.
+    gpu.write_word(0x1F80_1814, 0x0900_0000);
+    assert!(!tex_disabled(&mut gpu));
+}
+
+#[test]
+fn raw_texture_disabled_uses_command_color() {
+    let mut gpu = gpu();
+    gp0(&mut gpu, &[0xE300_0000, 0xE400_0000 | 1023 | (511 << 10)]);
+    gpu.write_word(0x1F80_1814, 0x0900_0001);
+    gp0(&mut gpu, &[0xE100_0800]);
+    // Raw textured triangle and rectangle, in red.
+    gp0(&mut gpu, &[0x2500_00FF, 0x0000_0000, 0, 0x0000_0010, 0, 0x0010_0000, 0]);
+    gp0(&mut gpu, &[0x6500_00FF, 0x0000_0020, 0, 0x0001_0002]);
+    gp0(&mut gpu, &[0xC000_0000, 0x0000_0000, 0x0001_0002]);
+    assert_eq!(gpu.read_word(0x1F80_1810), 0x001F_001F);
+    gp0(&mut gpu, &[0xC000_0000, 0x0000_0020, 0x0001_0002]);
+    assert_eq!(gpu.read_word(0x1F80_1810), 0x001F_001F);
+}
@@ -0,0 +1,229 @@
+// Estimates of how long the GPU takes to run drawing commands.
+//
+// These are approximations: the real GPU's timing depends on VRAM
+// page breaks and caching, which are not modelled.
+
+use super::renderer::GP0Command;
+
+/// Setup time for a polygon.
+const POLYGON_CYCLES: usize = 64;
+/// Extra setup time for a shaded polygon.
+const SHADED_CYCLES: usize = 32;
+/// Extra setup time for a textured polygon.
+const TEXTURED_CYCLES: usize = 64;
+/// Setup time for a rectangle or line.
+const PRIMITIVE_CYCLES: usize = 16;
+/// Time to start each line of a polygon or rectangle.
+const LINE_CYCLES: usize = 2;
+
+/// Area that drawing is clipped to, and the drawing offset.
+pub struct DrawingArea {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+    offset_x: i32,
+    offset_y: i32,
+}
+
+impl DrawingArea {
+    /// Make from the parameters of GP0(E3h), GP0(E4h) and GP0(E5h).
+    pub fn new(top_left: u32, bottom_right: u32, offset: u32) -> Self {
+        Self {
+            left: (top_left & 0x3FF) as i32,
+            top: ((top_left >> 10) & 0x1FF) as i32,
+            right: (bottom_right & 0x3FF) as i32 + 1,
+            bottom: ((bottom_right >> 10) & 0x1FF) as i32 + 1,
+            offset_x: sign_extend_11(offset),
+            offset_y: sign_extend_11(offset >> 11),
+        }
+    }
+
+    /// Clip a box to the drawing area, and find its size.
+    fn clip(&self, left: i32, top: i32, right: i32, bottom: i32) -> (usize, usize) {
+        let width = right.min(self.right) - left.max(self.left);
+        let height = bottom.min(self.bottom) - top.max(self.top);
+        (width.max(0) as usize, height.max(0) as usize)
+    }
+}
+
+/// Find the GPU cycles a GP0 command keeps the GPU busy for.
+/// Mask checking reads each pixel before writing, like blending.
+pub fn command_cycles(command: &GP0Command, area: &DrawingArea, check_mask: bool) -> usize {
+    use GP0Command::*;
+    let pixel = |textured: bool, transparent: bool| 1 + textured as usize + (transparent || check_mask) as usize;
+    match command {
+        FillRectangle(params) => {
+            let (width, height) = size(params[2]);
+            46 + height * (width / 8 + 9)
+        },
+
+        DrawTri{params, transparent} => polygon(area, params, &[1, 2, 3], false, false, pixel(false, *transparent)),
+        DrawTexTri{params, transparent} => polygon(area, params, &[1, 3, 5], false, true, pixel(true, *transparent)),
+        DrawTexBlendTri{params, transparent} => polygon(area, params, &[1, 3, 5], false, true, pixel(true, *transparent)),
+        DrawShadedTri{params, transparent} => polygon(area, params, &[1, 3, 5], true, false, pixel(false, *transparent)),
+        DrawTexShadedTri{params, transparent, use_shading} => polygon(area, params, &[1, 4, 7], *use_shading, true, pixel(true, *transparent)),
+
+        DrawQuad{params, transparent} => polygon(area, params, &[1, 2, 3, 4], false, false, pixel(false, *transparent)),
+        DrawTexQuad{params, transparent} => polygon(area, params, &[1, 3, 5, 7], false, true, pixel(true, *transparent)),
+        DrawTexBlendQuad{params, transparent} => polygon(area, params, &[1, 3, 5, 7], false, true, pixel(true, *transparent)),
+        DrawShadedQuad{params, transparent} => polygon(area, params, &[1, 3, 5, 7], true, false, pixel(false, *transparent)),
+        DrawTexShadedQuad{params, transparent, use_shading} => polygon(area, params, &[1, 4, 7, 10], *use_shading, true, pixel(true, *transparent)),
+
+        DrawLine{params, transparent} => line(params[1], params[2], pixel(false, *transparent)),
+        DrawShadedLine{params, transparent} => line(params[1], params[3], pixel(false, *transparent)),
+
+        DrawRect{params, transparent} => rectangle(area, params[1], rect_size(params[2]), pixel(false, *transparent)),
+        DrawTexRect{params, transparent} => rectangle(area, params[1], rect_size(params[3]), pixel(true, *transparent)),
+        DrawTexBlendedRect{params, transparent} => rectangle(area, params[1], rect_size(params[3]), pixel(true, *transparent)),
+        DrawFixedRect{params, size, transparent} => rectangle(area, params[1], (*size as usize, *size as usize), pixel(false, *transparent)),
+        DrawTexFixedRect{params, size, transparent} => rectangle(area, params[1], (*size as usize, *size as usize), pixel(true, *transparent)),
+        DrawTexBlendedFixedRect{params, size, transparent} => rectangle(area, params[1], (*size as usize, *size as usize), pixel(true, *transparent)),
+
+        // Each pixel is read, then written.
+        BlitVRAMtoVRAM{params} => {
+            let (width, height) = size(params[2]);
+            width * height * 2
+        },
+        // Transfers are paced by whoever is sending or receiving the data.
+        _ => 0,
+    }
+}
+
+/// Sign-extend the low 11 bits, as used for coordinates.
+fn sign_extend_11(value: u32) -> i32 {
+    ((value as i32) << 21) >> 21
+}
+
+fn vertex(data: u32) -> (i32, i32) {
+    (sign_extend_11(data), sign_extend_11(data >> 16))
+}
+
+/// Size of a rectangle or transfer, with the GPU's wrapping.
+fn size(data: u32) -> (usize, usize) {
+    let width = ((data & 0xFFFF).wrapping_sub(1) & 0x3FF) + 1;
+    let height = ((data >> 16).wrapping_sub(1) & 0x1FF) + 1;
+    (width as usize, height as usize)
+}
+
+fn rect_size(data: u32) -> (usize, usize) {
+    ((data & 0x3FF) as usize, ((data >> 16) & 0x1FF) as usize)
+}
+
+/// Quads are drawn as two triangles.
+fn polygon(area: &DrawingArea, params: &[u32], vertex_idx: &[usize], shaded: bool, textured: bool, pixel_cycles: usize) -> usize {
+    let vertices = vertex_idx.iter()
+        .map(|n| vertex(params[*n]))
+        .map(|(x, y)| (x + area.offset_x, y + area.offset_y))
+        .collect::<Vec<_>>();
+    let setup = POLYGON_CYCLES + if shaded {SHADED_CYCLES} else {0} + if textured {TEXTURED_CYCLES} else {0};
+    vertices.windows(3)
+        .map(|tri| setup + triangle(area, [tri[0], tri[1], tri[2]], pixel_cycles))
+        .sum()
+}
+
+fn triangle(area: &DrawingArea, vertices: [(i32, i32); 3], pixel_cycles: usize) -> usize {
+    let [a, b, c] = vertices;
+    let (left, right) = (a.0.min(b.0).min(c.0), a.0.max(b.0).max(c.0));
+    let (top, bottom) = (a.1.min(b.1).min(c.1), a.1.max(b.1).max(c.1));
+    if right - left > 1023 || bottom - top > 511 {
+        // Dropped without drawing.
+        return 0;
+    }
+    let (width, height) = area.clip(left, top, right, bottom);
+    let area_x2 = ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)).unsigned_abs() as usize;
+    // Assume the triangle covers the same share of the clipped box.
+    let box_size = ((right - left) * (bottom - top)).max(1) as usize;
+    let pixels = area_x2 / 2 * (width * height) / box_size;
+    height * LINE_CYCLES + pixels * pixel_cycles
+}
+
+fn rectangle(area: &DrawingArea, top_left: u32, size: (usize, usize), pixel_cycles: usize) -> usize {
+    let (x, y) = vertex(top_left);
+    let (x, y) = (x + area.offset_x, y + area.offset_y);
+    let (width, height) = area.clip(x, y, x + size.0 as i32, y + size.1 as i32);
+    PRIMITIVE_CYCLES + height * LINE_CYCLES + width * height * pixel_cycles
+}
+
+fn line(start: u32, end: u32, pixel_cycles: usize) -> usize {
+    let (a, b) = (vertex(start), vertex(end));
+    let length = (b.0 - a.0).abs().max((b.1 - a.1).abs()) as usize + 1;
+    PRIMITIVE_CYCLES + length * pixel_cycles
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GP0Command::*;
+
+    fn xy(x: i16, y: i16) -> u32 {
+        (x as u16 as u32) | ((y as u16 as u32) << 16)
+    }
+
+    fn area(right: u32, bottom: u32) -> DrawingArea {
+        DrawingArea::new(0, right | (bottom << 10), 0)
+    }
+
+    fn full_area() -> DrawingArea {
+        area(1023, 511)
+    }
+
+    #[test]
+    fn clipped_rectangle() {
+        let rect = DrawRect{params: [0, xy(0, 0), xy(64, 64)], transparent: false};
+        assert_eq!(command_cycles(&rect, &full_area(), false), PRIMITIVE_CYCLES + 64 * LINE_CYCLES + 64 * 64);
+        assert_eq!(command_cycles(&rect, &area(31, 31), false), PRIMITIVE_CYCLES + 32 * LINE_CYCLES + 32 * 32);
+        // Entirely outside the drawing area.
+        let outside = DrawRect{params: [0, xy(100, 100), xy(64, 64)], transparent: false};
+        assert_eq!(command_cycles(&outside, &area(31, 31), false), PRIMITIVE_CYCLES);
+    }
+
+    #[test]
+    fn drawing_offset() {
+        let rect = DrawRect{params: [0, xy(0, 0), xy(64, 64)], transparent: false};
+        // Offset by (-32, -32).
+        let offset = DrawingArea::new(0, 1023 | (511 << 10), 0x7E0 | (0x7E0 << 11));
+        assert_eq!(command_cycles(&rect, &offset, false), PRIMITIVE_CYCLES + 32 * LINE_CYCLES + 32 * 32);
+    }
+
+    #[test]
+    fn textured_costs_more() {
+        let flat = DrawRect{params: [0, xy(0, 0), xy(16, 16)], transparent: false};
+        let textured = DrawTexRect{params: [0, xy(0, 0), 0, xy(16, 16)], transparent: false};
+        let flat_cycles = command_cycles(&flat, &full_area(), false);
+        assert_eq!(command_cycles(&textured, &full_area(), false), flat_cycles + 16 * 16);
+    }
+
+    #[test]
+    fn transparent_costs_more() {
+        let opaque = DrawTri{params: [0, xy(0, 0), xy(32, 0), xy(0, 32)], transparent: false};
+        let transparent = DrawTri{params: [0, xy(0, 0), xy(32, 0), xy(0, 32)], transparent: true};
+        let opaque_cycles = command_cycles(&opaque, &full_area(), false);
+        let pixels = 32 * 32 / 2;
+        assert_eq!(opaque_cycles, POLYGON_CYCLES + 32 * LINE_CYCLES + pixels);
+        assert_eq!(command_cycles(&transparent, &full_area(), false), opaque_cycles + pixels);
+        // Mask checking reads the pixels too.
+        assert_eq!(command_cycles(&opaque, &full_area(), true), opaque_cycles + pixels);
+    }
+
+    #[test]
+    fn quad_is_two_triangles() {
+        let tri = DrawTri{params: [0, xy(0, 0), xy(32, 0), xy(0, 32)], transparent: false};
+        let quad = DrawQuad{params: [0, xy(0, 0), xy(32, 0), xy(0, 32), xy(32, 32)], transparent: false};
+        assert_eq!(command_cycles(&quad, &full_area(), false), command_cycles(&tri, &full_area(), false) * 2);
+    }
+
+    #[test]
+    fn oversized_polygon_is_dropped() {
+        let tri = DrawTri{params: [0, xy(0, 0), xy(1024, 0), xy(0, 32)], transparent: false};
+        assert_eq!(command_cycles(&tri, &full_area(), false), POLYGON_CYCLES);
+    }
+
+    #[test]
+    fn fill_and_copy() {
+        let fill = FillRectangle([0, 0, xy(64, 32)]);
+        assert_eq!(command_cycles(&fill, &full_area(), false), 46 + 32 * (64 / 8 + 9));
+        let copy = BlitVRAMtoVRAM{params: [0, xy(64, 0), xy(16, 16)]};
+        assert_eq!(command_cycles(&copy, &full_area(), false), 16 * 16 * 2);
+    }
+}
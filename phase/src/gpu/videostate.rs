@@ -1,7 +1,7 @@
 // This file manages timing of the video system.
 
 use crate::{interrupt::Interrupt, utils::bits::*};
-use super::InterlaceState;
+use super::{InterlaceState, OverscanMode, renderer::{DisplayArea, Size}};
 
 /// Returned when clocking the GPU.
 /// 
@@ -72,6 +72,24 @@ impl GPUClockRes {
     }
 }
 
+/// A point on the screen, in terms of the video beam.
+#[derive(Clone, Copy, Debug)]
+pub struct BeamPosition {
+    /// GPU cycles after h-sync.
+    pub h_cycles: f64,
+    /// Lines after v-sync.
+    pub line: usize,
+}
+
+impl BeamPosition {
+    /// Counters reported by the GunCon: 8MHz clock ticks since h-sync,
+    /// and lines since v-sync.
+    pub fn guncon_counters(&self) -> (u16, u16) {
+        let h_ticks = self.h_cycles * GUNCON_CLOCK / GPU_CLOCK;
+        (h_ticks as u16, self.line as u16)
+    }
+}
+
 /// Tracks cycle count and current state of the LCD drawing process.
 pub struct StateMachine {
     state:          VideoState,
@@ -81,12 +99,25 @@ pub struct StateMachine {
 
     // Timing constants:
     v_res:          usize,
+    draw_lines:     usize,
     h_res:          usize,
     dots_per_cycle: f64,
     h_draw_cycles:  f64,
     h_total_cycles: f64,
 
     interlace:      InterlaceState,
+
+    // Display settings, for finding where the beam is:
+    pal:            bool,
+    display_height: usize,
+    display_range_x: (u32, u32),
+    display_range_y: (u32, u32),
+    overscan_mode:  OverscanMode,
+    /// Worked out from the settings above whenever they change.
+    display_area:   DisplayArea,
+
+    /// Raise the lightpen interrupt when the beam reaches this point.
+    lightpen_target: Option<BeamPosition>,
 }
 
 impl StateMachine {
@@ -98,32 +129,48 @@ impl StateMachine {
             v_count:        0,
 
             v_res:          0,
+            draw_lines:     0,
             h_res:          0,
             dots_per_cycle: 0.0,
             h_draw_cycles:  0.0,
             h_total_cycles: 0.0,
 
             interlace:      InterlaceState::Off,
+
+            pal:            false,
+            display_height: 240,
+            display_range_x: (0x200, 0xC00),
+            display_range_y: (0x10, 0x100),
+            overscan_mode:  OverscanMode::default(),
+            display_area:   DisplayArea::new(OverscanMode::default(), Size::new(256, 240), false, (0x200, 0xC00), (0x10, 0x100)),
+
+            lightpen_target: None,
         };
-        machine.set_h_res_ntsc(256);
+        machine.set_display_mode(256, 240, false);
         machine
     }
 
-    /// Set new horizontal resolution using NTSC timings.
-    pub fn set_h_res_ntsc(&mut self, h_res: usize) {
-        self.v_res = ntsc::SCANLINES;
-        self.h_total_cycles = ntsc::H_CYCLES as f64;
+    /// Set new resolution, and NTSC or PAL timings.
+    pub fn set_display_mode(&mut self, h_res: usize, v_res: usize, pal: bool) {
+        self.pal = pal;
+        self.display_height = v_res;
+        (self.v_res, self.draw_lines, self.h_total_cycles) = if pal {
+            (pal::SCANLINES, pal::DRAW_LINES, pal::H_CYCLES as f64)
+        } else {
+            (ntsc::SCANLINES, ntsc::DRAW_LINES, ntsc::H_CYCLES as f64)
+        };
         self.h_res = h_res;
         let cycles_per_dot = match h_res {
-            256 => ntsc::DOT_COUNT_256,
-            320 => ntsc::DOT_COUNT_320,
-            368 => ntsc::DOT_COUNT_368,
-            512 => ntsc::DOT_COUNT_512,
-            640 => ntsc::DOT_COUNT_640,
+            256 => DOT_COUNT_256,
+            320 => DOT_COUNT_320,
+            368 => DOT_COUNT_368,
+            512 => DOT_COUNT_512,
+            640 => DOT_COUNT_640,
             _ => panic!("invalid horizontal resolution specified!"),
         } as f64;
         self.dots_per_cycle = 1.0 / cycles_per_dot;
         self.h_draw_cycles = cycles_per_dot * (self.h_res as f64);
+        self.update_display_area();
     }
 
     /// Set or unset interlace mode.
@@ -137,8 +184,56 @@ impl StateMachine {
         }
     }
 
+    /// Set the display area, as set by GP1(06h).
+    pub fn set_display_range_x(&mut self, begin: u32, end: u32) {
+        self.display_range_x = (begin, end);
+        self.update_display_area();
+    }
+
+    /// Set the display area, as set by GP1(07h).
+    pub fn set_display_range_y(&mut self, begin: u32, end: u32) {
+        self.display_range_y = (begin, end);
+        self.update_display_area();
+    }
+
+    /// Set how much of the picture is in each frame.
+    pub fn set_overscan_mode(&mut self, mode: OverscanMode) {
+        self.overscan_mode = mode;
+        self.update_display_area();
+    }
+
+    /// Find the beam position for a point on the screen.
+    /// 
+    /// X and Y should be between 0 and 1, covering the frame.
+    /// Returns None if the point is outside the displayed area.
+    pub fn beam_position(&self, x: f32, y: f32) -> Option<BeamPosition> {
+        self.display_area.beam_position(x, y).map(|(h_cycles, line)| BeamPosition { h_cycles, line })
+    }
+
+    pub fn set_lightpen_target(&mut self, target: Option<BeamPosition>) {
+        self.lightpen_target = target;
+    }
+
     /// Advance the state machine.
     pub fn clock(&mut self, cycles: usize) -> GPUClockRes {
+        let prev_line = self.v_count;
+        let prev_h_cycles = self.h_cycle_count;
+        let mut res = self.advance(cycles);
+        // Lines are counted from the start of the display here.
+        let target = self.lightpen_target.and_then(|target| Some((
+            target.h_cycles - self.display_range_x.0 as f64,
+            target.line.checked_sub(self.display_range_y.0 as usize)?
+        )));
+        if let Some((target_h_cycles, target_line)) = target {
+            let passed_target = self.v_count != prev_line || self.h_cycle_count >= target_h_cycles;
+            if prev_line == target_line && prev_h_cycles < target_h_cycles && passed_target {
+                res.irq.insert(Interrupt::ControllerLightpen);
+            }
+        }
+        res
+    }
+
+    fn advance(&mut self, cycles: usize) -> GPUClockRes {
         use VideoState::*;
         let gpu_cycles = (cycles as f64) * (11.0 / 7.0);
         self.h_cycle_count += gpu_cycles;
@@ -168,7 +263,7 @@ impl StateMachine {
                     self.h_cycle_count -= self.h_total_cycles;
                     self.v_count += 1;
                     self.h_dot = self.h_cycle_count * self.dots_per_cycle;
-                    if self.v_count < DRAWLINES {
+                    if self.v_count < self.draw_lines {
                         self.state = Drawing;
                         GPUClockRes::dots(dots)
                     } else {
@@ -221,6 +316,11 @@ impl StateMachine {
     fn toggle_interlace(&mut self) {
         self.interlace = self.interlace.toggle();
     }
+
+    fn update_display_area(&mut self) {
+        let resolution = Size::new(self.h_res as u16, self.display_height as u16);
+        self.display_area = DisplayArea::new(self.overscan_mode, resolution, self.pal, self.display_range_x, self.display_range_y);
+    }
 }
 
 /// State of the screen drawing process.
@@ -231,22 +331,30 @@ enum VideoState {
     VHBlank,    // Horizontal blanking period during v-blank.
 }
 
-/// Lines to draw per frame.
-const DRAWLINES: usize = 240;
+
+/// GPU clock rate, in Hz.
+const GPU_CLOCK: f64 = 33_868_800.0 * 11.0 / 7.0;
+/// Rate of the GunCon's horizontal counter, in Hz.
+const GUNCON_CLOCK: f64 = 8_000_000.0;
+
+/// GPU cycles per dot, for each horizontal resolution.
+const DOT_COUNT_256: usize = 10;
+const DOT_COUNT_320: usize = 8;
+const DOT_COUNT_368: usize = 7;
+const DOT_COUNT_512: usize = 5;
+const DOT_COUNT_640: usize = 4;
 
 /// NTSC timings
 mod ntsc {
     pub const SCANLINES: usize = 263;
+    /// Lines to draw per frame.
+    pub const DRAW_LINES: usize = 240;
     pub const H_CYCLES: usize = 3413;
-
-    pub const DOT_COUNT_256: usize = 10;
-    pub const DOT_COUNT_320: usize = 8;
-    pub const DOT_COUNT_368: usize = 7;
-    pub const DOT_COUNT_512: usize = 5;
-    pub const DOT_COUNT_640: usize = 4;
 }
 
-/// PAL timings (TODO)
+/// PAL timings
 mod pal {
-
+    pub const SCANLINES: usize = 314;
+    pub const DRAW_LINES: usize = 288;
+    pub const H_CYCLES: usize = 3406;
 }
\ No newline at end of file
@@ -49,7 +49,6 @@ impl InterruptControl {
     }
 
     fn set_mask(&mut self, data: u32) {
-        //println!("set i mask: {:X}", data);
         self.mask = Interrupt::from_bits_truncate(data);
     }
 }
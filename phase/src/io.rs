@@ -1,5 +1,6 @@
 use std::path::PathBuf;
-use super::{Frame, Port};
+use super::{Frame, Port, Slot};
+use crate::event::EventSender;
 
 use crossbeam_channel::{
     Sender, Receiver, bounded
@@ -25,7 +26,7 @@ pub struct IO {
 }
 
 impl IO {
-    pub fn new() -> (Self, BusIO) {
+    pub fn new(events: EventSender) -> (Self, BusIO) {
         let (input_tx, input_rx) = bounded(0);
         let frame = Arc::new(Mutex::new(Frame::new()));
         let io = Self {
@@ -37,6 +38,7 @@ impl IO {
             input_rx,
 
             frame,
+            events,
         };
         (io, bus_io)
     }
@@ -46,6 +48,7 @@ impl IO {
         if self.input_tx.send(input).is_ok() {
             let frame_data = self.frame.lock().unwrap();
             frame.size = frame_data.size;
+            frame.pixel_aspect_ratio = frame_data.pixel_aspect_ratio;
             frame.frame_buffer.resize(frame_data.frame_buffer.len(), 0);
             frame.frame_buffer.copy_from_slice(&frame_data.frame_buffer);
         }
@@ -60,6 +63,7 @@ pub struct BusIO {
     /// The frame isn't actually used here, it's just passed
     /// over to the render thread.
     frame:  Arc<Mutex<Frame>>,
+    events: EventSender,
 }
 
 impl BusIO {
@@ -77,6 +81,10 @@ impl BusIO {
     pub fn clone_frame_arc(&self) -> Arc<Mutex<Frame>> {
         self.frame.clone()
     }
+
+    pub fn clone_event_sender(&self) -> EventSender {
+        self.events.clone()
+    }
 }
 
 
@@ -85,22 +93,59 @@ pub enum InputMessage {
         path: PathBuf,
     },
     CDRemoved,
+    MultitapConnected {
+        port: Port,
+    },
+    MultitapDisconnected {
+        port: Port,
+    },
     ControllerConnected {
         port: Port,
+        slot: Slot,
         state: crate::peripheral::controller::ControllerState,
     },
     ControllerDisconnected {
         port: Port,
+        slot: Slot,
+    },
+    AnalogButton {
+        port: Port,
+        slot: Slot,
     },
     ControllerInput {
         port: Port,
+        slot: Slot,
         state: crate::peripheral::controller::ControllerState,
     },
     MemCardInserted {
         port: Port,
+        slot: Slot,
         path: PathBuf,
     },
     MemCardRemoved {
         port: Port,
-    }
+        slot: Slot,
+    },
+    SerialLinkConnected {
+        link: Box<dyn crate::serial::link::SerialLink>,
+    },
+    SerialLinkDisconnected,
+    DeinterlaceMode {
+        mode: crate::gpu::DeinterlaceMode,
+    },
+    OverscanMode {
+        mode: crate::gpu::OverscanMode,
+    },
+    TextureCache {
+        enable: bool,
+    },
+    ResolutionScale {
+        scale: usize,
+    },
+    RenderThreads {
+        threads: usize,
+    },
+    TrueColor {
+        enable: bool,
+    },
 }
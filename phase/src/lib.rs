@@ -13,12 +13,24 @@ mod serial;
 mod utils;
 mod io;
 mod audio;
+mod profiler;
+mod event;
 
 use std::path::PathBuf;
 use crossbeam_channel::Receiver;
 
 pub use crate::cpu::PSDebugger as PSDebugger;
+pub use crate::profiler::{ProfilerConfig, ProfileHandler, ProfileReport, ProfileCategory, HotSpot};
+pub use crate::mem::iolog::{IOLogConfig, IODevices};
+pub use crate::event::{Event, LogLevel, LogRecord, Subsystem};
+pub use crate::peripheral::stick::{StickConfig, DeadzoneShape};
+pub use crate::peripheral::buttons::{InputMacro, MacroStep};
+pub use crate::peripheral::memcard::{MemCardImage, SaveInfo, CardFormat, SaveFormat, SAVE_BLOCKS};
+pub use crate::serial::link::{SerialLink, SerialLines, LinkCable};
+pub use crate::gpu::{DeinterlaceMode, OverscanMode};
 use crate::peripheral::controller::ControllerState;
+use crate::peripheral::stick::StickInput;
+use crate::peripheral::buttons::ButtonInput;
 use crate::audio::{Resampler, SamplePacket, REAL_BASE_SAMPLE_RATE};
 
 type AudioChannel = Receiver<SamplePacket>;
@@ -27,6 +39,12 @@ type AudioChannel = Receiver<SamplePacket>;
 pub struct PlayStationConfig {
     /// Path to the bios file to use.
     pub bios_path:  PathBuf,
+    /// Directory of per-game memory cards.
+    /// 
+    /// If set, inserting a disc will insert the card "<SERIAL>.mcr"
+    /// from this directory into port 1, replacing any existing card.
+    /// The card is created if it doesn't exist.
+    pub mem_card_dir: Option<PathBuf>,
 }
 
 /// A PlayStation console.
@@ -34,24 +52,31 @@ pub struct PlayStation {
     cpu: Option<cpu::CPU>,
     io: io::IO,
     audio_channel: Option<AudioChannel>,
+    events: event::EventReceiver,
+    event_tx: event::EventSender,
     // Input state:
     input: Vec<io::InputMessage>,
-    port_1_controller: Option<ControllerState>,
-    port_2_controller: Option<ControllerState>,
+    controllers: [[Option<ControllerState>; 4]; 2],
+    sticks: [[StickInput; 4]; 2],
+    buttons: [[ButtonInput; 4]; 2],
 }
 
 impl PlayStation {
     pub fn new(config: PlayStationConfig) -> Self {
-        let (io, bus_io) = io::IO::new();
+        let (event_tx, events) = event::event_channel();
+        let (io, bus_io) = io::IO::new(event_tx.clone());
         let mut cpu = cpu::CPU::new(&config, bus_io);
         let audio_channel = cpu.enable_audio();
         Self {
             cpu: Some(cpu),
             io,
             audio_channel: Some(audio_channel),
+            events,
+            event_tx,
             input: Vec::new(),
-            port_1_controller: None,
-            port_2_controller: None,
+            controllers: [[None; 4]; 2],
+            sticks: Default::default(),
+            buttons: Default::default(),
         }
     }
 
@@ -68,11 +93,16 @@ impl PlayStation {
     /// This should be called at 60fps for NTSC,
     /// and 50fps for PAL.
     pub fn frame(&mut self, frame: &mut Frame) {
-        if let Some(state) = self.port_1_controller {
-            self.input.push(io::InputMessage::ControllerInput { port: Port::One, state });
-        }
-        if let Some(state) = self.port_2_controller {
-            self.input.push(io::InputMessage::ControllerInput { port: Port::Two, state });
+        for port in [Port::One, Port::Two] {
+            for slot in Slot::ALL {
+                if let Some(state) = self.controllers[port as usize][slot as usize].as_mut() {
+                    self.sticks[port as usize][slot as usize].apply(state);
+                    self.buttons[port as usize][slot as usize].apply(state);
+                    self.input.push(io::InputMessage::ControllerInput { port, slot, state: *state });
+                    // The peripheral keeps the movement until the game polls it.
+                    state.clear_mouse_delta();
+                }
+            }
         }
         let input = std::mem::replace(&mut self.input, Vec::new()).into_boxed_slice();
         self.io.get_frame(input, frame);
@@ -92,7 +122,8 @@ impl PlayStation {
                     sample_rx,
                     None,
                     REAL_BASE_SAMPLE_RATE,
-                    sample_rate
+                    sample_rate,
+                    self.event_tx.clone()
                 ),
             })
         } else {
@@ -100,31 +131,144 @@ impl PlayStation {
         }
     }
 
-    pub fn attach_controller(&mut self, controller: ControllerType, port: Port) {
+    /// Get all events produced since the last call.
+    /// 
+    /// Events are queued internally and dropped if the queue fills up,
+    /// so this should be called regularly (i.e. once per frame).
+    pub fn poll_events(&self) -> impl Iterator<Item = Event> + '_ {
+        self.events.poll()
+    }
+
+    /// Set the most verbose level of log message that will be produced.
+    /// By default this is `LogLevel::Info`.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.events.set_log_level(level);
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        self.events.log_level()
+    }
+
+    /// Enable the guest profiler.
+    /// Reports are produced every `config.report_frames` frames,
+    /// and can be retrieved from the returned handler.
+    /// 
+    /// Warning: this will panic if the CPU thread has begun.
+    pub fn enable_profiler(&mut self, config: ProfilerConfig) -> std::io::Result<ProfileHandler> {
+        let (profiler, handler) = profiler::Profiler::new(config)?;
+        self.cpu.as_mut().expect("CPU thread running!").enable_profiler(profiler);
+        Ok(handler)
+    }
+
+    /// Enable logging of I/O register accesses.
+    /// Each access to one of `config.devices` is written as a line
+    /// with the register name, value, PC and cycle count.
+    /// 
+    /// Warning: this will panic if the CPU thread has begun.
+    pub fn enable_io_logger(&mut self, config: IOLogConfig) -> std::io::Result<()> {
+        let logger = mem::iolog::IOLogger::new(config)?;
+        self.cpu.as_mut().expect("CPU thread running!").enable_io_logger(logger);
+        Ok(())
+    }
+
+    /// Plug a multitap into a port, allowing controllers and
+    /// memory cards in slots B-D to be used.
+    pub fn attach_multitap(&mut self, port: Port) {
+        self.input.push(io::InputMessage::MultitapConnected { port });
+    }
+
+    /// Unplug a multitap. Any devices in slots B-D remain attached,
+    /// but can't be seen by the console.
+    pub fn detach_multitap(&mut self, port: Port) {
+        self.input.push(io::InputMessage::MultitapDisconnected { port });
+    }
+
+    /// Plug a link cable into the serial port, replacing any existing cable.
+    /// Use `LinkCable::pair` to link two consoles in the same process.
+    pub fn connect_serial_link(&mut self, link: impl SerialLink + 'static) {
+        self.input.push(io::InputMessage::SerialLinkConnected { link: Box::new(link) });
+    }
+
+    pub fn disconnect_serial_link(&mut self) {
+        self.input.push(io::InputMessage::SerialLinkDisconnected);
+    }
+
+    /// Attach a controller to a port.
+    /// Slots B-D are only accessible via a multitap.
+    pub fn attach_controller(&mut self, controller: ControllerType, port: Port, slot: Slot) {
         let state = ControllerState::new(controller);
-        self.input.push(io::InputMessage::ControllerConnected { port, state });
-        match port {
-            Port::One => self.port_1_controller = Some(state),
-            Port::Two => self.port_2_controller = Some(state),
-        }
+        self.input.push(io::InputMessage::ControllerConnected { port, slot, state });
+        self.controllers[port as usize][slot as usize] = Some(state);
     }
 
-    pub fn detach_controller(&mut self, port: Port) {
-        self.input.push(io::InputMessage::ControllerDisconnected { port });
-        match port {
-            Port::One => self.port_1_controller = None,
-            Port::Two => self.port_2_controller = None,
-        }
+    pub fn detach_controller(&mut self, port: Port, slot: Slot) {
+        self.input.push(io::InputMessage::ControllerDisconnected { port, slot });
+        self.controllers[port as usize][slot as usize] = None;
     }
 
     /// Press a button on the controller plugged into a port.
-    pub fn press_button(&mut self, port: Port, button: Button, pressed: bool) {
-        // TODO: more gracefully handle errors?
-        let controller = match port {
-            Port::One => self.port_1_controller.as_mut().expect("port 1 controller is missing!"),
-            Port::Two => self.port_2_controller.as_mut().expect("port 2 controller is missing!"),
-        };
-        controller.press_button(button, pressed);
+    pub fn press_button(&mut self, port: Port, slot: Slot, button: Button, pressed: bool) {
+        let has_sticks = self.controller_mut(port, slot).has_sticks();
+        if !(has_sticks && self.sticks[port as usize][slot as usize].press_dpad(button, pressed)) {
+            self.buttons[port as usize][slot as usize].press(button, pressed);
+            self.controller_mut(port, slot).press_button(button, pressed);
+        }
+    }
+
+    /// Set autofire for a button. While held, the button alternates
+    /// between pressed and released every `rate` frames.
+    /// None disables autofire.
+    pub fn set_turbo(&mut self, port: Port, slot: Slot, button: Button, rate: Option<u32>) {
+        self.buttons[port as usize][slot as usize].set_turbo(button, rate);
+    }
+
+    /// Start playing a macro on a controller, replacing any macro already playing.
+    /// Each step is applied for the given number of frames.
+    pub fn play_macro(&mut self, port: Port, slot: Slot, input_macro: InputMacro) {
+        self.buttons[port as usize][slot as usize].play_macro(input_macro);
+    }
+
+    pub fn stop_macro(&mut self, port: Port, slot: Slot) {
+        self.buttons[port as usize][slot as usize].stop_macro();
+    }
+
+    pub fn macro_playing(&self, port: Port, slot: Slot) -> bool {
+        self.buttons[port as usize][slot as usize].macro_playing()
+    }
+
+    /// Press a button on a mouse plugged into a port.
+    pub fn press_mouse_button(&mut self, port: Port, slot: Slot, button: MouseButton, pressed: bool) {
+        self.controller_mut(port, slot).press_mouse_button(button, pressed);
+    }
+
+    /// Move a mouse plugged into a port.
+    /// Positive X is right, positive Y is down.
+    /// 
+    /// Movement is accumulated and sent to the console each frame.
+    pub fn move_mouse(&mut self, port: Port, slot: Slot, dx: i32, dy: i32) {
+        self.controller_mut(port, slot).move_mouse(dx, dy);
+    }
+
+    /// Press a button on a light gun plugged into a port.
+    pub fn press_light_gun_button(&mut self, port: Port, slot: Slot, button: LightGunButton, pressed: bool) {
+        self.controller_mut(port, slot).press_light_gun_button(button, pressed);
+    }
+
+    /// Aim a light gun plugged into a port.
+    /// 
+    /// X and Y go from 0 to 1 across the visible screen, from the top-left.
+    /// None, or values outside this range, aim off-screen
+    /// (which many games use for reloading).
+    pub fn aim_light_gun(&mut self, port: Port, slot: Slot, aim: Option<(f32, f32)>) {
+        self.controller_mut(port, slot).aim_light_gun(aim);
+    }
+
+    /// Press the analog button on a DualShock controller,
+    /// switching between digital and analog mode.
+    /// 
+    /// Some games lock the mode, in which case this does nothing.
+    pub fn press_analog_button(&mut self, port: Port, slot: Slot) {
+        self.input.push(io::InputMessage::AnalogButton { port, slot });
     }
 
     /// Update value for stick and axis. Only relevant for analog controllers.
@@ -132,13 +276,24 @@ impl PlayStation {
     /// The value should be between -1 and +1, with 0 the at-rest value.
     /// On the X-axis, 1 is right and -1 is left.
     /// On the Y-axis, 1 is bottom and -1 is top.
-    pub fn update_stick_axis(&mut self, port: Port, stick: AnalogStickAxis, value: f32) {
-        // TODO: more gracefully handle errors?
-        let controller = match port {
-            Port::One => self.port_1_controller.as_mut().expect("port 1 controller is missing!"),
-            Port::Two => self.port_2_controller.as_mut().expect("port 2 controller is missing!"),
-        };
-        controller.update_stick_axis(stick, value);
+    pub fn update_stick_axis(&mut self, port: Port, slot: Slot, stick: AnalogStickAxis, value: f32) {
+        self.update_axis(port, slot, stick.into(), value);
+    }
+
+    /// Update any analog input. See `Axis` for the range of each.
+    /// Axes that the controller doesn't have are ignored.
+    /// 
+    /// Stick axes are shaped according to the `StickConfig`.
+    pub fn update_axis(&mut self, port: Port, slot: Slot, axis: Axis, value: f32) {
+        if !self.sticks[port as usize][slot as usize].set_axis(axis, value) {
+            self.controller_mut(port, slot).update_axis(axis, value);
+        }
+    }
+
+    /// Set the deadzone, sensitivity and D-pad mapping for analog sticks
+    /// in a port. This is kept if the controller is changed.
+    pub fn set_stick_config(&mut self, port: Port, slot: Slot, config: StickConfig) {
+        self.sticks[port as usize][slot as usize].set_config(config);
     }
 
     /// Insert a CD. The path must point to:
@@ -156,20 +311,66 @@ impl PlayStation {
 
     /// Insert a memory card into a port.
     /// If the path points to a file that does not exist, it will be created.
-    pub fn insert_mem_card(&mut self, path: PathBuf, port: Port) {
-        self.input.push(io::InputMessage::MemCardInserted { port, path });
+    /// Slots B-D are only accessible via a multitap.
+    pub fn insert_mem_card(&mut self, path: PathBuf, port: Port, slot: Slot) {
+        self.input.push(io::InputMessage::MemCardInserted { port, slot, path });
     }
 
     /// Remove a previously inserted memory card from a port.
-    pub fn remove_mem_card(&mut self, port: Port) {
-        self.input.push(io::InputMessage::MemCardRemoved { port });
+    pub fn remove_mem_card(&mut self, port: Port, slot: Slot) {
+        self.input.push(io::InputMessage::MemCardRemoved { port, slot });
+    }
+
+    /// Set how 480i video is turned into whole frames.
+    pub fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode) {
+        self.input.push(io::InputMessage::DeinterlaceMode { mode });
+    }
+
+    /// Set how much of the picture outside the game's display area is shown.
+    pub fn set_overscan_mode(&mut self, mode: OverscanMode) {
+        self.input.push(io::InputMessage::OverscanMode { mode });
+    }
+
+    /// Emulate the GPU's texture and palette caches.
+    /// This is slower, and only a few games draw differently with it.
+    pub fn set_texture_cache(&mut self, enable: bool) {
+        self.input.push(io::InputMessage::TextureCache { enable });
+    }
+
+    /// Draw polygons at a multiple of the native resolution.
+    /// The scale is rounded up to 1, 2, 4 or 8. Frames are enlarged by the same amount.
+    pub fn set_resolution_scale(&mut self, scale: usize) {
+        self.input.push(io::InputMessage::ResolutionScale { scale });
+    }
+
+    /// Split drawing between this many threads.
+    /// The picture is the same as with one thread.
+    pub fn set_render_threads(&mut self, threads: usize) {
+        self.input.push(io::InputMessage::RenderThreads { threads });
+    }
+
+    /// Keep 24-bit colour for drawn pixels, and don't dither.
+    /// VRAM still holds 15-bit colour, so games read back what they expect.
+    pub fn set_true_color(&mut self, enable: bool) {
+        self.input.push(io::InputMessage::TrueColor { enable });
+    }
+}
+
+// Internal
+impl PlayStation {
+    fn controller_mut(&mut self, port: Port, slot: Slot) -> &mut ControllerState {
+        // TODO: more gracefully handle errors?
+        self.controllers[port as usize][slot as usize].as_mut()
+            .unwrap_or_else(|| panic!("controller {:?}{:?} is missing!", port, slot))
     }
 }
 
 /// Information for frame.
 pub struct Frame {
     pub frame_buffer: Vec<u8>,
-    pub size: (usize, usize)
+    pub size: (usize, usize),
+    /// Width of a pixel divided by its height, on a 4:3 screen.
+    pub pixel_aspect_ratio: f32,
 }
 
 impl Frame {
@@ -177,6 +378,7 @@ impl Frame {
         Self {
             frame_buffer: Vec::new(),
             size: (0, 0),
+            pixel_aspect_ratio: 1.0,
         }
     }
 
@@ -210,16 +412,42 @@ impl AudioHandler {
 /// while certain newer games do not support Digital controllers.
 pub enum ControllerType {
     Digital,
+    /// DualShock.
     Analog,
+    /// Analog joystick in analog (green) mode.
+    AnalogJoystick,
+    /// Namco NeGcon. Uses the Start, D-pad, R1 (R), Circle (A)
+    /// and Triangle (B) buttons, and the Twist, I, II and L axes.
+    NeGcon,
+    /// PlayStation Mouse.
+    Mouse,
+    /// Namco GunCon light gun.
+    GunCon,
+    /// Konami Justifier light gun.
+    Justifier,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 /// The port of controller or memory card.
 pub enum Port {
     One,
     Two,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The slot of a multitap.
+/// Without a multitap, only slot A is connected.
+pub enum Slot {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Slot {
+    pub const ALL: [Slot; 4] = [Slot::A, Slot::B, Slot::C, Slot::D];
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 // Controller analog stick side and axis.
 pub enum AnalogStickAxis {
@@ -229,7 +457,52 @@ pub enum AnalogStickAxis {
     RightY,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Any analog input.
+/// Each controller type only uses some of these.
+pub enum Axis {
+    /// Stick axes, from -1 to +1.
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    /// NeGcon twist, from -1 (left) to +1 (right).
+    Twist,
+    /// NeGcon analog buttons, from 0 (released) to +1 (fully pressed).
+    I,
+    II,
+    L,
+}
+
+impl From<AnalogStickAxis> for Axis {
+    fn from(stick: AnalogStickAxis) -> Self {
+        match stick {
+            AnalogStickAxis::LeftX => Axis::LeftX,
+            AnalogStickAxis::LeftY => Axis::LeftY,
+            AnalogStickAxis::RightX => Axis::RightX,
+            AnalogStickAxis::RightY => Axis::RightY,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Mouse buttons.
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Light gun buttons.
+pub enum LightGunButton {
+    Trigger,
+    /// A on the GunCon, Start on the Justifier.
+    A,
+    /// B on the GunCon, Back on the Justifier.
+    B,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 /// Controller buttons.
 pub enum Button {
     Select,
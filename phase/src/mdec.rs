@@ -103,17 +103,14 @@ impl MDEC {
 
 impl MemInterface for MDEC {
     fn read_word(&mut self, addr: u32) -> u32 {
-        let data = match addr {
+        match addr {
             0x1F80_1820 => self.read_data(),
             0x1F80_1824 => self.read_status(),
             _ => panic!("invalid MDEC read address"),
-        };
-        //println!("read mdec {:X} from {:X}", data, addr);
-        data
+        }
     }
 
     fn write_word(&mut self, addr: u32, data: u32) {
-        //println!("write mdec {:X} to {:X}", data, addr);
         match addr {
             0x1F80_1820 => self.write_command(data),
             0x1F80_1824 => self.write_control(data),
@@ -234,7 +234,7 @@ impl DMA {
 
 impl MemInterface for DMA {
     fn read_word(&mut self, addr: u32) -> u32 {
-        let data = match addr {
+        match addr {
             0x1F801080 => self.channels[0].base_addr,
             0x1F801084 => self.channels[0].block_control,
             0x1F801088 => self.channels[0].control.bits(),
@@ -267,13 +267,10 @@ impl MemInterface for DMA {
             0x1F8010F4 => self.interrupt.bits(),
 
             _ => panic!("invalid DMA address {:X}", addr),
-        };
-        //println!("DMA read {:X} from {:X}", data, addr);
-        data
+        }
     }
 
     fn write_word(&mut self, addr: u32, data: u32) {
-        //println!("DMA write {:X} => {:X}", data, addr);
         match addr {
             0x1F801080 => self.channels[0].set_addr(data),
             0x1F801084 => self.channels[0].block_control = data,
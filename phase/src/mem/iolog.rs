@@ -0,0 +1,303 @@
+// Logging of I/O register accesses, for debugging and reverse-engineering.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf
+};
+
+use crate::utils::bits::*;
+
+bitflags::bitflags! {
+    /// Set of I/O devices.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IODevices: u16 {
+        const MemControl    = bit!(0);
+        /// Controllers and memory cards (SIO0).
+        const Peripheral    = bit!(1);
+        /// Serial port (SIO1).
+        const Serial        = bit!(2);
+        const Interrupts    = bit!(3);
+        const DMA           = bit!(4);
+        const Timers        = bit!(5);
+        const CDROM         = bit!(6);
+        const GPU           = bit!(7);
+        const MDEC          = bit!(8);
+        const SPU           = bit!(9);
+    }
+}
+
+impl IODevices {
+    /// Find the device that lives at an I/O address.
+    pub fn from_addr(addr: u32) -> Self {
+        match addr {
+            0x1F80_1000..=0x1F80_1023 => IODevices::MemControl,
+            0x1F80_1040..=0x1F80_104F => IODevices::Peripheral,
+            0x1F80_1050..=0x1F80_105F => IODevices::Serial,
+            0x1F80_1060..=0x1F80_1063 => IODevices::MemControl,
+            0x1F80_1070..=0x1F80_1077 => IODevices::Interrupts,
+            0x1F80_1080..=0x1F80_10FF => IODevices::DMA,
+            0x1F80_1100..=0x1F80_113F => IODevices::Timers,
+            0x1F80_1800..=0x1F80_1807 => IODevices::CDROM,
+            0x1F80_1810..=0x1F80_1817 => IODevices::GPU,
+            0x1F80_1820..=0x1F80_1827 => IODevices::MDEC,
+            0x1F80_1C00..=0x1F80_1FFF => IODevices::SPU,
+            _ => IODevices::empty(),
+        }
+    }
+}
+
+/// Config for the I/O access logger.
+pub struct IOLogConfig {
+    /// Only accesses to these devices are logged.
+    pub devices: IODevices,
+    /// File to write the log to. If None, the log is written to stdout.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+pub enum IOAccessSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+/// A read or write of an I/O register.
+pub struct IOAccess {
+    pub addr: u32,
+    pub data: u32,
+    pub size: IOAccessSize,
+    pub write: bool,
+}
+
+/// Writes a line for each access to a filtered device.
+pub struct IOLogger {
+    devices: IODevices,
+    out: Box<dyn Write + Send>,
+}
+
+impl IOLogger {
+    pub fn new(config: IOLogConfig) -> std::io::Result<Self> {
+        let out: Box<dyn Write + Send> = if let Some(path) = config.path {
+            Box::new(BufWriter::new(File::create(path)?))
+        } else {
+            Box::new(std::io::stdout())
+        };
+        Ok(Self {
+            devices: config.devices,
+            out,
+        })
+    }
+
+    /// Check if accesses to this address should be logged.
+    #[inline]
+    pub fn filter(&self, addr: u32) -> bool {
+        self.devices.intersects(IODevices::from_addr(addr))
+    }
+
+    /// Log a single access.
+    ///
+    /// The CD-ROM index is needed to name CD-ROM registers.
+    pub fn log(&mut self, access: &IOAccess, cd_index: u8, pc: u32, cycle: u64) {
+        let device = IODevices::from_addr(access.addr).iter_names().next().map(|(name, _)| name).unwrap_or("?");
+        let name = register_name(access.addr, access.write, cd_index);
+        let dir = if access.write {"W"} else {"R"};
+        let value = match access.size {
+            IOAccessSize::Byte      => format!("{:02X}", access.data),
+            IOAccessSize::Halfword  => format!("{:04X}", access.data),
+            IOAccessSize::Word      => format!("{:08X}", access.data),
+        };
+        let _ = writeln!(self.out, "[{:>12}] ${:08X} {:<10} {} {:<18} ${:08X} = {}",
+            cycle, pc, device, dir, name, access.addr, value);
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// Get a symbolic name for an I/O register.
+fn register_name(addr: u32, write: bool, cd_index: u8) -> String {
+    let name = match addr {
+        0x1F80_1000 => "EXP1_BASE",
+        0x1F80_1004 => "EXP2_BASE",
+        0x1F80_1008 => "EXP1_DELAY",
+        0x1F80_100C => "EXP3_DELAY",
+        0x1F80_1010 => "BIOS_DELAY",
+        0x1F80_1014 => "SPU_DELAY",
+        0x1F80_1018 => "CDROM_DELAY",
+        0x1F80_101C => "EXP2_DELAY",
+        0x1F80_1020 => "COM_DELAY",
+        0x1F80_1060 => "RAM_SIZE",
+
+        0x1F80_1040 => if write {"JOY_TX_DATA"} else {"JOY_RX_DATA"},
+        0x1F80_1044..=0x1F80_1047 => "JOY_STAT",
+        0x1F80_1048 => "JOY_MODE",
+        0x1F80_104A => "JOY_CTRL",
+        0x1F80_104E => "JOY_BAUD",
+
+        0x1F80_1050 => if write {"SIO_TX_DATA"} else {"SIO_RX_DATA"},
+        0x1F80_1054..=0x1F80_1057 => "SIO_STAT",
+        0x1F80_1058 => "SIO_MODE",
+        0x1F80_105A => "SIO_CTRL",
+        0x1F80_105C => "SIO_MISC",
+        0x1F80_105E => "SIO_BAUD",
+
+        0x1F80_1070 => "I_STAT",
+        0x1F80_1074 => "I_MASK",
+
+        0x1F80_1080..=0x1F80_10EF => {
+            let channel = ["MDECin", "MDECout", "GPU", "CDROM", "SPU", "PIO", "OTC"][((addr >> 4) & 0x7) as usize];
+            let reg = match addr & 0xF {
+                0x0 => "MADR",
+                0x4 => "BCR",
+                0x8 => "CHCR",
+                _ => "?",
+            };
+            return format!("DMA_{}_{}", channel, reg);
+        },
+        0x1F80_10F0 => "DPCR",
+        0x1F80_10F4 => "DICR",
+
+        0x1F80_1100..=0x1F80_112F => {
+            let timer = (addr >> 4) & 0x3;
+            let reg = match addr & 0xF {
+                0x0 => "COUNT",
+                0x4 => "MODE",
+                0x8 => "TARGET",
+                _ => "?",
+            };
+            return format!("TIMER{}_{}", timer, reg);
+        },
+
+        0x1F80_1800 => "CD_INDEX_STATUS",
+        0x1F80_1801 => match (write, cd_index) {
+            (false, _) => "CD_RESPONSE",
+            (true, 0) => "CD_COMMAND",
+            (true, 1) => "CD_SOUND_MAP_DATA",
+            (true, 2) => "CD_SOUND_MAP_CODING",
+            (true, _) => "CD_VOL_R_TO_R",
+        },
+        0x1F80_1802 => match (write, cd_index) {
+            (false, _) => "CD_DATA",
+            (true, 0) => "CD_PARAMETER",
+            (true, 1) => "CD_INT_ENABLE",
+            (true, 2) => "CD_VOL_L_TO_L",
+            (true, _) => "CD_VOL_R_TO_L",
+        },
+        0x1F80_1803 => match (write, cd_index) {
+            (false, 0 | 2) => "CD_INT_ENABLE",
+            (false, _) => "CD_INT_FLAG",
+            (true, 0) => "CD_REQUEST",
+            (true, 1) => "CD_INT_FLAG",
+            (true, 2) => "CD_VOL_L_TO_R",
+            (true, _) => "CD_APPLY_VOL",
+        },
+
+        0x1F80_1810 => if write {"GP0"} else {"GPUREAD"},
+        0x1F80_1814 => if write {"GP1"} else {"GPUSTAT"},
+
+        0x1F80_1820 => if write {"MDEC_COMMAND"} else {"MDEC_DATA"},
+        0x1F80_1824 => if write {"MDEC_CONTROL"} else {"MDEC_STATUS"},
+
+        0x1F80_1C00..=0x1F80_1D7F => {
+            let voice = (addr >> 4) & 0x1F;
+            let reg = match addr & 0xF {
+                0x0 => "VOL_L",
+                0x2 => "VOL_R",
+                0x4 => "PITCH",
+                0x6 => "START_ADDR",
+                0x8 => "ADSR_LO",
+                0xA => "ADSR_HI",
+                0xC => "ADSR_VOL",
+                0xE => "REPEAT_ADDR",
+                _ => "?",
+            };
+            return format!("SPU_VOICE{}_{}", voice, reg);
+        },
+        0x1F80_1D80 => "SPU_MAIN_VOL_L",
+        0x1F80_1D82 => "SPU_MAIN_VOL_R",
+        0x1F80_1D84 => "SPU_REVERB_VOL_L",
+        0x1F80_1D86 => "SPU_REVERB_VOL_R",
+        0x1F80_1D88 | 0x1F80_1D8A => "SPU_KON",
+        0x1F80_1D8C | 0x1F80_1D8E => "SPU_KOFF",
+        0x1F80_1D90 | 0x1F80_1D92 => "SPU_PMON",
+        0x1F80_1D94 | 0x1F80_1D96 => "SPU_NON",
+        0x1F80_1D98 | 0x1F80_1D9A => "SPU_EON",
+        0x1F80_1D9C | 0x1F80_1D9E => "SPU_ENDX",
+        0x1F80_1DA2 => "SPU_REVERB_BASE",
+        0x1F80_1DA4 => "SPU_IRQ_ADDR",
+        0x1F80_1DA6 => "SPU_TRANSFER_ADDR",
+        0x1F80_1DA8 => "SPU_TRANSFER_FIFO",
+        0x1F80_1DAA => "SPUCNT",
+        0x1F80_1DAC => "SPU_TRANSFER_CTRL",
+        0x1F80_1DAE => "SPUSTAT",
+        0x1F80_1DB0 => "SPU_CD_VOL_L",
+        0x1F80_1DB2 => "SPU_CD_VOL_R",
+        0x1F80_1DB4 => "SPU_EXT_VOL_L",
+        0x1F80_1DB6 => "SPU_EXT_VOL_R",
+        0x1F80_1DB8 => "SPU_CUR_MAIN_VOL_L",
+        0x1F80_1DBA => "SPU_CUR_MAIN_VOL_R",
+        0x1F80_1DC0..=0x1F80_1DFF => "SPU_REVERB_CONFIG",
+        0x1F80_1E00..=0x1F80_1FFF => "SPU_INTERNAL",
+        _ => "?",
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn devices_by_addr() {
+        assert_eq!(IODevices::from_addr(0x1F80_1044), IODevices::Peripheral);
+        assert_eq!(IODevices::from_addr(0x1F80_1060), IODevices::MemControl);
+        assert_eq!(IODevices::from_addr(0x1F80_10F4), IODevices::DMA);
+        assert_eq!(IODevices::from_addr(0x1F80_1814), IODevices::GPU);
+        assert_eq!(IODevices::from_addr(0x1F80_1DAE), IODevices::SPU);
+        assert_eq!(IODevices::from_addr(0x1F80_1030), IODevices::empty());
+    }
+
+    #[test]
+    fn register_names() {
+        assert_eq!(register_name(0x1F80_1810, true, 0), "GP0");
+        assert_eq!(register_name(0x1F80_1810, false, 0), "GPUREAD");
+        assert_eq!(register_name(0x1F80_10A8, true, 0), "DMA_GPU_CHCR");
+        assert_eq!(register_name(0x1F80_1124, true, 0), "TIMER2_MODE");
+        assert_eq!(register_name(0x1F80_1C36, true, 0), "SPU_VOICE3_START_ADDR");
+        assert_eq!(register_name(0x1F80_1030, false, 0), "?");
+    }
+
+    #[test]
+    fn cdrom_names_follow_index() {
+        assert_eq!(register_name(0x1F80_1801, true, 0), "CD_COMMAND");
+        assert_eq!(register_name(0x1F80_1801, true, 3), "CD_VOL_R_TO_R");
+        assert_eq!(register_name(0x1F80_1801, false, 3), "CD_RESPONSE");
+        assert_eq!(register_name(0x1F80_1803, false, 0), "CD_INT_ENABLE");
+        assert_eq!(register_name(0x1F80_1803, false, 1), "CD_INT_FLAG");
+    }
+
+    #[test]
+    fn log_filtered_devices() {
+        let path = std::env::temp_dir().join(format!("phase-iolog-{}.txt", std::process::id()));
+        let mut logger = IOLogger::new(IOLogConfig {
+            devices: IODevices::GPU | IODevices::CDROM,
+            path: Some(path.clone()),
+        }).unwrap();
+        assert!(logger.filter(0x1F80_1814));
+        assert!(logger.filter(0x1F80_1801));
+        assert!(!logger.filter(0x1F80_1C00));
+
+        logger.log(&IOAccess { addr: 0x1F80_1814, data: 0x0800_0001, size: IOAccessSize::Word, write: true }, 0, 0x8001_0000, 1234);
+        logger.log(&IOAccess { addr: 0x1F80_1801, data: 0x19, size: IOAccessSize::Byte, write: true }, 0, 0xBFC0_0004, 5678);
+        logger.flush();
+        let log = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines, [
+            "[        1234] $80010000 GPU        W GP1                $1F801814 = 08000001",
+            "[        5678] $BFC00004 CDROM      W CD_COMMAND         $1F801801 = 19",
+        ]);
+    }
+}
@@ -2,6 +2,9 @@ pub mod ram;
 mod bios;
 mod control;
 mod dma;
+pub mod iolog;
+
+use std::path::PathBuf;
 
 use mips::mem::{Data, Mem32};
 use crossbeam_channel::unbounded;
@@ -9,10 +12,11 @@ use ram::RAM;
 use bios::BIOS;
 use control::MemControl;
 use dma::DMA;
+use iolog::{IOLogger, IOAccess, IOAccessSize};
 pub use dma::DMADevice;
 
 use crate::serial::SerialIO;
-use crate::{AudioChannel, PlayStationConfig};
+use crate::{AudioChannel, PlayStationConfig, Port, Slot};
 use crate::gpu::GPU;
 use crate::io::{BusIO, InputMessage};
 use crate::spu::SPU;
@@ -23,6 +27,8 @@ use crate::cdrom::CDROM;
 use crate::expansion::{ExpansionPort1, ExpansionPort2};
 use crate::peripheral::PeripheralPort;
 use crate::mdec::MDEC;
+use crate::profiler::{Profiler, ProfileCategory};
+use crate::event::{Event, EventSender, LogLevel, Subsystem};
 
 pub struct MemBus {
     control: MemControl,
@@ -44,11 +50,22 @@ pub struct MemBus {
     expansion_port_2: ExpansionPort2,
 
     io: BusIO,
+    events: EventSender,
+    mem_card_dir: Option<PathBuf>,
+    profiler: Option<Profiler>,
+    io_logger: Option<IOLogger>,
+
+    /// PC of the instruction currently executing.
+    /// Only tracked if the profiler or I/O logger is enabled.
+    current_pc: u32,
+    /// Cycles run so far, including DMA.
+    cycle_count: u64,
 }
 
 impl MemBus {
     pub fn new(config: &PlayStationConfig, io: BusIO) -> Self {
         let bios = BIOS::new(Some(&config.bios_path)).expect("error loading BIOS"); // TODO: handle error.
+        let events = io.clone_event_sender();
         Self {
             control: MemControl::new(),
             main_ram: RAM::new(2048 * 1024), // 2MB
@@ -58,17 +75,24 @@ impl MemBus {
 
             timers:     Timers::new(),
             dma:        DMA::new(),
-            cdrom:      CDROM::new(),
+            cdrom:      CDROM::new(events.clone()),
             spu:        SPU::new(),
-            gpu:        GPU::new(io.clone_frame_arc()),
-            peripheral: PeripheralPort::new(),
+            gpu:        GPU::new(io.clone_frame_arc(), events.clone()),
+            peripheral: PeripheralPort::new(events.clone()),
             mdec:       MDEC::new(),
             serial_io:  SerialIO::new(),
 
             expansion_port_1: ExpansionPort1::new(),
-            expansion_port_2: ExpansionPort2::new(),
+            expansion_port_2: ExpansionPort2::new(events.clone()),
 
             io: io,
+            events,
+            mem_card_dir: config.mem_card_dir.clone(),
+            profiler: None,
+            io_logger: None,
+
+            current_pc: 0,
+            cycle_count: 0,
         }
     }
 
@@ -78,11 +102,25 @@ impl MemBus {
         sample_rx
     }
 
+    pub fn enable_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn enable_io_logger(&mut self, logger: IOLogger) {
+        self.io_logger = Some(logger);
+    }
+
+    /// Set the PC currently executing, for profiling and logging purposes.
+    pub fn set_current_pc(&mut self, pc: u32) {
+        self.current_pc = pc;
+    }
+
     /// Clock internally, and set interrupt bits.
     /// 
     /// Returns false if a frame is about to begin,
     /// and therefore we are syncing with the real world.
     fn do_clock(&mut self, cycles: usize) -> bool {
+        self.cycle_count += cycles as u64;
         let gpu_stat = self.gpu.clock(cycles);
         if self.gpu.dma_ready() {
             self.dma.gpu_req();
@@ -115,13 +153,16 @@ impl MemBus {
 
         let peripheral_irq = self.peripheral.clock(cycles);
 
+        let serial_irq = self.serial_io.clock(cycles);
+
         self.interrupts.trigger_irq(
             gpu_stat.irq |
             dma_irq |
             timer_irq |
             cd_irq |
             spu_irq |
-            peripheral_irq
+            peripheral_irq |
+            serial_irq
         );
         gpu_stat.new_frame
     }
@@ -133,6 +174,7 @@ impl MemBus {
     fn do_dma(&mut self) {
         while let Some(transfer) = self.dma.get_transfer() {
             while let Some(wait_cycles) = self.mut_dma_device(transfer.device).wait_cycles() {
+                self.profile_dma_cycles(transfer.device, wait_cycles);
                 if self.do_clock(wait_cycles) {
                     self.begin_frame();
                 }
@@ -151,50 +193,118 @@ impl MemBus {
                 self.main_ram.write_word(ram_addr, data);
                 cycles
             };
+            self.profile_dma_cycles(transfer.device, cycles);
             if self.do_clock(cycles) {
                 self.begin_frame();
             }
         }
     }
 
+    /// Attribute DMA cycles to the subsystem that caused the stall.
+    fn profile_dma_cycles(&mut self, device: usize, cycles: usize) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            let category = match device {
+                2 => ProfileCategory::GPU,
+                3 => ProfileCategory::CDROM,
+                _ => ProfileCategory::DMA,
+            };
+            profiler.add_stall_cycles(category, cycles);
+        }
+    }
+
     /// Upon frame completion, send a frame to the outside world.
     fn begin_frame(&mut self) {
         // Sync up with the GPU.
         self.gpu.get_frame();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+        if let Some(logger) = self.io_logger.as_mut() {
+            logger.flush();
+        }
         self.peripheral.flush_mem_cards();
         let input = self.io.send_frame();
         for message in input {
             use InputMessage::*;
             match message {
-                CDInserted { path } => {
-                    self.cdrom.insert_disc(Some(&path)).expect("error inserting CD");
-                    println!("CD inserted: {:?}", path.to_str());
+                CDInserted { path } => match self.cdrom.insert_disc(Some(&path)) {
+                    Ok(()) => {
+                        self.events.send(Event::CDInserted { path });
+                        self.insert_game_mem_card();
+                    },
+                    Err(e) => self.events.send(Event::DiscReadError { message: format!("could not open {}: {}", path.display(), e) }),
                 },
                 CDRemoved => {
                     self.cdrom.insert_disc(None).expect("error removing CD");
-                    println!("CD removed");
+                    self.events.send(Event::CDRemoved);
+                },
+                MultitapConnected { port } => {
+                    self.peripheral.set_multitap(port, true);
+                    self.events.send(Event::MultitapConnected { port });
                 },
-                ControllerConnected { port, state } => {
-                    println!("Connected controller to port {:?}", port);
-                    self.peripheral.set_controller_state(port, state);
+                MultitapDisconnected { port } => {
+                    self.peripheral.set_multitap(port, false);
+                    self.events.send(Event::MultitapDisconnected { port });
                 },
-                ControllerDisconnected { port } => {
-                    println!("Disconnected controller at port {:?}", port);
-                    self.peripheral.clear_controller_state(port);
+                ControllerConnected { port, slot, state } => {
+                    self.peripheral.connect_controller(port, slot, state);
+                    self.events.send(Event::ControllerConnected { port, slot });
                 },
-                ControllerInput { port, state } => {
-                    self.peripheral.set_controller_state(port, state);
+                ControllerDisconnected { port, slot } => {
+                    self.peripheral.clear_controller_state(port, slot);
+                    self.events.send(Event::ControllerDisconnected { port, slot });
                 },
-                MemCardInserted { port, path } => {
-                    println!("Memory Card inserted to port {:?}: {:?}", port, path.to_str());
-                    self.peripheral.insert_mem_card(port, &path).expect("error inserting mem card");
+                ControllerInput { port, slot, state } => {
+                    self.peripheral.set_controller_state(port, slot, state);
+                    if state.is_light_gun() {
+                        let beam = state.light_gun_aim().and_then(|(x, y)| self.gpu.beam_position(x, y));
+                        self.peripheral.set_light_gun_beam(port, slot, beam);
+                    }
                 },
-                MemCardRemoved { port } => {
-                    println!("Memory Card removed from port {:?}", port);
-                    self.peripheral.remove_mem_card(port);
+                AnalogButton { port, slot } => self.peripheral.toggle_analog_mode(port, slot),
+                MemCardInserted { port, slot, path } => self.insert_mem_card(port, slot, path),
+                MemCardRemoved { port, slot } => {
+                    self.peripheral.remove_mem_card(port, slot);
+                    self.events.send(Event::MemCardRemoved { port, slot });
                 },
+                SerialLinkConnected { link } => {
+                    self.serial_io.connect_link(link);
+                    self.events.send(Event::SerialLinkConnected);
+                },
+                SerialLinkDisconnected => {
+                    self.serial_io.disconnect_link();
+                    self.events.send(Event::SerialLinkDisconnected);
+                },
+                DeinterlaceMode { mode } => self.gpu.set_deinterlace_mode(mode),
+                OverscanMode { mode } => self.gpu.set_overscan_mode(mode),
+                TextureCache { enable } => self.gpu.set_texture_cache(enable),
+                ResolutionScale { scale } => self.gpu.set_resolution_scale(scale),
+                RenderThreads { threads } => self.gpu.set_render_threads(threads),
+                TrueColor { enable } => self.gpu.set_true_color(enable),
             }
         }
+        self.gpu.set_lightpen_target(self.peripheral.lightpen_target());
+    }
+
+    fn insert_mem_card(&mut self, port: Port, slot: Slot, path: PathBuf) {
+        match self.peripheral.insert_mem_card(port, slot, &path) {
+            Ok(()) => self.events.send(Event::MemCardInserted { port, slot, path }),
+            Err(e) => self.events.log(LogLevel::Error, Subsystem::MemCard, format_args!("could not insert {} into port {:?}{:?}: {}", path.display(), port, slot, e)),
+        }
+    }
+
+    /// Insert the per-game memory card for the current disc, if enabled.
+    fn insert_game_mem_card(&mut self) {
+        let Some(dir) = self.mem_card_dir.as_ref() else {
+            return;
+        };
+        if let Some(serial) = self.cdrom.disc_serial() {
+            let path = dir.join(format!("{}.mcr", serial));
+            let _ = std::fs::create_dir_all(dir);
+            self.insert_mem_card(Port::One, Slot::A, path);
+        } else {
+            self.events.log(LogLevel::Warn, Subsystem::MemCard, format_args!("could not find disc serial: keeping current memory card"));
+        }
     }
 }
 
@@ -203,6 +313,9 @@ impl Mem32 for MemBus {
     const LITTLE_ENDIAN: bool = true;
 
     fn clock(&mut self, cycles: usize) -> u8 {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.add_cpu_cycles(self.current_pc, cycles);
+        }
         if self.do_clock(cycles) {
             self.begin_frame();
         }
@@ -221,7 +334,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => (self.main_ram.read_byte(addr & 0x1F_FFFF), 1),
             0x1F00_0000..=0x1F7F_FFFF => (self.expansion_port_1.read_byte(addr), 1),
             0x1F80_0000..=0x1F80_03FF => (self.scratchpad.read_byte(addr & 0x3FF), 1),
-            0x1F80_1000..=0x1F80_1FFF => (self.mut_io_device(addr).read_byte(addr), 1),
+            0x1F80_1000..=0x1F80_1FFF => {
+                let data = self.mut_io_device(addr).read_byte(addr);
+                self.log_io(addr, data as u32, IOAccessSize::Byte, false);
+                (data, 1)
+            },
             0x1F80_2000..=0x1F80_2FFF => (self.expansion_port_2.read_byte(addr), 1),
             0x1FC0_0000..=0x1FC7_FFFF => (self.bios.read_byte(addr & 0x7_FFFF), 1),
             _ => panic!("read invalid address {:X}", addr),
@@ -234,7 +351,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => {self.main_ram.write_byte(addr & 0x1F_FFFF, data); 1},
             0x1F00_0000..=0x1F7F_FFFF => {self.expansion_port_1.write_byte(addr, data); 1},
             0x1F80_0000..=0x1F80_03FF => {self.scratchpad.write_byte(addr & 0x3FF, data); 1},
-            0x1F80_1000..=0x1F80_1FFF => {self.mut_io_device(addr).write_byte(addr, data); 1},
+            0x1F80_1000..=0x1F80_1FFF => {
+                self.log_io(addr, data as u32, IOAccessSize::Byte, true);
+                self.mut_io_device(addr).write_byte(addr, data);
+                1
+            },
             0x1F80_2000..=0x1F80_2FFF => {self.expansion_port_2.write_byte(addr, data); 1},
             0x1FC0_0000..=0x1FC7_FFFF => 1, // BIOS
             _ => panic!("write invalid address {:X}", addr),
@@ -246,7 +367,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => (self.main_ram.read_halfword(addr & 0x1F_FFFF), 1),
             0x1F00_0000..=0x1F7F_FFFF => (self.expansion_port_1.read_halfword(addr), 1),
             0x1F80_0000..=0x1F80_03FF => (self.scratchpad.read_halfword(addr & 0x3FF), 1),
-            0x1F80_1000..=0x1F80_1FFF => (self.mut_io_device(addr).read_halfword(addr), 1),
+            0x1F80_1000..=0x1F80_1FFF => {
+                let data = self.mut_io_device(addr).read_halfword(addr);
+                self.log_io(addr, data as u32, IOAccessSize::Halfword, false);
+                (data, 1)
+            },
             0x1F80_2000..=0x1F80_2FFF => (self.expansion_port_2.read_halfword(addr), 1),
             0x1FC0_0000..=0x1FC7_FFFF => (self.bios.read_halfword(addr & 0x7_FFFF), 1),
             _ => panic!("read invalid address {:X}", addr),
@@ -259,7 +384,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => {self.main_ram.write_halfword(addr & 0x1F_FFFF, data); 1},
             0x1F00_0000..=0x1F7F_FFFF => {self.expansion_port_1.write_halfword(addr, data); 1},
             0x1F80_0000..=0x1F80_03FF => {self.scratchpad.write_halfword(addr & 0x3FF, data); 1},
-            0x1F80_1000..=0x1F80_1FFF => {self.mut_io_device(addr).write_halfword(addr, data); 1},
+            0x1F80_1000..=0x1F80_1FFF => {
+                self.log_io(addr, data as u32, IOAccessSize::Halfword, true);
+                self.mut_io_device(addr).write_halfword(addr, data);
+                1
+            },
             0x1F80_2000..=0x1F80_2FFF => {self.expansion_port_2.write_halfword(addr, data); 1},
             0x1FC0_0000..=0x1FC7_FFFF => 1, // BIOS
             _ => panic!("write invalid address {:X}", addr),
@@ -271,7 +400,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => (self.main_ram.read_word(addr & 0x1F_FFFF), 1),
             0x1F00_0000..=0x1F7F_FFFF => (self.expansion_port_1.read_word(addr), 1),
             0x1F80_0000..=0x1F80_03FF => (self.scratchpad.read_word(addr & 0x3FF), 1),
-            0x1F80_1000..=0x1F80_1FFF => (self.mut_io_device(addr).read_word(addr), 1),
+            0x1F80_1000..=0x1F80_1FFF => {
+                let data = self.mut_io_device(addr).read_word(addr);
+                self.log_io(addr, data as u32, IOAccessSize::Word, false);
+                (data, 1)
+            },
             0x1F80_2000..=0x1F80_2FFF => (self.expansion_port_2.read_word(addr), 1),
             0x1FC0_0000..=0x1FC7_FFFF => (self.bios.read_word(addr & 0x7_FFFF), 1),
             _ => panic!("read invalid address {:X}", addr),
@@ -284,7 +417,11 @@ impl Mem32 for MemBus {
             0x0000_0000..=0x007F_FFFF => {self.main_ram.write_word(addr & 0x1F_FFFF, data); 1},
             0x1F00_0000..=0x1F7F_FFFF => {self.expansion_port_1.write_word(addr, data); 1},
             0x1F80_0000..=0x1F80_03FF => {self.scratchpad.write_word(addr & 0x3FF, data); 1},
-            0x1F80_1000..=0x1F80_1FFF => {self.mut_io_device(addr).write_word(addr, data); 1},
+            0x1F80_1000..=0x1F80_1FFF => {
+                self.log_io(addr, data as u32, IOAccessSize::Word, true);
+                self.mut_io_device(addr).write_word(addr, data);
+                1
+            },
             0x1F80_2000..=0x1F80_2FFF => {self.expansion_port_2.write_word(addr, data); 1},
             0x1FC0_0000..=0x1FC7_FFFF => 1, // BIOS
             _ => panic!("write invalid address {:X}", addr),
@@ -295,9 +432,6 @@ impl Mem32 for MemBus {
 impl MemBus {
     /// Mutably reference an I/O device.
     fn mut_io_device<'a>(&'a mut self, addr: u32) -> &'a mut dyn MemInterface {
-        if addr != 0x1F801814 {
-            //println!("access I/O {:X}", addr);
-        }
         match addr {
             0x1F80_1000..=0x1F80_1023 => &mut self.control,
             0x1F80_1040..=0x1F80_104F => &mut self.peripheral,
@@ -314,6 +448,17 @@ impl MemBus {
         }
     }
 
+    /// Log an I/O access, if the logger is enabled.
+    #[inline]
+    fn log_io(&mut self, addr: u32, data: u32, size: IOAccessSize, write: bool) {
+        if let Some(logger) = self.io_logger.as_mut() {
+            if logger.filter(addr) {
+                let access = IOAccess { addr, data, size, write };
+                logger.log(&access, self.cdrom.port_index(), self.current_pc, self.cycle_count);
+            }
+        }
+    }
+
     /// Mutably reference a DMA device.
     fn mut_dma_device<'a>(&'a mut self, device: usize) -> &'a mut dyn DMADevice {
         match device {
@@ -0,0 +1,188 @@
+// Turbo and macros, applied to button input once per frame.
+
+use crate::{Button, utils::bits::*};
+use super::controller::ControllerState;
+
+/// A timed sequence of button states.
+#[derive(Clone, Debug, Default)]
+pub struct InputMacro {
+    pub steps: Vec<MacroStep>,
+}
+
+/// Buttons held for a number of frames (at least 1).
+/// Buttons not listed are left as the user holds them.
+#[derive(Clone, Debug)]
+pub struct MacroStep {
+    pub buttons: Vec<Button>,
+    pub frames: u32,
+}
+
+/// Button state for one controller.
+#[derive(Default)]
+pub struct ButtonInput {
+    /// Buttons held by the user.
+    held: u16,
+    /// Frame that each button was last pressed.
+    press_frame: [u64; 16],
+    /// Turbo rate of each button, in frames.
+    turbo: [Option<u32>; 16],
+    playback: Option<MacroPlayback>,
+    /// Buttons set by turbo or macro on the previous frame.
+    prev_managed: u16,
+    frame: u64,
+}
+
+struct MacroPlayback {
+    input_macro: InputMacro,
+    step: usize,
+    frames_left: u32,
+}
+
+impl ButtonInput {
+    pub fn press(&mut self, button: Button, pressed: bool) {
+        let index = button as usize;
+        if pressed && !test_bit!(self.held, index) {
+            self.press_frame[index] = self.frame;
+        }
+        if pressed {
+            self.held |= bit!(index);
+        } else {
+            self.held &= !bit!(index);
+        }
+    }
+
+    pub fn set_turbo(&mut self, button: Button, rate: Option<u32>) {
+        self.turbo[button as usize] = rate.map(|r| r.max(1));
+    }
+
+    pub fn play_macro(&mut self, input_macro: InputMacro) {
+        self.playback = input_macro.steps.first().map(|step| step.frames.max(1)).map(|frames_left| MacroPlayback {
+            frames_left,
+            step: 0,
+            input_macro,
+        });
+    }
+
+    pub fn stop_macro(&mut self) {
+        self.playback = None;
+    }
+
+    pub fn macro_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Advance by a frame and write the buttons affected by turbo and macros.
+    pub fn apply(&mut self, state: &mut ControllerState) {
+        let macro_buttons = self.playback.as_ref()
+            .map(|p| p.input_macro.steps[p.step].buttons.iter().fold(0, |acc, b| acc | bit!(*b as usize)))
+            .unwrap_or(0);
+        let mut turbo_buttons = 0;
+        let mut turbo_released = 0;
+        for (index, rate) in self.turbo.iter().enumerate() {
+            if let Some(rate) = rate {
+                turbo_buttons |= bit!(index);
+                let held_frames = self.frame - self.press_frame[index];
+                if (held_frames / (*rate as u64)) % 2 == 1 {
+                    turbo_released |= bit!(index);
+                }
+            }
+        }
+        let managed = turbo_buttons | macro_buttons | self.prev_managed;
+        let pressed = (self.held & !turbo_released) | macro_buttons;
+        state.set_buttons(managed, pressed);
+        self.prev_managed = turbo_buttons | macro_buttons;
+        self.frame += 1;
+        self.advance_macro();
+    }
+}
+
+// Internal
+impl ButtonInput {
+    fn advance_macro(&mut self) {
+        let Some(playback) = self.playback.as_mut() else {
+            return;
+        };
+        playback.frames_left -= 1;
+        if playback.frames_left == 0 {
+            playback.step += 1;
+            match playback.input_macro.steps.get(playback.step) {
+                Some(step) => playback.frames_left = step.frames.max(1),
+                None => self.playback = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ControllerType;
+
+    /// Run a frame, and return which of the buttons are pressed.
+    fn frame(input: &mut ButtonInput, state: &mut ControllerState, buttons: &[Button]) -> Vec<bool> {
+        input.apply(state);
+        let mut data = [0; 4];
+        state.get_binary(&mut data);
+        buttons.iter().map(|b| !test_bit!(data[1], *b as usize)).collect()
+    }
+
+    #[test]
+    fn turbo_period() {
+        let mut input = ButtonInput::default();
+        let mut state = ControllerState::new(ControllerType::Digital);
+        input.set_turbo(Button::Cross, Some(2));
+        input.press(Button::Cross, true);
+        let pressed = (0..8).map(|_| frame(&mut input, &mut state, &[Button::Cross])[0]).collect::<Vec<_>>();
+        assert_eq!(pressed, [true, true, false, false, true, true, false, false]);
+        // The period restarts with each press.
+        input.press(Button::Cross, false);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Cross]), [false]);
+        input.press(Button::Cross, true);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Cross]), [true]);
+    }
+
+    #[test]
+    fn macro_sequence() {
+        let mut input = ButtonInput::default();
+        let mut state = ControllerState::new(ControllerType::Digital);
+        state.press_button(Button::Square, true);
+        input.play_macro(InputMacro { steps: vec![
+            MacroStep { buttons: vec![Button::Cross], frames: 2 },
+            MacroStep { buttons: vec![Button::Circle], frames: 1 },
+            MacroStep { buttons: vec![], frames: 0 },
+        ]});
+        let buttons = [Button::Cross, Button::Circle, Button::Square];
+        // Buttons the user holds are left alone.
+        assert_eq!(frame(&mut input, &mut state, &buttons), [true, false, true]);
+        assert_eq!(frame(&mut input, &mut state, &buttons), [true, false, true]);
+        assert_eq!(frame(&mut input, &mut state, &buttons), [false, true, true]);
+        // Steps last at least a frame.
+        assert!(input.macro_playing());
+        assert_eq!(frame(&mut input, &mut state, &buttons), [false, false, true]);
+        assert!(!input.macro_playing());
+    }
+
+    #[test]
+    fn managed_buttons_are_released() {
+        let mut input = ButtonInput::default();
+        let mut state = ControllerState::new(ControllerType::Digital);
+        input.play_macro(InputMacro { steps: vec![MacroStep { buttons: vec![Button::Triangle], frames: 1 }] });
+        assert_eq!(frame(&mut input, &mut state, &[Button::Triangle]), [true]);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Triangle]), [false]);
+
+        // Turbo switched off during the released part of its period
+        // goes back to what the user holds.
+        input.set_turbo(Button::Cross, Some(1));
+        input.press(Button::Cross, true);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Cross]), [true]);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Cross]), [false]);
+        input.set_turbo(Button::Cross, None);
+        assert_eq!(frame(&mut input, &mut state, &[Button::Cross]), [true]);
+
+        // A stopped macro lets go of its buttons.
+        input.play_macro(InputMacro { steps: vec![MacroStep { buttons: vec![Button::Start], frames: 10 }] });
+        assert_eq!(frame(&mut input, &mut state, &[Button::Start]), [true]);
+        input.stop_macro();
+        assert_eq!(frame(&mut input, &mut state, &[Button::Start]), [false]);
+    }
+}
@@ -1,10 +1,23 @@
 use crate::{
     utils::bits::*,
-    ControllerType, Button, AnalogStickAxis
+    ControllerType, Button, Axis, AnalogStickAxis, MouseButton, LightGunButton
 };
 
-const DIGITAL_INFO: u16 = 0x5A41;
+pub const DIGITAL_INFO: u16 = 0x5A41;
 pub const ANALOG_INFO: u16 = 0x5A73;
+pub const JOYSTICK_INFO: u16 = 0x5A53;
+pub const NEGCON_INFO: u16 = 0x5A23;
+pub const MOUSE_INFO: u16 = 0x5A12;
+pub const GUNCON_INFO: u16 = 0x5A63;
+pub const JUSTIFIER_INFO: u16 = 0x5A31;
+
+/// GunCon counters when aiming off-screen.
+const GUNCON_OFFSCREEN: [u16; 2] = [0x0001, 0x000A];
+
+/// Mouse button bits: other bits are always set.
+const MOUSE_BUTTONS: u16 = 0xFCFF;
+const MOUSE_LEFT: u16 = bit!(11);
+const MOUSE_RIGHT: u16 = bit!(10);
 
 #[derive(Clone, Copy)]
 pub struct ControllerState {
@@ -12,23 +25,51 @@ pub struct ControllerState {
     buttons:     ControllerButtons,
     right_stick: Option<StickAxis>,
     left_stick:  Option<StickAxis>,
+    negcon:      Option<NeGconAxes>,
+    /// Mouse movement since the last frame.
+    mouse_delta: Option<(i32, i32)>,
+    /// Light gun aim, from 0 to 1 across the screen.
+    aim:         Option<(f32, f32)>,
 }
 
 impl ControllerState {
     pub fn new(controller: ControllerType) -> Self {
         match controller {
-            ControllerType::Digital => Self {
-                info: DIGITAL_INFO,
-                buttons: ControllerButtons::all(),
-                left_stick: None,
-                right_stick: None,
-            },
+            ControllerType::Digital => Self::with_info(DIGITAL_INFO),
             ControllerType::Analog => Self {
-                info: ANALOG_INFO,
-                buttons: ControllerButtons::all(),
                 left_stick: Some(StickAxis::default()),
                 right_stick: Some(StickAxis::default()),
-            }
+                ..Self::with_info(ANALOG_INFO)
+            },
+            ControllerType::AnalogJoystick => Self {
+                left_stick: Some(StickAxis::default()),
+                right_stick: Some(StickAxis::default()),
+                ..Self::with_info(JOYSTICK_INFO)
+            },
+            ControllerType::NeGcon => Self {
+                negcon: Some(NeGconAxes::default()),
+                ..Self::with_info(NEGCON_INFO)
+            },
+            ControllerType::Mouse => Self {
+                buttons: ControllerButtons::from_bits_retain(MOUSE_BUTTONS),
+                mouse_delta: Some((0, 0)),
+                ..Self::with_info(MOUSE_INFO)
+            },
+            ControllerType::GunCon => Self::with_info(GUNCON_INFO),
+            ControllerType::Justifier => Self::with_info(JUSTIFIER_INFO),
+        }
+    }
+
+    /// A controller with only buttons.
+    fn with_info(info: u16) -> Self {
+        Self {
+            info,
+            buttons: ControllerButtons::all(),
+            left_stick: None,
+            right_stick: None,
+            negcon: None,
+            mouse_delta: None,
+            aim: None,
         }
     }
 
@@ -37,28 +78,113 @@ impl ControllerState {
         self.buttons.set(ControllerButtons::from_bits_retain(bit), !pressed);
     }
 
-    pub fn update_stick_axis(&mut self, stick: AnalogStickAxis, value: f32) {
-        let int_val = ((value + 1.0) * 128.0).clamp(0.0, 255.0) as u8;
-        match stick {
-            AnalogStickAxis::LeftX => if let Some(stick) = self.left_stick.as_mut() {
-                stick.x = int_val;
+    /// Set the buttons in `mask` to pressed or released, according to `pressed`.
+    pub fn set_buttons(&mut self, mask: u16, pressed: u16) {
+        let bits = (self.buttons.bits() & !mask) | (!pressed & mask);
+        self.buttons = ControllerButtons::from_bits_retain(bits);
+    }
+
+    pub fn press_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        let bit = match button {
+            MouseButton::Left => MOUSE_LEFT,
+            MouseButton::Right => MOUSE_RIGHT,
+        };
+        self.buttons.set(ControllerButtons::from_bits_retain(bit), !pressed);
+    }
+
+    pub fn press_light_gun_button(&mut self, button: LightGunButton, pressed: bool) {
+        let button = match button {
+            LightGunButton::Trigger => ControllerButtons::Circle,
+            LightGunButton::A => ControllerButtons::Start,
+            LightGunButton::B => ControllerButtons::Cross,
+        };
+        self.buttons.set(button, !pressed);
+    }
+
+    /// Aim a light gun at a point on the screen, or off-screen if None.
+    pub fn aim_light_gun(&mut self, aim: Option<(f32, f32)>) {
+        self.aim = aim;
+    }
+
+    pub fn is_light_gun(&self) -> bool {
+        self.info == GUNCON_INFO || self.info == JUSTIFIER_INFO
+    }
+
+    pub fn light_gun_aim(&self) -> Option<(f32, f32)> {
+        self.aim
+    }
+
+    /// Add relative mouse movement.
+    pub fn move_mouse(&mut self, dx: i32, dy: i32) {
+        if let Some((x, y)) = self.mouse_delta.as_mut() {
+            *x = x.saturating_add(dx);
+            *y = y.saturating_add(dy);
+        }
+    }
+
+    pub fn mouse_delta(&self) -> Option<(i32, i32)> {
+        self.mouse_delta
+    }
+
+    /// Remove mouse movement once it has been passed to the console.
+    pub fn clear_mouse_delta(&mut self) {
+        if let Some(delta) = self.mouse_delta.as_mut() {
+            *delta = (0, 0);
+        }
+    }
+
+    /// Update an axis. Axes the controller doesn't have are ignored.
+    pub fn update_axis(&mut self, axis: Axis, value: f32) {
+        let centred = ((value + 1.0) * 128.0).clamp(0.0, 255.0) as u8;
+        let pressure = (value * 255.0).clamp(0.0, 255.0) as u8;
+        match axis {
+            Axis::LeftX => if let Some(stick) = self.left_stick.as_mut() {
+                stick.x = centred;
+            },
+            Axis::LeftY => if let Some(stick) = self.left_stick.as_mut() {
+                stick.y = centred;
+            },
+            Axis::RightX => if let Some(stick) = self.right_stick.as_mut() {
+                stick.x = centred;
             },
-            AnalogStickAxis::LeftY => if let Some(stick) = self.left_stick.as_mut() {
-                stick.y = int_val;
+            Axis::RightY => if let Some(stick) = self.right_stick.as_mut() {
+                stick.y = centred;
             },
-            AnalogStickAxis::RightX => if let Some(stick) = self.right_stick.as_mut() {
-                stick.x = int_val;
+            Axis::Twist => if let Some(negcon) = self.negcon.as_mut() {
+                negcon.twist = centred;
             },
-            AnalogStickAxis::RightY => if let Some(stick) = self.right_stick.as_mut() {
-                stick.y = int_val;
+            Axis::I => if let Some(negcon) = self.negcon.as_mut() {
+                negcon.i = pressure;
+            },
+            Axis::II => if let Some(negcon) = self.negcon.as_mut() {
+                negcon.ii = pressure;
+            },
+            Axis::L => if let Some(negcon) = self.negcon.as_mut() {
+                negcon.l = pressure;
             },
         }
     }
 
+    pub fn update_stick_axis(&mut self, stick: AnalogStickAxis, value: f32) {
+        self.update_axis(stick.into(), value);
+    }
+
     pub fn get_binary(&self, data: &mut [u16; 4]) {
         data[0] = self.info;
         data[1] = self.buttons.bits();
-        if let Some(right) = self.right_stick {
+        if self.info == GUNCON_INFO {
+            // Filled in with the beam position later.
+            data[2..].copy_from_slice(&GUNCON_OFFSCREEN);
+            return;
+        }
+        if let Some(negcon) = self.negcon {
+            data[2] = u16::from_le_bytes([negcon.twist, negcon.i]);
+            data[3] = u16::from_le_bytes([negcon.ii, negcon.l]);
+            return;
+        }
+        if let Some(delta) = self.mouse_delta {
+            data[2] = mouse_report(delta);
+        } else if let Some(right) = self.right_stick {
             data[2] = right.get_binary();
         } else {
             data[2] = 0x0000;
@@ -70,6 +196,15 @@ impl ControllerState {
         }
     }
 
+    pub fn has_sticks(&self) -> bool {
+        self.left_stick.is_some()
+    }
+
+    /// Returns true if this controller supports the DualShock config protocol.
+    pub fn is_dualshock(&self) -> bool {
+        self.info == ANALOG_INFO
+    }
+
     pub fn enable_analog_mode(&mut self, analog: bool) {
         if analog {
             self.info = ANALOG_INFO;
@@ -79,6 +214,36 @@ impl ControllerState {
     }
 }
 
+/// NeGcon analog inputs.
+#[derive(Clone, Copy)]
+struct NeGconAxes {
+    twist: u8,
+    i: u8,
+    ii: u8,
+    l: u8,
+}
+
+impl Default for NeGconAxes {
+    fn default() -> Self {
+        Self {
+            twist: 0x80,
+            i: 0x00,
+            ii: 0x00,
+            l: 0x00,
+        }
+    }
+}
+
+/// Mouse movement sent in a single report.
+pub fn clamp_delta(delta: i32) -> i8 {
+    delta.clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+/// Mouse movement bytes of a report.
+pub fn mouse_report((x, y): (i32, i32)) -> u16 {
+    u16::from_le_bytes([clamp_delta(x) as u8, clamp_delta(y) as u8])
+}
+
 #[derive(Clone, Copy)]
 pub struct StickAxis {
     x: u8,
@@ -121,3 +286,65 @@ bitflags::bitflags! {
         const Select    = bit!(0);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(state: &ControllerState) -> [u16; 4] {
+        let mut data = [0; 4];
+        state.get_binary(&mut data);
+        data
+    }
+
+    #[test]
+    fn negcon_report() {
+        let mut state = ControllerState::new(ControllerType::NeGcon);
+        // Twist is centred and the analog buttons are released.
+        assert_eq!(report(&state), [NEGCON_INFO, 0xFFFF, 0x0080, 0x0000]);
+        state.update_axis(Axis::Twist, -1.0);
+        state.update_axis(Axis::I, 1.0);
+        state.update_axis(Axis::II, 0.5);
+        state.update_axis(Axis::L, 0.25);
+        // Bytes are twist, I, II, L.
+        assert_eq!(report(&state)[2..], [0xFF00, 0x3F7F]);
+        state.update_axis(Axis::Twist, 1.0);
+        assert_eq!(report(&state)[2] & 0xFF, 0xFF);
+        state.update_axis(Axis::Twist, 0.0);
+        assert_eq!(report(&state)[2] & 0xFF, 0x80);
+    }
+
+    #[test]
+    fn negcon_pressure() {
+        let mut state = ControllerState::new(ControllerType::NeGcon);
+        for (value, expected) in [(0.0, 0x00), (0.5, 0x7F), (1.0, 0xFF), (-0.5, 0x00), (2.0, 0xFF)] {
+            state.update_axis(Axis::I, value);
+            assert_eq!(report(&state)[2] >> 8, expected, "pressure {value}");
+        }
+    }
+
+    #[test]
+    fn negcon_ignores_sticks() {
+        let mut state = ControllerState::new(ControllerType::NeGcon);
+        state.update_axis(Axis::LeftX, 1.0);
+        state.update_axis(Axis::RightY, -1.0);
+        assert_eq!(report(&state)[2..], [0x0080, 0x0000]);
+    }
+
+    #[test]
+    fn joystick_report() {
+        let mut state = ControllerState::new(ControllerType::AnalogJoystick);
+        assert_eq!(report(&state), [JOYSTICK_INFO, 0xFFFF, 0x8080, 0x8080]);
+        state.update_axis(Axis::RightX, 1.0);
+        state.update_axis(Axis::RightY, -1.0);
+        state.update_axis(Axis::LeftX, -1.0);
+        state.update_axis(Axis::LeftY, 0.5);
+        // Right stick X, Y, then left stick X, Y.
+        assert_eq!(report(&state)[2..], [0x00FF, 0xC000]);
+        // NeGcon axes don't apply.
+        state.update_axis(Axis::Twist, 1.0);
+        assert_eq!(report(&state)[2..], [0x00FF, 0xC000]);
+        // The joystick has no analog mode to switch into.
+        assert!(!state.is_dualshock());
+    }
+}
@@ -0,0 +1,73 @@
+// Safe writing and backups of memory card files.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime
+};
+
+/// Number of backups kept for each card.
+pub const MAX_BACKUPS: usize = 8;
+
+/// Write the whole file atomically.
+///
+/// The data is written to a temporary file which is then renamed
+/// over the target, so a crash will never leave a partly written file.
+pub fn atomic_write(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Copy the card file into its backup directory, with a timestamp,
+/// and remove the oldest backups beyond `MAX_BACKUPS`.
+///
+/// The backup directory is "<card file name>.backup" next to the card.
+pub fn backup(path: &Path) -> std::io::Result<PathBuf> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let backup_dir = path.with_file_name(format!("{}.backup", file_name));
+    std::fs::create_dir_all(&backup_dir)?;
+
+    let backup_path = backup_dir.join(format!("{}-{}", timestamp(), file_name));
+    std::fs::copy(path, &backup_path)?;
+
+    // Names start with the timestamp, so they sort oldest first.
+    let mut backups = std::fs::read_dir(&backup_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().ends_with(&file_name)))
+        .collect::<Vec<_>>();
+    backups.sort();
+    let excess = backups.len().saturating_sub(MAX_BACKUPS);
+    for old in &backups[..excess] {
+        std::fs::remove_file(old)?;
+    }
+    Ok(backup_path)
+}
+
+/// Current UTC time as "YYYYMMDD-HHMMSS".
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    // Convert days since epoch to a civil date.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, (time / 60) % 60, time % 60)
+}
@@ -0,0 +1,327 @@
+// Memory card filesystem: the directory and the saves it points to.
+
+use std::{
+    io::{Error, ErrorKind},
+    path::Path
+};
+
+use super::{
+    MEM_CARD_SIZE, format_new_memcard_data,
+    backup::atomic_write,
+    formats::{CardFormat, SaveFormat, RawSave},
+    sjis
+};
+
+pub const FRAME_SIZE: usize = 128;
+pub const BLOCK_SIZE: usize = 8 * 1024;
+/// Number of blocks available for saves. Block 0 holds the directory.
+pub const SAVE_BLOCKS: usize = 15;
+
+const ICON_SIZE: usize = 16;
+
+// Directory frame block states.
+const BLOCK_FREE: u8        = 0xA0;
+const BLOCK_FIRST: u8       = 0x51;
+const BLOCK_MIDDLE: u8      = 0x52;
+const BLOCK_LAST: u8        = 0x53;
+const NO_NEXT_BLOCK: u16    = 0xFFFF;
+
+/// Information about a single save on a card.
+#[derive(Clone, Debug)]
+pub struct SaveInfo {
+    /// Block the save begins in (1-15). Use this to refer to the save.
+    pub first_block: usize,
+    /// Number of blocks the save occupies.
+    pub block_count: usize,
+    /// Full file name, made up of the region, product code and identifier.
+    pub file_name: String,
+    /// E.g. "BA" for America, "BI" for Japan, "BE" for Europe.
+    pub region: String,
+    /// E.g. "SLUS-00594".
+    pub product_code: String,
+    pub identifier: String,
+    /// Decoded from the Shift-JIS title in the save header.
+    pub title: String,
+    /// Each frame is 16x16 RGBA.
+    pub icon_frames: Vec<Vec<u8>>,
+}
+
+/// A 128kB memory card image, for inspecting and editing saves.
+#[derive(Clone)]
+pub struct MemCardImage {
+    data: Vec<u8>,
+}
+
+impl MemCardImage {
+    /// Create a new formatted card.
+    pub fn new() -> Self {
+        let mut data = vec![0; MEM_CARD_SIZE];
+        format_new_memcard_data(&mut data);
+        Self { data }
+    }
+
+    /// Load a card from a file.
+    /// The format is chosen based on the file extension.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let format = CardFormat::from_path(path);
+        let file_data = std::fs::read(path)?;
+        Self::from_bytes(&file_data, format)
+    }
+
+    /// Write the card to a file.
+    /// The format is chosen based on the file extension.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let format = CardFormat::from_path(path);
+        atomic_write(path, &self.to_bytes(format))
+    }
+
+    pub fn from_bytes(file_data: &[u8], format: CardFormat) -> std::io::Result<Self> {
+        let data = format.extract_card(file_data)?;
+        if &data[0..2] != b"MC" {
+            return Err(Error::new(ErrorKind::InvalidData, "not a memory card image"));
+        }
+        Ok(Self { data })
+    }
+
+    pub fn to_bytes(&self, format: CardFormat) -> Vec<u8> {
+        format.wrap_card(&self.data)
+    }
+
+    /// Check the directory frame checksums.
+    /// Returns the indices of any frames that don't match.
+    pub fn verify(&self) -> Vec<usize> {
+        verify_directory(&self.data)
+    }
+
+    /// Get the raw 128kB card data.
+    pub fn raw_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// List all saves on the card.
+    pub fn saves(&self) -> Vec<SaveInfo> {
+        (1..=SAVE_BLOCKS)
+            .filter(|block| self.block_state(*block) == BLOCK_FIRST)
+            .map(|block| self.save_info(block))
+            .collect()
+    }
+
+    /// Number of blocks not used by any save.
+    pub fn free_blocks(&self) -> usize {
+        (1..=SAVE_BLOCKS).filter(|block| self.is_free(*block)).count()
+    }
+
+    /// Delete the save starting at the block.
+    ///
+    /// Like the BIOS, this only marks the blocks as free.
+    pub fn delete_save(&mut self, first_block: usize) -> std::io::Result<()> {
+        for block in self.save_chain(first_block)? {
+            let frame = self.dir_frame_mut(block);
+            // 0x5X => 0xAX
+            frame[0] = (frame[0] & 0x0F) | 0xA0;
+            update_frame_checksum(frame);
+        }
+        Ok(())
+    }
+
+    /// Export the save starting at the block.
+    pub fn export_save(&self, first_block: usize, format: SaveFormat) -> std::io::Result<Vec<u8>> {
+        let save = self.read_save(first_block)?;
+        Ok(format.wrap_save(&save, &self.save_title(first_block)))
+    }
+
+    /// Export the save starting at the block to a file.
+    /// The format is chosen based on the file extension.
+    pub fn export_save_to_file(&self, first_block: usize, path: &Path) -> std::io::Result<()> {
+        let format = SaveFormat::from_path(path).ok_or(Error::new(ErrorKind::InvalidInput, "unknown save file format"))?;
+        std::fs::write(path, self.export_save(first_block, format)?)
+    }
+
+    /// Import a save. Returns the first block it was written to.
+    pub fn import_save(&mut self, data: &[u8], format: SaveFormat) -> std::io::Result<usize> {
+        let save = format.extract_save(data)?;
+        self.write_save(&save)
+    }
+
+    /// Import a save from a file. Returns the first block it was written to.
+    /// The format is chosen based on the file extension.
+    pub fn import_save_from_file(&mut self, path: &Path) -> std::io::Result<usize> {
+        let format = SaveFormat::from_path(path).ok_or(Error::new(ErrorKind::InvalidInput, "unknown save file format"))?;
+        let data = std::fs::read(path)?;
+        self.import_save(&data, format)
+    }
+
+    /// Copy a save from this card to another.
+    /// Returns the first block it was written to on the destination card.
+    pub fn copy_save(&self, first_block: usize, dest: &mut MemCardImage) -> std::io::Result<usize> {
+        let save = self.read_save(first_block)?;
+        dest.write_save(&save)
+    }
+}
+
+// Internal
+impl MemCardImage {
+    fn dir_frame(&self, block: usize) -> &[u8] {
+        &self.data[(block * FRAME_SIZE)..((block + 1) * FRAME_SIZE)]
+    }
+
+    fn dir_frame_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data[(block * FRAME_SIZE)..((block + 1) * FRAME_SIZE)]
+    }
+
+    fn block_data(&self, block: usize) -> &[u8] {
+        &self.data[(block * BLOCK_SIZE)..((block + 1) * BLOCK_SIZE)]
+    }
+
+    fn block_state(&self, block: usize) -> u8 {
+        self.dir_frame(block)[0]
+    }
+
+    fn is_free(&self, block: usize) -> bool {
+        (self.block_state(block) & 0xF0) == BLOCK_FREE
+    }
+
+    fn next_block(&self, block: usize) -> u16 {
+        let frame = self.dir_frame(block);
+        u16::from_le_bytes([frame[8], frame[9]])
+    }
+
+    /// Get all the blocks that make up the save.
+    fn save_chain(&self, first_block: usize) -> std::io::Result<Vec<usize>> {
+        if first_block < 1 || first_block > SAVE_BLOCKS || self.block_state(first_block) != BLOCK_FIRST {
+            return Err(Error::new(ErrorKind::NotFound, format!("no save begins at block {}", first_block)));
+        }
+        let mut chain = vec![first_block];
+        let mut block = first_block;
+        while self.next_block(block) != NO_NEXT_BLOCK {
+            // Pointers count from 0, for block 1.
+            block = self.next_block(block) as usize + 1;
+            if block > SAVE_BLOCKS || chain.contains(&block) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("broken block chain in save at block {}", first_block)));
+            }
+            chain.push(block);
+        }
+        Ok(chain)
+    }
+
+    fn file_name(&self, block: usize) -> String {
+        let name = &self.dir_frame(block)[0x0A..0x1E];
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..len]).into_owned()
+    }
+
+    fn save_title(&self, first_block: usize) -> String {
+        sjis::decode(&self.block_data(first_block)[0x04..0x44])
+    }
+
+    fn save_info(&self, first_block: usize) -> SaveInfo {
+        let file_name = self.file_name(first_block);
+        let region = file_name.get(0..2).unwrap_or("").to_string();
+        let product_code = file_name.get(2..12).unwrap_or("").to_string();
+        let identifier = file_name.get(12..).unwrap_or("").to_string();
+        SaveInfo {
+            first_block,
+            block_count: self.save_chain(first_block).map(|c| c.len()).unwrap_or(1),
+            file_name,
+            region,
+            product_code,
+            identifier,
+            title: self.save_title(first_block),
+            icon_frames: self.icon_frames(first_block),
+        }
+    }
+
+    fn icon_frames(&self, first_block: usize) -> Vec<Vec<u8>> {
+        let header = self.block_data(first_block);
+        let frame_count = match header[0x02] {
+            0x11 => 1,
+            0x12 => 2,
+            0x13 => 3,
+            _ => 0,
+        };
+        let palette = (0..16).map(|i| {
+            let colour = u16::from_le_bytes([header[0x60 + i * 2], header[0x61 + i * 2]]);
+            let r = colour & 0x1F;
+            let g = (colour >> 5) & 0x1F;
+            let b = (colour >> 10) & 0x1F;
+            let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+            let alpha = if colour == 0 {0} else {0xFF};
+            [expand(r), expand(g), expand(b), alpha]
+        }).collect::<Vec<_>>();
+        (0..frame_count).map(|frame| {
+            let bitmap = &header[((frame + 1) * FRAME_SIZE)..((frame + 2) * FRAME_SIZE)];
+            let mut pixels = Vec::with_capacity(ICON_SIZE * ICON_SIZE * 4);
+            for byte in bitmap {
+                pixels.extend_from_slice(&palette[(byte & 0xF) as usize]);
+                pixels.extend_from_slice(&palette[(byte >> 4) as usize]);
+            }
+            pixels
+        }).collect()
+    }
+
+    fn read_save(&self, first_block: usize) -> std::io::Result<RawSave> {
+        let chain = self.save_chain(first_block)?;
+        let mut data = Vec::with_capacity(chain.len() * BLOCK_SIZE);
+        for block in chain.iter() {
+            data.extend_from_slice(self.block_data(*block));
+        }
+        let mut file_name = [0; 20];
+        file_name.copy_from_slice(&self.dir_frame(first_block)[0x0A..0x1E]);
+        Ok(RawSave { file_name, data })
+    }
+
+    fn write_save(&mut self, save: &RawSave) -> std::io::Result<usize> {
+        let block_count = save.data.len() / BLOCK_SIZE;
+        if block_count == 0 || save.data.len() % BLOCK_SIZE != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "save data is not a whole number of blocks"));
+        }
+        if self.saves().iter().any(|s| s.file_name.as_bytes() == save.name_bytes()) {
+            return Err(Error::new(ErrorKind::AlreadyExists, "a save with the same name already exists on the card"));
+        }
+        let free = (1..=SAVE_BLOCKS).filter(|block| self.is_free(*block)).collect::<Vec<_>>();
+        if free.len() < block_count {
+            return Err(Error::new(ErrorKind::StorageFull, format!("save needs {} blocks, but only {} are free", block_count, free.len())));
+        }
+        let blocks = &free[..block_count];
+        for (i, block) in blocks.iter().enumerate() {
+            let start = block * BLOCK_SIZE;
+            self.data[start..(start + BLOCK_SIZE)].copy_from_slice(&save.data[(i * BLOCK_SIZE)..((i + 1) * BLOCK_SIZE)]);
+
+            let next = blocks.get(i + 1).map(|b| (*b - 1) as u16).unwrap_or(NO_NEXT_BLOCK);
+            let frame = self.dir_frame_mut(*block);
+            frame.fill(0);
+            frame[0] = if i == 0 {
+                BLOCK_FIRST
+            } else if i == block_count - 1 {
+                BLOCK_LAST
+            } else {
+                BLOCK_MIDDLE
+            };
+            if i == 0 {
+                frame[4..8].copy_from_slice(&(save.data.len() as u32).to_le_bytes());
+                frame[0x0A..0x1E].copy_from_slice(&save.file_name);
+            }
+            frame[8..10].copy_from_slice(&next.to_le_bytes());
+            update_frame_checksum(frame);
+        }
+        Ok(blocks[0])
+    }
+}
+
+/// Check the header and directory frame checksums.
+/// Returns the indices of any frames that don't match.
+pub fn verify_directory(card: &[u8]) -> Vec<usize> {
+    (0..=SAVE_BLOCKS).filter(|i| {
+        let frame = &card[(i * FRAME_SIZE)..((i + 1) * FRAME_SIZE)];
+        frame[FRAME_SIZE - 1] != frame_checksum(frame)
+    }).collect()
+}
+
+/// XOR of the first 127 bytes of the frame, stored in the last byte.
+pub fn frame_checksum(frame: &[u8]) -> u8 {
+    frame[..(FRAME_SIZE - 1)].iter().fold(0, |acc, b| acc ^ b)
+}
+
+fn update_frame_checksum(frame: &mut [u8]) {
+    frame[FRAME_SIZE - 1] = frame_checksum(frame);
+}
@@ -0,0 +1,195 @@
+// File formats for whole memory cards and single saves.
+
+use std::{
+    io::{Error, ErrorKind},
+    path::Path
+};
+
+use super::{
+    MEM_CARD_SIZE,
+    directory::{FRAME_SIZE, BLOCK_SIZE, frame_checksum}
+};
+
+const GME_HEADER_SIZE: usize = 0xF40;
+const GME_MAGIC: &[u8] = b"123-456-STD";
+const VMP_HEADER_SIZE: usize = 0x80;
+const VMP_MAGIC: &[u8] = b"\0PMV";
+
+const MCS_HEADER_SIZE: usize = FRAME_SIZE;
+const PSV_HEADER_SIZE: usize = 0x84;
+const PSV_MAGIC: &[u8] = b"\0VSP";
+const MCB_HEADER_SIZE: usize = 54;
+
+/// Formats for full memory card images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardFormat {
+    /// Raw 128kB image (.mcr, .mcd, .srm, etc.)
+    Raw,
+    /// DexDrive image (.gme)
+    DexDrive,
+    /// PSP virtual memory card (.vmp)
+    ///
+    /// The PSP signature is not generated, so exported cards
+    /// will work in emulators but not on PSP firmware.
+    VMP,
+}
+
+impl CardFormat {
+    /// Find the format from a file extension.
+    /// Anything unknown is treated as raw.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("gme") => CardFormat::DexDrive,
+            Some("vmp") => CardFormat::VMP,
+            _ => CardFormat::Raw,
+        }
+    }
+
+    pub(super) fn extract_card(&self, file_data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let header_size = match self {
+            CardFormat::Raw => 0,
+            CardFormat::DexDrive => {
+                if !file_data.starts_with(GME_MAGIC) {
+                    return Err(Error::new(ErrorKind::InvalidData, "missing DexDrive header"));
+                }
+                GME_HEADER_SIZE
+            },
+            CardFormat::VMP => {
+                if !file_data.starts_with(VMP_MAGIC) {
+                    return Err(Error::new(ErrorKind::InvalidData, "missing VMP header"));
+                }
+                VMP_HEADER_SIZE
+            },
+        };
+        if file_data.len() != header_size + MEM_CARD_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("card file should be {} bytes, found {}", header_size + MEM_CARD_SIZE, file_data.len())));
+        }
+        Ok(file_data[header_size..].to_vec())
+    }
+
+    pub(super) fn wrap_card(&self, card: &[u8]) -> Vec<u8> {
+        let mut header = match self {
+            CardFormat::Raw => Vec::new(),
+            CardFormat::DexDrive => {
+                let mut header = vec![0; GME_HEADER_SIZE];
+                header[..GME_MAGIC.len()].copy_from_slice(GME_MAGIC);
+                header[18] = 0x01;
+                header[20] = 0x01;
+                header[21] = b'M';
+                // Copies of the directory state and next block bytes.
+                for i in 0..15 {
+                    let frame = &card[((i + 1) * FRAME_SIZE)..];
+                    header[22 + i] = frame[0];
+                    header[38 + i] = frame[8];
+                }
+                header
+            },
+            CardFormat::VMP => {
+                let mut header = vec![0; VMP_HEADER_SIZE];
+                header[..VMP_MAGIC.len()].copy_from_slice(VMP_MAGIC);
+                header[4] = VMP_HEADER_SIZE as u8;
+                header
+            },
+        };
+        header.extend_from_slice(card);
+        header
+    }
+}
+
+/// Formats for single saves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// PSXGameEdit (.mcs): directory frame followed by the blocks.
+    MCS,
+    /// PS3 virtual save (.psv)
+    ///
+    /// The PS3 signature is not generated, so exported saves
+    /// will work in emulators but not on PS3 firmware.
+    PSV,
+    /// Smart Link (.mcb): Action Replay style header followed by the blocks.
+    MCB,
+}
+
+impl SaveFormat {
+    /// Find the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("mcs") => Some(SaveFormat::MCS),
+            Some("psv") => Some(SaveFormat::PSV),
+            Some("mcb") => Some(SaveFormat::MCB),
+            _ => None,
+        }
+    }
+
+    pub(super) fn extract_save(&self, file_data: &[u8]) -> std::io::Result<RawSave> {
+        let (header_size, name_offset, magic) = match self {
+            SaveFormat::MCS => (MCS_HEADER_SIZE, 0x0A, None),
+            SaveFormat::PSV => (PSV_HEADER_SIZE, 0x64, Some(PSV_MAGIC)),
+            SaveFormat::MCB => (MCB_HEADER_SIZE, 0x00, None),
+        };
+        if let Some(magic) = magic {
+            if !file_data.starts_with(magic) {
+                return Err(Error::new(ErrorKind::InvalidData, "missing save header"));
+            }
+        }
+        if file_data.len() <= header_size || (file_data.len() - header_size) % BLOCK_SIZE != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "save data is not a whole number of blocks"));
+        }
+        let mut file_name = [0; 20];
+        file_name.copy_from_slice(&file_data[name_offset..(name_offset + 20)]);
+        Ok(RawSave {
+            file_name,
+            data: file_data[header_size..].to_vec(),
+        })
+    }
+
+    pub(super) fn wrap_save(&self, save: &RawSave, title: &str) -> Vec<u8> {
+        let mut header = match self {
+            SaveFormat::MCS => {
+                let mut header = vec![0; MCS_HEADER_SIZE];
+                header[0] = 0x51;
+                header[4..8].copy_from_slice(&(save.data.len() as u32).to_le_bytes());
+                header[8..10].copy_from_slice(&0xFFFF_u16.to_le_bytes());
+                header[0x0A..0x1E].copy_from_slice(&save.file_name);
+                header[MCS_HEADER_SIZE - 1] = frame_checksum(&header);
+                header
+            },
+            SaveFormat::PSV => {
+                let mut header = vec![0; PSV_HEADER_SIZE];
+                header[..PSV_MAGIC.len()].copy_from_slice(PSV_MAGIC);
+                header[0x38..0x3C].copy_from_slice(&0x14_u32.to_le_bytes()); // PS1 save
+                header[0x3C..0x40].copy_from_slice(&1_u32.to_le_bytes());
+                header[0x40..0x44].copy_from_slice(&(save.data.len() as u32).to_le_bytes());
+                header[0x44..0x48].copy_from_slice(&(PSV_HEADER_SIZE as u32).to_le_bytes());
+                header[0x48..0x4C].copy_from_slice(&0x200_u32.to_le_bytes());
+                header[0x64..0x78].copy_from_slice(&save.file_name);
+                header
+            },
+            SaveFormat::MCB => {
+                let mut header = vec![0; MCB_HEADER_SIZE];
+                header[0..20].copy_from_slice(&save.file_name);
+                for (i, c) in title.chars().filter(|c| c.is_ascii()).take(32).enumerate() {
+                    header[21 + i] = c as u8;
+                }
+                header
+            },
+        };
+        header.extend_from_slice(&save.data);
+        header
+    }
+}
+
+/// A save, as it is stored on the card.
+pub(super) struct RawSave {
+    pub file_name: [u8; 20],
+    /// All blocks, in order.
+    pub data: Vec<u8>,
+}
+
+impl RawSave {
+    /// File name without null padding.
+    pub fn name_bytes(&self) -> &[u8] {
+        let len = self.file_name.iter().position(|b| *b == 0).unwrap_or(self.file_name.len());
+        &self.file_name[..len]
+    }
+}
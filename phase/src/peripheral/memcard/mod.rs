@@ -1,27 +1,40 @@
+mod backup;
+mod directory;
+mod formats;
+mod sjis;
+#[cfg(test)]
+mod test;
+
 use std::{
-    fs::File,
-    io::{
-        Read,
-        Write,
-        Seek,
-        SeekFrom
-    },
-    path::Path
+    path::{Path, PathBuf},
+    time::{Duration, Instant}
 };
 
+use crate::event::{Event, EventSender, LogLevel, Subsystem};
+
+pub use directory::{MemCardImage, SaveInfo, SAVE_BLOCKS};
+pub use formats::{CardFormat, SaveFormat};
+
 const MEM_CARD_SIZE: usize = 128 * 1024;
 const ID_1: u8 = 0x5A;
 const ID_2: u8 = 0x5D;
 const CMD_ACK_1: u8 = 0x5C;
 const CMD_ACK_2: u8 = 0x5D;
 const MEM_CARD_ID: [u8; 4] = [0x04, 0x00, 0x00, 0x80];
+const SECTOR_SIZE: usize = 128;
+/// Minimum time between backups of a card while it is in use.
+const BACKUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 /// A 128kB PSX memory card, backed by a file.
 pub struct MemoryCard {
-    file: File,
+    path: PathBuf,
 
     buffer: Vec<u8>,
     dirty: bool,
+    /// Time of the last backup made this session.
+    last_backup: Option<Instant>,
+    /// Incoming sector data, committed once the write is verified.
+    write_buffer: [u8; SECTOR_SIZE],
 
     mode: Option<MemoryCardMode>,
     flag: u8,
@@ -32,34 +45,36 @@ pub struct MemoryCard {
 
     sector_ok: bool,
     checksum_ok: bool,
+
+    events: EventSender,
 }
 
 impl MemoryCard {
-    pub fn new(path: &Path) -> std::io::Result<Self> {
-        let mut buffer = vec![0; MEM_CARD_SIZE];
-        let (file, dirty) = if std::fs::exists(path)? {
-            println!("Existing memory card found at {}", path.to_str().unwrap());
-            let mut file = File::options()
-                .read(true)
-                .write(true)
-                .open(path)?;
-            file.read_exact(&mut buffer)?;
-            (file, false)
+    pub fn new(path: &Path, events: &EventSender) -> std::io::Result<Self> {
+        let (buffer, dirty) = if std::fs::exists(path)? {
+            events.log(LogLevel::Info, Subsystem::MemCard, format_args!("Existing memory card found at {}", path.display()));
+            let buffer = std::fs::read(path)?;
+            if buffer.len() != MEM_CARD_SIZE {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("memory card should be {} bytes, found {}", MEM_CARD_SIZE, buffer.len())));
+            }
+            let bad_frames = directory::verify_directory(&buffer);
+            if !bad_frames.is_empty() {
+                events.send(Event::MemCardChecksumMismatch { path: path.to_path_buf(), frames: bad_frames });
+            }
+            (buffer, false)
         } else {
-            println!("Create new memory card at {}", path.to_str().unwrap());
+            events.log(LogLevel::Info, Subsystem::MemCard, format_args!("Create new memory card at {}", path.display()));
+            let mut buffer = vec![0; MEM_CARD_SIZE];
             format_new_memcard_data(&mut buffer);
-            let file = File::options()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(path)?;
-            (file, true)
+            (buffer, true)
         };
         Ok(Self {
-            file,
+            path: path.to_path_buf(),
 
             buffer,
             dirty,
+            last_backup: None,
+            write_buffer: [0; SECTOR_SIZE],
 
             mode: None,
             flag: 0x08,
@@ -70,16 +85,30 @@ impl MemoryCard {
 
             sector_ok: false,
             checksum_ok: false,
+
+            events: events.clone(),
         })
     }
 
     /// Flush the internal buffer to disk.
-    pub fn flush(&mut self) {
-        if self.dirty {
-            self.file.seek(SeekFrom::Start(0)).expect("could not seek mem card");
-            self.file.write(&self.buffer).expect("could not write mem card");
-            self.dirty = false;
+    /// 
+    /// The previous file is backed up first, at most once every `BACKUP_INTERVAL`.
+    /// The file is replaced atomically.
+    /// 
+    /// Returns true if anything was written.
+    pub fn flush(&mut self) -> std::io::Result<bool> {
+        if !self.dirty {
+            return Ok(false);
+        }
+        let backup_due = self.last_backup.is_none_or(|t| t.elapsed() >= BACKUP_INTERVAL);
+        if backup_due && self.path.exists() {
+            let backup_path = backup::backup(&self.path)?;
+            self.events.log(LogLevel::Debug, Subsystem::MemCard, format_args!("Backed up memory card to {}", backup_path.display()));
+            self.last_backup = Some(Instant::now());
         }
+        backup::atomic_write(&self.path, &self.buffer)?;
+        self.dirty = false;
+        Ok(true)
     }
 
     /// Transfer a byte to the memory card, and receive a byte.
@@ -164,7 +193,7 @@ impl MemoryCard {
                     132 => {
                         self.checksum_ok = self.read_checksum() == data_in;
                         if !self.checksum_ok {
-                            panic!("checksum provided to mem card does not match");
+                            self.events.log(LogLevel::Warn, Subsystem::MemCard, format_args!("Rejected write to sector {:X}: checksum mismatch", self.sector_addr));
                         }
                         0x00
                     },
@@ -178,7 +207,7 @@ impl MemoryCard {
                         } else if !self.sector_ok {
                             0xFF
                         } else {
-                            self.dirty = true;
+                            self.commit_write();
                             0x47 // 'G' = Good
                         }
                     }
@@ -202,6 +231,13 @@ impl MemoryCard {
     }
 }
 
+impl Drop for MemoryCard {
+    /// Make sure data isn't lost if the emulator shuts down
+    /// (or the CPU thread panics) before the next flush.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 enum MemoryCardMode {
@@ -265,11 +301,18 @@ impl MemoryCard {
     }
 
     fn write_data(&mut self, data: u8) {
-        self.buffer[self.byte_addr] = data;
+        self.write_buffer[self.byte_addr % SECTOR_SIZE] = data;
         self.byte_addr += 1;
         self.checksum ^= data;
     }
 
+    /// Copy the verified sector into the card.
+    fn commit_write(&mut self) {
+        let start = (self.sector_addr as usize) * SECTOR_SIZE;
+        self.buffer[start..(start + SECTOR_SIZE)].copy_from_slice(&self.write_buffer);
+        self.dirty = true;
+    }
+
     fn read_checksum(&mut self) -> u8 {
         self.checksum
     }
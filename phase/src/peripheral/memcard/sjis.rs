@@ -0,0 +1,91 @@
+// Minimal Shift-JIS decoding for save titles.
+
+/// Decode a Shift-JIS string, stopping at the first null.
+///
+/// Full-width ASCII (which most titles use) is converted to plain ASCII.
+/// Kana and common punctuation are decoded, and anything else
+/// (i.e. kanji) is replaced with U+FFFD.
+pub fn decode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        match byte {
+            0x00 => break,
+            0x01..=0x7F => {
+                out.push(byte as char);
+                i += 1;
+            },
+            0xA1..=0xDF => {
+                // Half-width katakana.
+                out.push(char::from_u32(0xFF61 + (byte - 0xA1) as u32).unwrap());
+                i += 1;
+            },
+            0x81..=0x9F | 0xE0..=0xFC if i + 1 < data.len() => {
+                let code = ((byte as u16) << 8) | (data[i + 1] as u16);
+                out.push(decode_double(code));
+                i += 2;
+            },
+            _ => {
+                out.push(char::REPLACEMENT_CHARACTER);
+                i += 1;
+            }
+        }
+    }
+    out.trim_end().to_string()
+}
+
+fn decode_double(code: u16) -> char {
+    let unicode = match code {
+        0x8140 => ' ' as u32,
+        0x8141 => 0x3001, // 、
+        0x8142 => 0x3002, // 。
+        0x8143 => ',' as u32,
+        0x8144 => '.' as u32,
+        0x8145 => 0x30FB, // ・
+        0x8146 => ':' as u32,
+        0x8147 => ';' as u32,
+        0x8148 => '?' as u32,
+        0x8149 => '!' as u32,
+        0x814F => '^' as u32,
+        0x8151 => '_' as u32,
+        0x815B => 0x30FC, // ー
+        0x815C | 0x815D | 0x817C => '-' as u32,
+        0x815E => '/' as u32,
+        0x815F => '\\' as u32,
+        0x8160 => '~' as u32,
+        0x8162 => '|' as u32,
+        0x8165 | 0x8166 => '\'' as u32,
+        0x8167 | 0x8168 => '"' as u32,
+        0x8169 => '(' as u32,
+        0x816A => ')' as u32,
+        0x816D => '[' as u32,
+        0x816E => ']' as u32,
+        0x816F => '{' as u32,
+        0x8170 => '}' as u32,
+        0x8175 => 0x300C, // 「
+        0x8176 => 0x300D, // 」
+        0x8177 => 0x300E, // 『
+        0x8178 => 0x300F, // 』
+        0x817B => '+' as u32,
+        0x817E => 0xD7, // ×
+        0x8181 => '=' as u32,
+        0x8183 => '<' as u32,
+        0x8184 => '>' as u32,
+        0x818F => 0xA5, // ¥
+        0x8190 => '$' as u32,
+        0x8193 => '%' as u32,
+        0x8194 => '#' as u32,
+        0x8195 => '&' as u32,
+        0x8196 => '*' as u32,
+        0x8197 => '@' as u32,
+        0x824F..=0x8258 => ('0' as u32) + (code - 0x824F) as u32,
+        0x8260..=0x8279 => ('A' as u32) + (code - 0x8260) as u32,
+        0x8281..=0x829A => ('a' as u32) + (code - 0x8281) as u32,
+        0x829F..=0x82F1 => 0x3041 + (code - 0x829F) as u32, // Hiragana
+        0x8340..=0x837E => 0x30A1 + (code - 0x8340) as u32, // Katakana
+        0x8380..=0x8396 => 0x30A1 + (code - 0x8341) as u32,
+        _ => 0xFFFD,
+    };
+    char::from_u32(unicode).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
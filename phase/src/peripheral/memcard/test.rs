@@ -0,0 +1,157 @@
+use super::*;
+use directory::BLOCK_SIZE;
+
+/// Make a save with a recognisable header and data.
+fn make_save(name: &[u8], blocks: usize) -> Vec<u8> {
+    let mut data = vec![0; BLOCK_SIZE * blocks];
+    data[0..2].copy_from_slice(b"SC");
+    data[2] = 0x11;
+    data[3] = blocks as u8;
+    // "ＡＢ1" in Shift-JIS.
+    data[4..9].copy_from_slice(&[0x82, 0x60, 0x82, 0x61, b'1']);
+    for (i, byte) in data.iter_mut().enumerate().skip(0x200) {
+        *byte = i as u8;
+    }
+    let mut file = vec![0; 54];
+    file[..name.len()].copy_from_slice(name);
+    file.extend_from_slice(&data);
+    file
+}
+
+#[test]
+fn import_and_list() {
+    let mut card = MemCardImage::new();
+    assert_eq!(card.free_blocks(), SAVE_BLOCKS);
+    let first = card.import_save(&make_save(b"BASLUS-00001GAME", 3), SaveFormat::MCB).unwrap();
+    assert_eq!(first, 1);
+    assert_eq!(card.free_blocks(), SAVE_BLOCKS - 3);
+
+    let saves = card.saves();
+    assert_eq!(saves.len(), 1);
+    assert_eq!(saves[0].block_count, 3);
+    assert_eq!(saves[0].region, "BA");
+    assert_eq!(saves[0].product_code, "SLUS-00001");
+    assert_eq!(saves[0].identifier, "GAME");
+    assert_eq!(saves[0].title, "AB1");
+    assert_eq!(saves[0].icon_frames.len(), 1);
+}
+
+#[test]
+fn save_format_round_trip() {
+    let mut card = MemCardImage::new();
+    let first = card.import_save(&make_save(b"BISLPS-00002SAVE", 2), SaveFormat::MCB).unwrap();
+    for format in [SaveFormat::MCS, SaveFormat::PSV, SaveFormat::MCB] {
+        let exported = card.export_save(first, format).unwrap();
+        let mut other = MemCardImage::new();
+        let other_first = other.import_save(&exported, format).unwrap();
+        assert_eq!(other.export_save(other_first, format).unwrap(), exported);
+        assert_eq!(other.saves()[0].file_name, "BISLPS-00002SAVE");
+    }
+}
+
+#[test]
+fn delete_and_copy() {
+    let mut card = MemCardImage::new();
+    let a = card.import_save(&make_save(b"BASLUS-00003A", 2), SaveFormat::MCB).unwrap();
+    let b = card.import_save(&make_save(b"BASLUS-00003B", 1), SaveFormat::MCB).unwrap();
+    assert!(card.import_save(&make_save(b"BASLUS-00003B", 1), SaveFormat::MCB).is_err());
+
+    card.delete_save(a).unwrap();
+    assert_eq!(card.saves().len(), 1);
+    assert_eq!(card.free_blocks(), SAVE_BLOCKS - 1);
+
+    let mut dest = MemCardImage::new();
+    card.copy_save(b, &mut dest).unwrap();
+    assert_eq!(dest.saves()[0].file_name, "BASLUS-00003B");
+}
+
+#[test]
+fn card_format_round_trip() {
+    let mut card = MemCardImage::new();
+    card.import_save(&make_save(b"BESLES-00004X", 4), SaveFormat::MCB).unwrap();
+    for format in [CardFormat::Raw, CardFormat::DexDrive, CardFormat::VMP] {
+        let bytes = card.to_bytes(format);
+        let loaded = MemCardImage::from_bytes(&bytes, format).unwrap();
+        assert_eq!(loaded.raw_data(), card.raw_data());
+    }
+}
+
+#[test]
+fn verify_checksums() {
+    let mut card = MemCardImage::new();
+    card.import_save(&make_save(b"BASLUS-00005V", 1), SaveFormat::MCB).unwrap();
+    assert!(card.verify().is_empty());
+
+    let mut data = card.raw_data().to_vec();
+    data[directory::FRAME_SIZE + 0x0A] ^= 0xFF;
+    assert_eq!(directory::verify_directory(&data), vec![1]);
+}
+
+/// A new empty test directory.
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("phase-memcard-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn backups_are_rotated() {
+    let dir = temp_dir("rotate");
+    let path = dir.join("card.mcd");
+    std::fs::write(&path, b"new").unwrap();
+    let backup_dir = dir.join("card.mcd.backup");
+    std::fs::create_dir_all(&backup_dir).unwrap();
+    for i in 0..10 {
+        std::fs::write(backup_dir.join(format!("20000101-0000{:02}-card.mcd", i)), b"old").unwrap();
+    }
+    // Backups of other cards are left alone.
+    std::fs::write(backup_dir.join("20000101-000000-other.mcd"), b"other").unwrap();
+
+    let backup_path = backup::backup(&path).unwrap();
+    assert_eq!(std::fs::read(&backup_path).unwrap(), b"new");
+    let mut names = std::fs::read_dir(&backup_dir).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names.len(), backup::MAX_BACKUPS + 1);
+    // The oldest three went.
+    assert_eq!(names[0], "20000101-000000-other.mcd");
+    assert_eq!(names[1], "20000101-000003-card.mcd");
+    assert!(names.contains(&backup_path.file_name().unwrap().to_string_lossy().into_owned()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn atomic_write_over_leftover_temp() {
+    let dir = temp_dir("atomic");
+    let path = dir.join("card.mcd");
+    std::fs::write(&path, b"old card").unwrap();
+    // Left by a crash part way through a write.
+    let tmp_path = dir.join("card.mcd.tmp");
+    std::fs::write(&tmp_path, b"half written data").unwrap();
+
+    backup::atomic_write(&path, b"card").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"card");
+    assert!(!tmp_path.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn load_reports_bad_checksums() {
+    let dir = temp_dir("checksum");
+    let path = dir.join("card.mcd");
+    let mut data = MemCardImage::new().raw_data().to_vec();
+    data[2 * directory::FRAME_SIZE + 0x0A] ^= 0xFF;
+    std::fs::write(&path, &data).unwrap();
+
+    let (events, receiver) = crate::event::event_channel();
+    let card = MemoryCard::new(&path, &events).unwrap();
+    let frames = receiver.poll().find_map(|event| match event {
+        Event::MemCardChecksumMismatch { path: bad_path, frames } if bad_path == path => Some(frames),
+        _ => None,
+    });
+    assert_eq!(frames, Some(vec![2]));
+    drop(card);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,11 +1,16 @@
 pub mod controller;
-mod memcard;
+pub mod buttons;
+pub mod memcard;
+pub mod stick;
+#[cfg(test)]
+mod test;
 
 use std::collections::VecDeque;
 use std::path::Path;
 
 use crate::{
-    Port, interrupt::Interrupt, utils::{bits::*, interface::MemInterface}
+    Port, Slot, interrupt::Interrupt, gpu::BeamPosition, utils::{bits::*, interface::MemInterface},
+    event::{Event, EventSender, LogLevel, Subsystem}
 };
 
 use controller::ControllerState;
@@ -19,24 +24,22 @@ pub struct PeripheralPort {
     baudrate_reload: u32,
     baudrate_timer: u32,
 
-    slot_select: u8,
     in_fifo: VecDeque<u8>,
     out_fifo: VecDeque<u8>,
     transfer_mode: TransferMode,
     transfer_active: bool,
     irq_latch: bool,
-    multitap_mem_card: bool,
+    /// Multitap slot addressed by the current transfer.
+    selected_slot: usize,
 
     // Devices:
-    port_1_controller: ControllerData,
-    port_2_controller: ControllerData,
+    ports: [PortDevices; 2],
 
-    port_1_mem_card: Option<MemoryCard>,
-    port_2_mem_card: Option<MemoryCard>,
+    events: EventSender,
 }
 
 impl PeripheralPort {
-    pub fn new() -> Self {
+    pub fn new(events: EventSender) -> Self {
         Self {
             status: JoypadStatus::empty(),
             mode: JoypadMode::empty(),
@@ -44,19 +47,16 @@ impl PeripheralPort {
             baudrate_reload: 0,
             baudrate_timer: 0,
 
-            slot_select: 0,
             in_fifo: VecDeque::new(),
             out_fifo: VecDeque::new(),
             transfer_mode: TransferMode::None,
             transfer_active: false,
             irq_latch: false,
-            multitap_mem_card: false,
+            selected_slot: 0,
 
-            port_1_controller: ControllerData::new(),
-            port_2_controller: ControllerData::new(),
+            ports: [PortDevices::new(), PortDevices::new()],
 
-            port_1_mem_card: None,
-            port_2_mem_card: None,
+            events,
         }
     }
 
@@ -79,41 +79,89 @@ impl PeripheralPort {
         }
     }
 
-    pub fn set_controller_state(&mut self, port: Port, state: ControllerState) {
-        match port {
-            Port::One => state.get_binary(&mut self.port_1_controller.output_data),
-            Port::Two => state.get_binary(&mut self.port_2_controller.output_data),
-        }
+    /// Plug in a new controller, resetting its mode and motors.
+    pub fn connect_controller(&mut self, port: Port, slot: Slot, state: ControllerState) {
+        let controller = &mut self.ports[port as usize].controllers[slot as usize];
+        *controller = ControllerData::new();
+        controller.dualshock = state.is_dualshock();
+        controller.analog_mode = controller.dualshock;
+        controller.set_state(&state);
+    }
+
+    pub fn set_controller_state(&mut self, port: Port, slot: Slot, state: ControllerState) {
+        self.ports[port as usize].controllers[slot as usize].set_state(&state);
     }
 
-    pub fn clear_controller_state(&mut self, port: Port) {
-        match port {
-            Port::One => self.port_1_controller.output_data.fill(0xFFFF),// TODO: analog = 0?
-            Port::Two => self.port_1_controller.output_data.fill(0xFFFF),// TODO: analog = 0?
+    pub fn clear_controller_state(&mut self, port: Port, slot: Slot) {
+        self.ports[port as usize].controllers[slot as usize] = ControllerData::new(); // TODO: analog = 0?
+    }
+
+    /// Set where a light gun is pointing, or None if off-screen.
+    pub fn set_light_gun_beam(&mut self, port: Port, slot: Slot, beam: Option<BeamPosition>) {
+        let controller = &mut self.ports[port as usize].controllers[slot as usize];
+        controller.beam = beam;
+        if controller.output_data[0] == controller::GUNCON_INFO {
+            if let Some((x, y)) = beam.map(|b| b.guncon_counters()) {
+                controller.output_data[2] = x;
+                controller.output_data[3] = y;
+            }
         }
     }
 
-    pub fn insert_mem_card(&mut self, port: Port, path: &Path) -> std::io::Result<()> {
-        match port {
-            Port::One => self.port_1_mem_card = Some(MemoryCard::new(path)?),
-            Port::Two => self.port_2_mem_card = Some(MemoryCard::new(path)?),
+    /// Where the lightpen interrupt should be raised, if a Justifier
+    /// has enabled it and is aimed at the screen.
+    pub fn lightpen_target(&self) -> Option<BeamPosition> {
+        self.ports.iter()
+            .flat_map(|p| p.controllers.iter())
+            .filter(|c| c.output_data[0] == controller::JUSTIFIER_INFO && c.lightpen_enabled)
+            .find_map(|c| c.beam)
+    }
+
+    /// Toggle between digital and analog mode, as if pressing the
+    /// analog button. Has no effect if the game has locked the mode.
+    pub fn toggle_analog_mode(&mut self, port: Port, slot: Slot) {
+        let controller = &mut self.ports[port as usize].controllers[slot as usize];
+        if controller.dualshock && !controller.analog_locked {
+            controller.set_analog_mode(!controller.analog_mode);
         }
+    }
+
+    /// Connect or disconnect a multitap.
+    /// Devices in slots B-D are only visible while a multitap is connected.
+    pub fn set_multitap(&mut self, port: Port, connected: bool) {
+        let devices = &mut self.ports[port as usize];
+        devices.multitap = connected;
+        devices.tap_mode = false;
+    }
+
+    /// Insert a memory card. Any existing card in the slot is flushed first.
+    pub fn insert_mem_card(&mut self, port: Port, slot: Slot, path: &Path) -> std::io::Result<()> {
+        // The old card might be backed by the same file.
+        self.flush_mem_card(port, slot);
+        let mem_card = MemoryCard::new(path, &self.events)?;
+        self.ports[port as usize].mem_cards[slot as usize] = Some(mem_card);
         Ok(())
     }
 
-    pub fn remove_mem_card(&mut self, port: Port) {
-        match port {
-            Port::One => self.port_1_mem_card = None,
-            Port::Two => self.port_2_mem_card = None,
-        }
+    pub fn remove_mem_card(&mut self, port: Port, slot: Slot) {
+        self.flush_mem_card(port, slot);
+        self.ports[port as usize].mem_cards[slot as usize] = None;
     }
 
     pub fn flush_mem_cards(&mut self) {
-        if let Some(mem_card) = self.port_1_mem_card.as_mut() {
-            mem_card.flush();
+        for port in [Port::One, Port::Two] {
+            for slot in Slot::ALL {
+                self.flush_mem_card(port, slot);
+            }
         }
-        if let Some(mem_card) = self.port_2_mem_card.as_mut() {
-            mem_card.flush();
+    }
+
+    fn flush_mem_card(&mut self, port: Port, slot: Slot) {
+        let mem_card = self.ports[port as usize].mem_cards[slot as usize].as_mut();
+        match mem_card.map(|m| m.flush()) {
+            Some(Ok(true)) => self.events.send(Event::MemCardWritten { port, slot }),
+            Some(Err(e)) => self.events.log(LogLevel::Error, Subsystem::MemCard, format_args!("could not write mem card in port {:?}{:?}: {}", port, slot, e)),
+            _ => {},
         }
     }
 }
@@ -122,19 +170,16 @@ impl PeripheralPort {
 // (In fact, word might be unnecessary)
 impl MemInterface for PeripheralPort {
     fn read_word(&mut self, addr: u32) -> u32 {
-        let data = match addr {
+        match addr {
             0x1F80_1040 => self.receive_data(),
             0x1F80_1044 => self.read_status(),
             0x1F80_1048 => self.get_mode_control(),
             0x1F80_104C => self.baudrate_reload << 16,
             _ => panic!("invalid peripheral addr"),
-        };
-        //println!("Peripheral: read w{:X} from {:X}", data, addr);
-        data
+        }
     }
 
     fn write_word(&mut self, addr: u32, data: u32) {
-        //println!("Peripheral: write w{:X} to {:X}", data, addr);
         match addr {
             0x1F80_1040 => self.send_data(data as u8),
             0x1F80_1044 => {},
@@ -148,7 +193,7 @@ impl MemInterface for PeripheralPort {
     }
 
     fn read_halfword(&mut self, addr: u32) -> u16 {
-        let data = match addr {
+        match addr {
             0x1F80_1040 => self.receive_data() as u16,
             0x1F80_1044 => self.read_status() as u16,
             0x1F80_1046 => (self.read_status() >> 16) as u16,
@@ -156,13 +201,10 @@ impl MemInterface for PeripheralPort {
             0x1F80_104A => self.control.bits(),
             0x1F80_104E => self.baudrate_reload as u16,
             _ => panic!("invalid peripheral addr"),
-        };
-        //println!("Peripheral: read h{:X} from {:X}", data, addr);
-        data
+        }
     }
 
     fn write_halfword(&mut self, addr: u32, data: u16) {
-        //println!("Peripheral: write h{:X} to {:X}", data, addr);
         match addr {
             0x1F80_1040 => self.send_data(data as u8),
             0x1F80_1048 => self.set_mode(data),
@@ -173,16 +215,13 @@ impl MemInterface for PeripheralPort {
     }
 
     fn read_byte(&mut self, addr: u32) -> u8 {
-        let data = match addr {
+        match addr {
             0x1F80_1040 => self.receive_data() as u8,
             _ => panic!("cannot access peripheral byte {:X}", addr),
-        };
-        //println!("Peripheral: read b{:X} from {:X}", data, addr);
-        data
+        }
     }
 
     fn write_byte(&mut self, addr: u32, data: u8) {
-        //println!("Peripheral: write b{:X} to {:X}", data, addr);
         match addr {
             0x1F80_1040 => self.send_data(data),
             _ => panic!("cannot access peripheral byte {:X}", addr),
@@ -231,6 +270,8 @@ bitflags::bitflags! {
     }
 }
 
+const MULTITAP_ID: [u8; 2] = [0x80, 0x5A];
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum TransferMode {
     None,
@@ -282,10 +323,6 @@ impl PeripheralPort {
             self.transfer_mode = TransferMode::None;
         }
         self.control = control.intersection(JoypadControl::Writable);
-        if control.contains(JoypadControl::JoyNOutput) {
-            self.slot_select = if control.contains(JoypadControl::SlotSelect) {1} else {0};
-            //self.control.insert(JoypadControl::RXEnable);
-        }
         if control.contains(JoypadControl::TXEnable) {
             self.status.insert(JoypadStatus::TXReady1);
             if control.contains(JoypadControl::TXIntEnable) {
@@ -299,10 +336,7 @@ impl PeripheralPort {
             self.transfer_mode = TransferMode::None;
             self.in_fifo.clear();
             self.out_fifo.clear();
-            if let Some(mem_card) = self.port_1_mem_card.as_mut() {
-                mem_card.cancel_transfer();
-            }
-            if let Some(mem_card) = self.port_2_mem_card.as_mut() {
+            for mem_card in self.ports.iter_mut().flat_map(|p| p.mem_cards.iter_mut().flatten()) {
                 mem_card.cancel_transfer();
             }
         }
@@ -340,44 +374,28 @@ impl PeripheralPort {
         self.transfer_active = !self.in_fifo.is_empty();
         match self.transfer_mode {
             TransferMode::None => {
-                self.multitap_mem_card = false;
-                if data_in == 0x01 {
-                    self.transfer_mode = TransferMode::Controller(0);
-                    self.push_data(0xFF);
-                } else if data_in == 0x81 {
-                    self.transfer_mode = TransferMode::MemCard;
-                    self.push_data(0xFF);
-                } else if data_in & 0xF0 == 0x80 {
-                    self.multitap_mem_card = true;
-                    self.transfer_mode = TransferMode::MemCard;
-                    self.push_data(0xFF);
-                } else if data_in == 0x00 {
-                    // ...
-                    self.push_data(0xFF);
-                } else {
-                    panic!("unrecognised peripheral data {:X}", data_in);
+                let devices = &self.ports[self.port_index()];
+                match data_in {
+                    0x01..=0x04 if data_in == 0x01 || devices.multitap => {
+                        self.selected_slot = (data_in - 0x01) as usize;
+                        self.transfer_mode = if data_in == 0x01 && devices.tap_mode {
+                            TransferMode::Multitap(0)
+                        } else {
+                            TransferMode::Controller(0)
+                        };
+                    },
+                    0x81..=0x84 if data_in == 0x81 || devices.multitap => {
+                        self.selected_slot = (data_in - 0x81) as usize;
+                        self.transfer_mode = TransferMode::MemCard;
+                    },
+                    // Nothing responds to these:
+                    0x00..=0x04 | 0x81..=0x84 => {},
+                    _ => self.events.log(LogLevel::Debug, Subsystem::Peripheral, format_args!("no device answers to {:X}", data_in)),
                 }
+                self.push_data(0xFF);
             },
             TransferMode::Controller(n) => self.process_controller_mode(data_in, n),
-            TransferMode::Multitap(n) => {
-                // For now: just send 1 controller.
-                let controller = if self.control.contains(JoypadControl::SlotSelect) {
-                    &self.port_2_controller
-                } else {
-                    &self.port_1_controller
-                };
-                let (transfer_mode, data) = match n {
-                    0 => (TransferMode::Multitap(1), controller.output_data[0].to_le_bytes()[0]),
-                    1 => (TransferMode::Multitap(2), controller.output_data[0].to_le_bytes()[1]),
-                    2 => (TransferMode::Multitap(3), controller.output_data[1].to_le_bytes()[0]),
-                    3 => (TransferMode::Multitap(4), controller.output_data[1].to_le_bytes()[1]),
-                    4..=30 => (TransferMode::Multitap(n+1), 0xFF),
-                    31 => (TransferMode::None, 0xFF),
-                    _ => unreachable!()
-                };
-                self.transfer_mode = transfer_mode;
-                self.push_data(data);
-            },
+            TransferMode::Multitap(n) => self.process_multitap_mode(data_in, n),
             TransferMode::MemCard => self.process_memcard_mode(data_in),
         }
         if self.transfer_mode == TransferMode::None {
@@ -390,15 +408,25 @@ impl PeripheralPort {
     }
 
     fn process_controller_mode(&mut self, data_in: u8, n: u8) {
-        let controller = if self.control.contains(JoypadControl::SlotSelect) {
-            &mut self.port_2_controller
-        } else {
-            &mut self.port_1_controller
-        };
+        let port = self.port_index();
+        let devices = &mut self.ports[port];
+        let controller = &mut devices.controllers[self.selected_slot];
+        let mut rumble_changed = false;
+        if n == 0 {
+            controller.command = data_in;
+        } else if n >= 2 {
+            match controller.command {
+                0x42 => rumble_changed = controller.set_motor((n - 2) as usize, data_in),
+                0x4D if controller.config_mode => controller.motor_map[(n - 2) as usize] = data_in,
+                _ => {},
+            }
+            if n == 2 && controller.output_data[0] == controller::JUSTIFIER_INFO {
+                controller.lightpen_enabled = test_bit!(data_in, 4);
+            }
+        }
         let (transfer_mode, data) = if controller.config_mode {
             match n {
                 0 => {
-                    controller.config_command = data_in;
                     match data_in {
                         0x42 => { // Buttons: leave alone.
                             controller.config_data.clone_from_slice(&controller.output_data[1..]);
@@ -411,7 +439,7 @@ impl PeripheralPort {
                         },
                         0x45 => { // get LED state
                             controller.config_data[0] = 0x0201;
-                            controller.config_data[1] = 0x0200; // TODO: lower-byte: analog mode.
+                            controller.config_data[1] = 0x0200 | (controller.analog_mode as u16);
                             controller.config_data[2] = 0x0001;
                         },
                         0x46 => { // pad info act
@@ -419,14 +447,15 @@ impl PeripheralPort {
                         },
                         0x47 => { // unknown?
                             controller.config_data[0] = 0x0000;
-                            controller.config_data[1] = 0x0002;
-                            controller.config_data[2] = 0x0001;
                         },
                         0x4C => { // get variable response
                             controller.config_data.fill(0);
                         },
-                        0x4D => { // get/set rumble protocol
-                            controller.config_data.fill(0xFF); // TODO: actually implement this
+                        0x4D => { // get/set rumble protocol: reply with old mapping
+                            for (data, map) in controller.config_data.iter_mut().zip(controller.motor_map.chunks_exact(2)) {
+                                *data = u16::from_le_bytes([map[0], map[1]]);
+                            }
+                            rumble_changed = controller.stop_motors();
                         },
                         _ => panic!("unrecognised controller config mode {:X}", data_in),
                     }
@@ -434,14 +463,18 @@ impl PeripheralPort {
                 },
                 1 => (TransferMode::Controller(2), 0x5A), // Data should be 0x00
                 2 => {
-                    match controller.config_command {
+                    match controller.command {
                         0x42 => {},
                         0x43 => match data_in {
                             0x00 => controller.config_pending = false, // Exit config mode
                             0x01 => controller.config_pending = true, // Stay in config mode.
                             _ => panic!("unrecognised config mode command {:X}", data_in),
                         },
-                        0x44 => {}, // TODO: led state
+                        0x44 => match data_in {
+                            0x00 => controller.set_analog_mode(false),
+                            0x01 => controller.set_analog_mode(true),
+                            _ => {},
+                        },
                         0x45 => {},
                         0x46 => match data_in { // pad info act
                             0x00 => {
@@ -457,18 +490,30 @@ impl PeripheralPort {
                                 controller.config_data[2] = 0x0000;
                             }
                         },
-                        0x47 => {},
+                        0x47 => if data_in == 0x00 {
+                            controller.config_data[1] = 0x0002;
+                            controller.config_data[2] = 0x0001;
+                        } else {
+                            controller.config_data[1] = 0x0000;
+                            controller.config_data[2] = 0x0000;
+                        },
                         0x4C => match data_in {
                             0x00 => controller.config_data[1] = 0x0400,
                             0x01 => controller.config_data[1] = 0x0700,
                             _ => controller.config_data[1] = 0x0000,
                         },
-                        0x4D => {}, // TODO.
+                        0x4D => {},
                         _ => unreachable!(),
                     }
                     (TransferMode::Controller(3), controller.config_data[0].to_le_bytes()[0])
                 },
-                3 => (TransferMode::Controller(4), controller.config_data[0].to_le_bytes()[1]),
+                3 => {
+                    if controller.command == 0x44 {
+                        // Lock the analog mode button.
+                        controller.analog_locked = data_in == 0x03;
+                    }
+                    (TransferMode::Controller(4), controller.config_data[0].to_le_bytes()[1])
+                },
                 4 => (TransferMode::Controller(5), controller.config_data[1].to_le_bytes()[0]),
                 5 => (TransferMode::Controller(6), controller.config_data[1].to_le_bytes()[1]),
                 6 => (TransferMode::Controller(7), controller.config_data[2].to_le_bytes()[0]),
@@ -484,22 +529,23 @@ impl PeripheralPort {
             match n {
                 0 => {
                     if data_in == 0x43 {
-                        controller.config_pending = true;
+                        controller.config_pending = controller.dualshock;
                     } else if data_in != 0x42 {
                         panic!("Unexpected data in after controller select: {:X}", data_in);
                     }
                     (TransferMode::Controller(1), controller.output_data[0].to_le_bytes()[0])
                 },
                 1 => {
+                    // A multitap switches to reading all slots on the next poll
+                    // if this byte is 01h.
+                    if devices.multitap {
+                        devices.tap_mode = data_in == 0x01;
+                    }
                     let data = controller.output_data[0].to_le_bytes()[1];
                     if data == 0xFF {
                         (TransferMode::None, 0xFF)
                     } else {
-                        //if data_in == 0x01 { // TODO:
-                        //    (TransferMode::Multitap(0), data)
-                        //} else {
-                            (TransferMode::Controller(2), data)
-                        //}
+                        (TransferMode::Controller(2), data)
                     }
                 },
                 2 => {
@@ -512,45 +558,69 @@ impl PeripheralPort {
                     }
                     (TransferMode::Controller(3), controller.output_data[1].to_le_bytes()[0])
                 },
-                3 => {
-                    if controller.config_pending {
+                3..=7 => {
+                    if n == 3 && controller.config_pending {
                         controller.config_mode = true;
                     }
-                    // TODO: this is a bit horrible...
-                    let mode = if controller.output_data[0] == controller::ANALOG_INFO {
-                        TransferMode::Controller(4)
+                    let data = controller.output_data[n as usize / 2].to_le_bytes()[n as usize % 2];
+                    if n as usize == 1 + 2 * controller.response_len() {
+                        if controller.is_mouse() {
+                            controller.mouse_reported();
+                        }
+                        (TransferMode::None, data)
                     } else {
-                        TransferMode::None
-                    };
-                    (mode, controller.output_data[1].to_le_bytes()[1])
-                },
-                4 => {
-                    (TransferMode::Controller(5), controller.output_data[2].to_le_bytes()[0])
-                },
-                5 => {
-                    (TransferMode::Controller(6), controller.output_data[2].to_le_bytes()[1])
-                },
-                6 => {
-                    (TransferMode::Controller(7), controller.output_data[3].to_le_bytes()[0])
-                },
-                7 => {
-                    (TransferMode::None, controller.output_data[3].to_le_bytes()[1])
+                        (TransferMode::Controller(n + 1), data)
+                    }
                 },
                 _ => unreachable!()
             }
         };
+        if rumble_changed {
+            self.send_rumble(port, self.selected_slot);
+        }
         self.transfer_mode = transfer_mode;
         self.push_data(data);
     }
 
-    fn process_memcard_mode(&mut self, data_in: u8) {
-        let mem_card = if self.multitap_mem_card {
-            None
-        } else if self.control.contains(JoypadControl::SlotSelect) {
-            self.port_2_mem_card.as_mut()
-        } else {
-            self.port_1_mem_card.as_mut()
+    /// Read all four controllers through the multitap.
+    fn process_multitap_mode(&mut self, data_in: u8, n: u8) {
+        let port = self.port_index();
+        let devices = &mut self.ports[port];
+        let (transfer_mode, data) = match n {
+            0 => (TransferMode::Multitap(1), MULTITAP_ID[0]),
+            1 => {
+                devices.tap_mode = data_in == 0x01;
+                (TransferMode::Multitap(2), MULTITAP_ID[1])
+            },
+            2..=33 => {
+                let slot = ((n - 2) / 8) as usize;
+                let byte = ((n - 2) % 8) as usize;
+                let controller = &mut devices.controllers[slot];
+                let rumble_changed = byte >= 2 && controller.set_motor(byte - 2, data_in);
+                // Short responses are padded to 8 bytes.
+                let data = if controller.is_connected() && byte < 2 + 2 * controller.response_len() {
+                    controller.output_data[byte / 2].to_le_bytes()[byte % 2]
+                } else {
+                    0xFF
+                };
+                if controller.is_mouse() && byte == 1 + 2 * controller.response_len() {
+                    controller.mouse_reported();
+                }
+                if rumble_changed {
+                    self.send_rumble(port, slot);
+                }
+                let mode = if n == 33 {TransferMode::None} else {TransferMode::Multitap(n + 1)};
+                (mode, data)
+            },
+            _ => unreachable!()
         };
+        self.transfer_mode = transfer_mode;
+        self.push_data(data);
+    }
+
+    fn process_memcard_mode(&mut self, data_in: u8) {
+        let port = self.port_index();
+        let mem_card = self.ports[port].mem_cards[self.selected_slot].as_mut();
         let data = if let Some(mem_card) = mem_card {
             let data = mem_card.transfer_data(data_in);
             if mem_card.transfer_complete() {
@@ -577,6 +647,21 @@ impl PeripheralPort {
         //}
     }
 
+    fn send_rumble(&self, port: usize, slot: usize) {
+        let controller = &self.ports[port].controllers[slot];
+        self.events.send(Event::RumbleChanged {
+            port: if port == 0 {Port::One} else {Port::Two},
+            slot: Slot::ALL[slot],
+            small: controller.small_motor,
+            large: controller.large_motor,
+        });
+    }
+
+    /// Index of the currently selected port.
+    fn port_index(&self) -> usize {
+        if self.control.contains(JoypadControl::SlotSelect) {1} else {0}
+    }
+
     fn trigger_irq(&mut self) {
         let irq = self.status.contains(JoypadStatus::IRQ);
         self.status.insert(JoypadStatus::IRQ);
@@ -586,24 +671,135 @@ impl PeripheralPort {
     }
 }
 
+/// Devices plugged into a port, either directly or through a multitap.
+/// Without a multitap only slot A is used.
+struct PortDevices {
+    multitap: bool,
+    /// Multitap will read all four controllers on the next poll.
+    tap_mode: bool,
+    controllers: [ControllerData; 4],
+    mem_cards: [Option<MemoryCard>; 4],
+}
+
+impl PortDevices {
+    fn new() -> Self {
+        Self {
+            multitap: false,
+            tap_mode: false,
+            controllers: std::array::from_fn(|_| ControllerData::new()),
+            mem_cards: [None, None, None, None],
+        }
+    }
+}
+
 struct ControllerData {
     output_data: [u16; 4],
+    /// Command byte of the current transfer.
+    command: u8,
     // TODO: config mode feels messy
-    config_mode: bool, // TODO: one per controller?
-    config_command: u8,
+    config_mode: bool,
     config_data: [u16; 3],
     config_pending: bool,
+
+    /// Supports the DualShock config and rumble protocol.
+    dualshock: bool,
+    analog_mode: bool,
+    /// Analog button is disabled.
+    analog_locked: bool,
+    /// For each byte after the poll command: 00h = small motor,
+    /// 01h = large motor, FFh = unused.
+    motor_map: [u8; 6],
+    small_motor: u8,
+    large_motor: u8,
+
+    /// Mouse movement the console hasn't polled yet.
+    mouse_delta: (i32, i32),
+
+    /// Where a light gun is aimed.
+    beam: Option<BeamPosition>,
+    /// Justifier will raise the lightpen interrupt.
+    lightpen_enabled: bool,
 }
 
 impl ControllerData {
     fn new() -> Self {
         Self {
             output_data: [0xFFFF, 0xFFFF, 0x0000, 0x0000],
+            command: 0,
 
             config_mode: false,
-            config_command: 0,
             config_data: [0; 3],
             config_pending: false,
+
+            dualshock: false,
+            analog_mode: false,
+            analog_locked: false,
+            motor_map: [0xFF; 6],
+            small_motor: 0,
+            large_motor: 0,
+
+            mouse_delta: (0, 0),
+
+            beam: None,
+            lightpen_enabled: false,
+        }
+    }
+
+    fn set_state(&mut self, state: &ControllerState) {
+        state.get_binary(&mut self.output_data);
+        if self.dualshock && !self.analog_mode {
+            self.output_data[0] = controller::DIGITAL_INFO;
         }
+        if let Some((x, y)) = state.mouse_delta() {
+            self.mouse_delta = (self.mouse_delta.0.saturating_add(x), self.mouse_delta.1.saturating_add(y));
+            self.output_data[2] = controller::mouse_report(self.mouse_delta);
+        }
+    }
+
+    /// Remove the movement sent in a poll.
+    /// Anything beyond the range of a single report is kept for the next one.
+    fn mouse_reported(&mut self) {
+        let (x, y) = self.mouse_delta;
+        self.mouse_delta = (x - controller::clamp_delta(x) as i32, y - controller::clamp_delta(y) as i32);
+        self.output_data[2] = controller::mouse_report(self.mouse_delta);
+    }
+
+    /// Number of halfwords of data sent after the ID.
+    fn response_len(&self) -> usize {
+        ((self.output_data[0] & 0xF) as usize).clamp(1, 3)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.output_data[0] != 0xFFFF
+    }
+
+    fn is_mouse(&self) -> bool {
+        self.output_data[0] == controller::MOUSE_INFO
+    }
+
+    fn set_analog_mode(&mut self, analog: bool) {
+        self.analog_mode = analog;
+        self.output_data[0] = if analog {controller::ANALOG_INFO} else {controller::DIGITAL_INFO};
+    }
+
+    /// Set a motor from a byte of the poll command, according to the mapping.
+    /// Returns true if the motor state changed.
+    fn set_motor(&mut self, index: usize, value: u8) -> bool {
+        let (small, large) = (self.small_motor, self.large_motor);
+        match self.motor_map.get(index) {
+            // The small motor is either on or off.
+            Some(0x00) => self.small_motor = if value & 1 != 0 {0xFF} else {0x00},
+            Some(0x01) => self.large_motor = value,
+            _ => {},
+        }
+        small != self.small_motor || large != self.large_motor
+    }
+
+    /// Returns true if either motor was running.
+    fn stop_motors(&mut self) -> bool {
+        let running = self.small_motor != 0 || self.large_motor != 0;
+        self.small_motor = 0;
+        self.large_motor = 0;
+        running
     }
 }
\ No newline at end of file
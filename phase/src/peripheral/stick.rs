@@ -0,0 +1,261 @@
+// Shaping of analog stick input before it is sent to the console.
+
+use crate::{Axis, Button};
+use super::controller::ControllerState;
+
+/// How the deadzone is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DeadzoneShape {
+    /// Based on the distance of the stick from the centre.
+    #[default]
+    Radial,
+    /// Applied to each axis separately.
+    Axial,
+}
+
+/// Analog stick response settings.
+/// The default passes input through unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct StickConfig {
+    /// Input below this (0-1) is treated as centred.
+    pub deadzone: f32,
+    pub deadzone_shape: DeadzoneShape,
+    /// Smallest output (0-1) once the stick leaves the deadzone.
+    /// Cancels out deadzones built into games.
+    pub anti_deadzone: f32,
+    /// Multiplier applied to input after the deadzone.
+    pub sensitivity: f32,
+    /// Input (0-1) at which the output is at full deflection.
+    pub saturation: f32,
+    /// Drive the left stick with the D-pad instead of pressing the D-pad buttons.
+    /// Only applies to controllers with sticks.
+    pub dpad_to_stick: bool,
+    /// Number of frames for the D-pad to move the stick to full deflection.
+    /// 0 moves it instantly.
+    pub dpad_ramp_frames: u32,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.0,
+            deadzone_shape: DeadzoneShape::Radial,
+            anti_deadzone: 0.0,
+            sensitivity: 1.0,
+            saturation: 1.0,
+            dpad_to_stick: false,
+            dpad_ramp_frames: 0,
+        }
+    }
+}
+
+/// Raw stick input for one controller, shaped once per frame.
+#[derive(Clone, Copy, Default)]
+pub struct StickInput {
+    config: StickConfig,
+    left: (f32, f32),
+    right: (f32, f32),
+    /// Up, down, left, right.
+    dpad: [bool; 4],
+    /// Left stick position driven by the D-pad.
+    dpad_stick: (f32, f32),
+}
+
+impl StickInput {
+    pub fn set_config(&mut self, config: StickConfig) {
+        self.config = config;
+    }
+
+    /// Set a raw stick axis. Returns false if this isn't a stick axis.
+    pub fn set_axis(&mut self, axis: Axis, value: f32) -> bool {
+        match axis {
+            Axis::LeftX => self.left.0 = value,
+            Axis::LeftY => self.left.1 = value,
+            Axis::RightX => self.right.0 = value,
+            Axis::RightY => self.right.1 = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns true if the button was used to move the stick.
+    pub fn press_dpad(&mut self, button: Button, pressed: bool) -> bool {
+        if !self.config.dpad_to_stick {
+            return false;
+        }
+        let index = match button {
+            Button::DUp => 0,
+            Button::DDown => 1,
+            Button::DLeft => 2,
+            Button::DRight => 3,
+            _ => return false,
+        };
+        self.dpad[index] = pressed;
+        true
+    }
+
+    /// Advance by a frame and write the shaped stick positions.
+    pub fn apply(&mut self, state: &mut ControllerState) {
+        self.ramp_dpad_stick();
+        let left = if self.dpad_stick != (0.0, 0.0) {
+            self.dpad_stick
+        } else {
+            self.shape(self.left)
+        };
+        let right = self.shape(self.right);
+        state.update_axis(Axis::LeftX, left.0);
+        state.update_axis(Axis::LeftY, left.1);
+        state.update_axis(Axis::RightX, right.0);
+        state.update_axis(Axis::RightY, right.1);
+    }
+}
+
+// Internal
+impl StickInput {
+    fn shape(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        match self.config.deadzone_shape {
+            DeadzoneShape::Radial => {
+                let magnitude = x.hypot(y);
+                if magnitude == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let scale = self.response(magnitude) / magnitude;
+                ((x * scale).clamp(-1.0, 1.0), (y * scale).clamp(-1.0, 1.0))
+            },
+            DeadzoneShape::Axial => (
+                self.response(x.abs()).copysign(x),
+                self.response(y.abs()).copysign(y),
+            ),
+        }
+    }
+
+    /// Map an input magnitude (0-1) to an output magnitude (0-1).
+    fn response(&self, input: f32) -> f32 {
+        let config = &self.config;
+        if input <= config.deadzone {
+            return 0.0;
+        }
+        let range = (config.saturation - config.deadzone).max(f32::EPSILON);
+        let scaled = ((input - config.deadzone) / range * config.sensitivity).clamp(0.0, 1.0);
+        config.anti_deadzone + (1.0 - config.anti_deadzone) * scaled
+    }
+
+    fn ramp_dpad_stick(&mut self) {
+        let [up, down, left, right] = self.dpad.map(|d| if d {1.0} else {0.0});
+        let (x, y) = (right - left, down - up);
+        let magnitude = f32::hypot(x, y);
+        let target = if magnitude > 0.0 {(x / magnitude, y / magnitude)} else {(0.0, 0.0)};
+        let step = if self.config.dpad_ramp_frames == 0 {
+            2.0
+        } else {
+            1.0 / self.config.dpad_ramp_frames as f32
+        };
+        let approach = |current: f32, target: f32| if current < target {
+            (current + step).min(target)
+        } else {
+            (current - step).max(target)
+        };
+        self.dpad_stick = (approach(self.dpad_stick.0, target.0), approach(self.dpad_stick.1, target.1));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stick(config: StickConfig) -> StickInput {
+        let mut input = StickInput::default();
+        input.set_config(config);
+        input
+    }
+
+    fn assert_near(a: (f32, f32), b: (f32, f32)) {
+        assert!((a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn default_passes_through() {
+        let input = stick(StickConfig::default());
+        assert_near(input.shape((0.3, -0.6)), (0.3, -0.6));
+        assert_near(input.shape((0.0, 0.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn radial_deadzone() {
+        let input = stick(StickConfig { deadzone: 0.2, ..Default::default() });
+        // Inside the circle, even though X alone is outside.
+        assert_near(input.shape((0.15, 0.1)), (0.0, 0.0));
+        // Direction is kept, magnitude is rescaled.
+        let (x, y) = input.shape((0.36, 0.48));
+        assert!((x.hypot(y) - 0.5).abs() < 1e-5);
+        assert!((y / x - 0.48 / 0.36).abs() < 1e-5);
+    }
+
+    #[test]
+    fn axial_deadzone() {
+        let input = stick(StickConfig { deadzone: 0.2, deadzone_shape: DeadzoneShape::Axial, ..Default::default() });
+        // Each axis is cut separately.
+        assert_near(input.shape((0.6, 0.15)), (0.5, 0.0));
+        assert_near(input.shape((-0.6, -1.0)), (-0.5, -1.0));
+    }
+
+    #[test]
+    fn anti_deadzone() {
+        let input = stick(StickConfig { deadzone: 0.2, anti_deadzone: 0.3, ..Default::default() });
+        assert_eq!(input.response(0.2), 0.0);
+        assert!((input.response(0.2001) - 0.3).abs() < 1e-3);
+        assert!((input.response(0.6) - 0.65).abs() < 1e-5);
+        assert_eq!(input.response(1.0), 1.0);
+    }
+
+    #[test]
+    fn saturation() {
+        let input = stick(StickConfig { saturation: 0.8, ..Default::default() });
+        assert!((input.response(0.4) - 0.5).abs() < 1e-5);
+        assert_eq!(input.response(0.8), 1.0);
+        assert_eq!(input.response(0.9), 1.0);
+    }
+
+    #[test]
+    fn sensitivity_clamps() {
+        let input = stick(StickConfig { sensitivity: 2.0, ..Default::default() });
+        assert!((input.response(0.25) - 0.5).abs() < 1e-5);
+        assert_eq!(input.response(0.75), 1.0);
+        // Both axes stay in range when scaled up.
+        assert_near(input.shape((0.7, -0.7)), (std::f32::consts::FRAC_1_SQRT_2, -std::f32::consts::FRAC_1_SQRT_2));
+        assert_near(stick(StickConfig { sensitivity: 2.0, deadzone_shape: DeadzoneShape::Axial, ..Default::default() }).shape((0.7, -0.7)), (1.0, -1.0));
+    }
+
+    #[test]
+    fn dpad_ramp() {
+        let mut input = stick(StickConfig { dpad_to_stick: true, dpad_ramp_frames: 4, ..Default::default() });
+        assert!(input.press_dpad(Button::DRight, true));
+        for frame in 1..=6 {
+            input.ramp_dpad_stick();
+            assert_near(input.dpad_stick, ((frame as f32 / 4.0).min(1.0), 0.0));
+        }
+        input.press_dpad(Button::DRight, false);
+        for _ in 0..4 {
+            input.ramp_dpad_stick();
+        }
+        assert_near(input.dpad_stick, (0.0, 0.0));
+    }
+
+    #[test]
+    fn dpad_diagonal() {
+        let mut input = stick(StickConfig { dpad_to_stick: true, ..Default::default() });
+        input.press_dpad(Button::DUp, true);
+        input.press_dpad(Button::DRight, true);
+        input.ramp_dpad_stick();
+        // Diagonals reach the edge of the circle, not the corner.
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(input.dpad_stick, (diagonal, -diagonal));
+    }
+
+    #[test]
+    fn dpad_needs_config() {
+        let mut input = stick(StickConfig::default());
+        assert!(!input.press_dpad(Button::DUp, true));
+        assert!(!stick(StickConfig { dpad_to_stick: true, ..Default::default() }).press_dpad(Button::Cross, true));
+    }
+}
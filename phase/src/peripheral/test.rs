@@ -0,0 +1,177 @@
+use super::*;
+use crate::{Axis, Button, ControllerType, event::event_channel};
+
+const BAUDRATE_RELOAD: u16 = 0x88;
+
+fn peripheral_port() -> PeripheralPort {
+    let (events, _) = event_channel();
+    let mut port = PeripheralPort::new(events);
+    port.write_halfword(0x1F80_1048, 0x000D);
+    port.write_halfword(0x1F80_104E, BAUDRATE_RELOAD);
+    port
+}
+
+/// Send a sequence of bytes to a port, and return the replies.
+/// Stops early if the device stops acknowledging.
+fn transfer(peripheral: &mut PeripheralPort, port: Port, data: &[u8]) -> Vec<u8> {
+    let select = if port == Port::Two {JoypadControl::SlotSelect.bits()} else {0};
+    peripheral.write_halfword(0x1F80_104A, select | JoypadControl::TXEnable.bits() | JoypadControl::JoyNOutput.bits());
+    let mut replies = Vec::new();
+    for byte in data {
+        peripheral.write_byte(0x1F80_1040, *byte);
+        peripheral.clock(BAUDRATE_RELOAD as usize * 8);
+        replies.push(peripheral.read_byte(0x1F80_1040));
+        if peripheral.transfer_mode == TransferMode::None {
+            break;
+        }
+    }
+    // End the transfer.
+    peripheral.write_halfword(0x1F80_104A, 0);
+    replies
+}
+
+/// Digital controller with a button held down.
+fn controller(button: Button) -> ControllerState {
+    let mut state = ControllerState::new(ControllerType::Digital);
+    state.press_button(button, true);
+    state
+}
+
+#[test]
+fn select_slot_through_multitap() {
+    let mut peripheral = peripheral_port();
+    peripheral.set_multitap(Port::One, true);
+    peripheral.connect_controller(Port::One, Slot::A, controller(Button::Cross));
+    peripheral.connect_controller(Port::One, Slot::B, controller(Button::Circle));
+    // Cross is bit 14 and circle is bit 13, active low.
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x01, 0x42, 0x00, 0x00, 0x00]), [0xFF, 0x41, 0x5A, 0xFF, 0xBF]);
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x02, 0x42, 0x00, 0x00, 0x00]), [0xFF, 0x41, 0x5A, 0xFF, 0xDF]);
+}
+
+#[test]
+fn slots_need_multitap() {
+    let mut peripheral = peripheral_port();
+    peripheral.connect_controller(Port::One, Slot::B, controller(Button::Circle));
+    // Only slot A answers without a multitap.
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x02, 0x42]), [0xFF]);
+    peripheral.set_multitap(Port::One, true);
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x02, 0x42, 0x00, 0x00, 0x00]), [0xFF, 0x41, 0x5A, 0xFF, 0xDF]);
+    // A multitap on the other port doesn't help.
+    peripheral.connect_controller(Port::Two, Slot::B, controller(Button::Circle));
+    assert_eq!(transfer(&mut peripheral, Port::Two, &[0x02, 0x42]), [0xFF]);
+}
+
+#[test]
+fn empty_slot() {
+    let mut peripheral = peripheral_port();
+    peripheral.set_multitap(Port::One, true);
+    // Nothing answers after the ID.
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x03, 0x42, 0x00, 0x00]), [0xFF, 0xFF, 0xFF]);
+}
+
+#[test]
+fn read_all_slots() {
+    let mut peripheral = peripheral_port();
+    peripheral.set_multitap(Port::One, true);
+    peripheral.connect_controller(Port::One, Slot::A, controller(Button::Cross));
+    peripheral.connect_controller(Port::One, Slot::B, controller(Button::Circle));
+    // Ask for all slots on the next poll.
+    transfer(&mut peripheral, Port::One, &[0x01, 0x42, 0x01, 0x00, 0x00]);
+    let mut poll = vec![0x01, 0x42, 0x01];
+    poll.resize(35, 0x00);
+    let replies = transfer(&mut peripheral, Port::One, &poll);
+    assert_eq!(replies[..3], [0xFF, 0x80, 0x5A]);
+    // Short responses are padded, and empty slots read as FFh.
+    assert_eq!(replies[3..11], [0x41, 0x5A, 0xFF, 0xBF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(replies[11..19], [0x41, 0x5A, 0xFF, 0xDF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    assert!(replies[19..].iter().all(|b| *b == 0xFF));
+    assert_eq!(replies.len(), 35);
+}
+
+/// Path for a file in a new empty test directory.
+fn temp_path(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("phase-peripheral-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+#[test]
+fn reinsert_mem_card() {
+    let path = temp_path("reinsert.mcd");
+    let mut peripheral = peripheral_port();
+    peripheral.insert_mem_card(Port::One, Slot::A, &path).unwrap();
+
+    // Write sector 0x40.
+    let data = [0x33; 128];
+    let mut write = vec![0x81, 0x57, 0x00, 0x00, 0x00, 0x40];
+    write.extend_from_slice(&data);
+    write.extend_from_slice(&[0x40, 0x00, 0x00, 0x00]);
+    assert_eq!(transfer(&mut peripheral, Port::One, &write)[137], 0x47);
+
+    peripheral.insert_mem_card(Port::One, Slot::A, &path).unwrap();
+    let mut read = vec![0x81, 0x52, 0x00, 0x00, 0x00, 0x40];
+    read.resize(140, 0x00);
+    let replies = transfer(&mut peripheral, Port::One, &read);
+    assert_eq!(replies[10..138], data);
+    assert_eq!(replies[139], 0x47);
+    drop(peripheral);
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn unknown_device() {
+    let mut peripheral = peripheral_port();
+    peripheral.connect_controller(Port::One, Slot::A, controller(Button::Cross));
+    let path = temp_path("unknown.mcd");
+    peripheral.insert_mem_card(Port::One, Slot::A, &path).unwrap();
+    // Nothing answers, and the next transfer still works.
+    for device in [0x21, 0x85, 0x8F] {
+        assert_eq!(transfer(&mut peripheral, Port::One, &[device, 0x42]), [0xFF]);
+    }
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x01, 0x42, 0x00, 0x00, 0x00]), [0xFF, 0x41, 0x5A, 0xFF, 0xBF]);
+    drop(peripheral);
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn negcon_bytes() {
+    let mut peripheral = peripheral_port();
+    let mut negcon = ControllerState::new(ControllerType::NeGcon);
+    negcon.update_axis(Axis::Twist, -1.0);
+    negcon.update_axis(Axis::I, 1.0);
+    negcon.update_axis(Axis::L, 0.5);
+    peripheral.connect_controller(Port::One, Slot::A, negcon);
+    assert_eq!(transfer(&mut peripheral, Port::One, &[0x01, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), [0xFF, 0x23, 0x5A, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x7F]);
+}
+
+/// Mouse that has moved since the last frame.
+fn mouse(dx: i32, dy: i32) -> ControllerState {
+    let mut state = ControllerState::new(ControllerType::Mouse);
+    state.move_mouse(dx, dy);
+    state
+}
+
+#[test]
+fn mouse_movement_waits_for_poll() {
+    let mut peripheral = peripheral_port();
+    peripheral.connect_controller(Port::One, Slot::A, mouse(0, 0));
+    // Two frames without the game polling.
+    peripheral.set_controller_state(Port::One, Slot::A, mouse(10, -5));
+    peripheral.set_controller_state(Port::One, Slot::A, mouse(10, -5));
+    let poll = [0x01, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(transfer(&mut peripheral, Port::One, &poll), [0xFF, 0x12, 0x5A, 0xFF, 0xFC, 20, -10i8 as u8]);
+    // Movement is only reported once.
+    peripheral.set_controller_state(Port::One, Slot::A, mouse(0, 0));
+    assert_eq!(transfer(&mut peripheral, Port::One, &poll)[5..], [0, 0]);
+}
+
+#[test]
+fn mouse_movement_split_across_polls() {
+    let mut peripheral = peripheral_port();
+    peripheral.connect_controller(Port::One, Slot::A, mouse(200, -300));
+    let poll = [0x01, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(transfer(&mut peripheral, Port::One, &poll)[5..], [127, -128i8 as u8]);
+    assert_eq!(transfer(&mut peripheral, Port::One, &poll)[5..], [73, -128i8 as u8]);
+    assert_eq!(transfer(&mut peripheral, Port::One, &poll)[5..], [0, -44i8 as u8]);
+}
@@ -0,0 +1,317 @@
+// Guest profiler: attributes emulated cycles to guest code and subsystems.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf}
+};
+
+use crossbeam_channel::{
+    Sender, Receiver, unbounded
+};
+
+/// Config for the guest profiler.
+pub struct ProfilerConfig {
+    /// Number of frames to accumulate before producing a report.
+    pub report_frames: usize,
+    /// Size in bytes of the PC ranges that cycles are bucketed into.
+    /// This will be rounded up to a power of two, of at most 2GB.
+    pub range_size: u32,
+    /// Number of hot spots to list in each report.
+    pub top_entries: usize,
+    /// Optional symbol file.
+    ///
+    /// Each line should start with a hex address, and end with the symbol name.
+    /// This covers plain "ADDR NAME" lists and `nm`-style "ADDR T NAME" output.
+    pub symbols_path: Option<PathBuf>,
+}
+
+impl Default for ProfilerConfig {
+    fn default() -> Self {
+        Self {
+            report_frames: 60,
+            range_size: 0x40,
+            top_entries: 20,
+            symbols_path: None,
+        }
+    }
+}
+
+/// What the emulated cycles were spent on.
+///
+/// Stalls are only seen through DMA: the GPU and CD-ROM categories
+/// count DMA channels 2 and 3. Time the CPU spends polling a device's
+/// registers, such as waiting on GPUSTAT, is counted as CPU time in
+/// the code doing the polling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProfileCategory {
+    /// Executing guest code.
+    CPU,
+    /// CPU stalled by DMA transfers (excluding GPU and CD-ROM).
+    DMA,
+    /// CPU stalled by GPU DMA, including waiting for the GPU to take commands.
+    GPU,
+    /// CPU stalled by CD-ROM DMA.
+    CDROM,
+}
+
+const CATEGORIES: [ProfileCategory; 4] = [
+    ProfileCategory::CPU,
+    ProfileCategory::DMA,
+    ProfileCategory::GPU,
+    ProfileCategory::CDROM,
+];
+
+/// A range of guest code which cycles were spent in.
+#[derive(Clone, Debug)]
+pub struct HotSpot {
+    pub start:  u32,
+    pub end:    u32,
+    pub symbol: Option<String>,
+    pub cycles: u64,
+}
+
+/// Profile covering a number of frames.
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub frames: usize,
+    pub total_cycles: u64,
+    pub category_cycles: Vec<(ProfileCategory, u64)>,
+    /// Sorted with the most expensive first.
+    pub hot_spots: Vec<HotSpot>,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total_cycles.max(1) as f64;
+        writeln!(f, "Profile: {} frames, {} cycles ({} per frame)", self.frames, self.total_cycles, self.total_cycles / (self.frames.max(1) as u64))?;
+        for (category, cycles) in &self.category_cycles {
+            writeln!(f, "  {:<6} {:>12} {:>6.2}%", format!("{:?}", category), cycles, (*cycles as f64) * 100.0 / total)?;
+        }
+        writeln!(f, "Hot spots:")?;
+        for spot in &self.hot_spots {
+            let name = spot.symbol.as_deref().unwrap_or("");
+            writeln!(f, "  ${:08X}-${:08X} {:>12} {:>6.2}% {}", spot.start, spot.end, spot.cycles, (spot.cycles as f64) * 100.0 / total, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Created by PlayStation.
+pub struct ProfileHandler {
+    report_rx: Receiver<ProfileReport>,
+}
+
+impl ProfileHandler {
+    /// Get the most recent report, if a new one is ready.
+    pub fn poll_report(&self) -> Option<ProfileReport> {
+        self.report_rx.try_iter().last()
+    }
+}
+
+/// Lives on the memory bus, and counts cycles.
+pub struct Profiler {
+    report_frames: usize,
+    range_mask: u32,
+    top_entries: usize,
+    /// Sorted by address.
+    symbols: Vec<(u32, String)>,
+
+    frames: usize,
+    pc_cycles: HashMap<u32, u64>,
+    category_cycles: [u64; 4],
+
+    report_tx: Sender<ProfileReport>,
+}
+
+impl Profiler {
+    pub fn new(config: ProfilerConfig) -> std::io::Result<(Self, ProfileHandler)> {
+        let symbols = if let Some(path) = config.symbols_path.as_ref() {
+            load_symbols(path)?
+        } else {
+            Vec::new()
+        };
+        let (report_tx, report_rx) = unbounded();
+        let range_size = config.range_size.max(4).checked_next_power_of_two().unwrap_or(1 << 31);
+        let profiler = Self {
+            report_frames: config.report_frames.max(1),
+            range_mask: !(range_size - 1),
+            top_entries: config.top_entries,
+            symbols,
+
+            frames: 0,
+            pc_cycles: HashMap::new(),
+            category_cycles: [0; 4],
+
+            report_tx,
+        };
+        Ok((profiler, ProfileHandler { report_rx }))
+    }
+
+    /// Count cycles spent executing at the PC.
+    pub fn add_cpu_cycles(&mut self, pc: u32, cycles: usize) {
+        *self.pc_cycles.entry(pc & self.range_mask).or_default() += cycles as u64;
+        self.category_cycles[ProfileCategory::CPU as usize] += cycles as u64;
+    }
+
+    /// Count cycles where the CPU is stalled by another subsystem.
+    pub fn add_stall_cycles(&mut self, category: ProfileCategory, cycles: usize) {
+        self.category_cycles[category as usize] += cycles as u64;
+    }
+
+    /// Call at the start of each frame. Sends a report every N frames.
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+        if self.frames >= self.report_frames {
+            let report = self.make_report();
+            let _ = self.report_tx.send(report);
+            self.frames = 0;
+            self.pc_cycles.clear();
+            self.category_cycles = [0; 4];
+        }
+    }
+}
+
+// Internal
+impl Profiler {
+    fn make_report(&self) -> ProfileReport {
+        let range_size = (!self.range_mask).wrapping_add(1);
+        let mut hot_spots = if self.symbols.is_empty() {
+            self.pc_cycles.iter().map(|(start, cycles)| HotSpot {
+                start: *start,
+                end: start.wrapping_add(range_size - 1),
+                symbol: None,
+                cycles: *cycles
+            }).collect::<Vec<_>>()
+        } else {
+            // Merge ranges into the symbols that contain them.
+            let mut by_symbol: HashMap<usize, HotSpot> = HashMap::new();
+            let mut unknown = Vec::new();
+            for (start, cycles) in self.pc_cycles.iter() {
+                match self.symbols.partition_point(|(addr, _)| *addr <= *start).checked_sub(1) {
+                    Some(idx) => {
+                        let spot = by_symbol.entry(idx).or_insert_with(|| HotSpot {
+                            start: self.symbols[idx].0,
+                            end: self.symbols.get(idx + 1).map(|(addr, _)| addr.wrapping_sub(1)).unwrap_or(u32::MAX),
+                            symbol: Some(self.symbols[idx].1.clone()),
+                            cycles: 0,
+                        });
+                        spot.cycles += *cycles;
+                    },
+                    None => unknown.push(HotSpot {
+                        start: *start,
+                        end: start.wrapping_add(range_size - 1),
+                        symbol: None,
+                        cycles: *cycles
+                    }),
+                }
+            }
+            unknown.extend(by_symbol.into_values());
+            unknown
+        };
+        hot_spots.sort_by(|a, b| b.cycles.cmp(&a.cycles));
+        hot_spots.truncate(self.top_entries);
+
+        ProfileReport {
+            frames: self.frames,
+            total_cycles: self.category_cycles.iter().sum(),
+            category_cycles: CATEGORIES.iter().map(|c| (*c, self.category_cycles[*c as usize])).collect(),
+            hot_spots,
+        }
+    }
+}
+
+/// Load a symbol list, sorted by address.
+fn load_symbols(path: &Path) -> std::io::Result<Vec<(u32, String)>> {
+    let symbol_file = std::fs::read_to_string(path)?;
+    Ok(parse_symbols(&symbol_file))
+}
+
+fn parse_symbols(symbol_file: &str) -> Vec<(u32, String)> {
+    let mut symbols = symbol_file.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let addr_str = fields.next()?;
+        let addr_str = addr_str.trim_start_matches("0x").trim_start_matches('$');
+        let addr = u32::from_str_radix(addr_str, 16).ok()?;
+        let name = fields.last()?;
+        Some((addr, name.to_string()))
+    }).collect::<Vec<_>>();
+    symbols.sort_by_key(|(addr, _)| *addr);
+    symbols
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profiler(range_size: u32) -> (Profiler, ProfileHandler) {
+        Profiler::new(ProfilerConfig {
+            report_frames: 2,
+            range_size,
+            top_entries: 3,
+            symbols_path: None,
+        }).unwrap()
+    }
+
+    #[test]
+    fn range_size_rounding() {
+        assert_eq!(profiler(0).0.range_mask, !3);
+        assert_eq!(profiler(0x30).0.range_mask, !0x3F);
+        // Too big to round up.
+        assert_eq!(profiler(0x9000_0000).0.range_mask, 0x8000_0000);
+        assert_eq!(profiler(u32::MAX).0.range_mask, 0x8000_0000);
+    }
+
+    #[test]
+    fn report_every_n_frames() {
+        let (mut profiler, handler) = profiler(0x10);
+        profiler.add_cpu_cycles(0x8001_0004, 10);
+        profiler.end_frame();
+        assert!(handler.poll_report().is_none());
+        profiler.add_cpu_cycles(0x8001_000C, 5);
+        profiler.add_cpu_cycles(0x8002_0000, 20);
+        profiler.add_stall_cycles(ProfileCategory::GPU, 7);
+        profiler.end_frame();
+
+        let report = handler.poll_report().unwrap();
+        assert_eq!(report.frames, 2);
+        assert_eq!(report.total_cycles, 42);
+        assert_eq!(report.category_cycles, [
+            (ProfileCategory::CPU, 35),
+            (ProfileCategory::DMA, 0),
+            (ProfileCategory::GPU, 7),
+            (ProfileCategory::CDROM, 0),
+        ]);
+        let spots = report.hot_spots.iter().map(|s| (s.start, s.end, s.cycles)).collect::<Vec<_>>();
+        assert_eq!(spots, [(0x8002_0000, 0x8002_000F, 20), (0x8001_0000, 0x8001_000F, 15)]);
+
+        // Counts start again after a report.
+        profiler.end_frame();
+        profiler.end_frame();
+        assert_eq!(handler.poll_report().unwrap().total_cycles, 0);
+    }
+
+    #[test]
+    fn hot_spots_by_symbol() {
+        let (mut profiler, handler) = profiler(0x10);
+        profiler.symbols = parse_symbols("80010000 T main\n0x80010100 update\n$80020000 t draw\nnot a symbol");
+        assert_eq!(profiler.symbols.len(), 3);
+        profiler.add_cpu_cycles(0x8001_0000, 1);
+        profiler.add_cpu_cycles(0x8001_0080, 2);
+        profiler.add_cpu_cycles(0x8001_0100, 4);
+        profiler.add_cpu_cycles(0x8002_0040, 8);
+        // Before the first symbol.
+        profiler.add_cpu_cycles(0x8000_0000, 16);
+        profiler.end_frame();
+        profiler.end_frame();
+
+        let report = handler.poll_report().unwrap();
+        let spots = report.hot_spots.iter().map(|s| (s.start, s.end, s.symbol.as_deref(), s.cycles)).collect::<Vec<_>>();
+        // Only the top 3 are kept.
+        assert_eq!(spots, [
+            (0x8000_0000, 0x8000_000F, None, 16),
+            (0x8002_0000, u32::MAX, Some("draw"), 8),
+            (0x8001_0100, 0x8001_FFFF, Some("update"), 4),
+        ]);
+    }
+}
@@ -0,0 +1,185 @@
+// Transports for the SIO1 link cable.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+};
+use crossbeam_channel::{Sender, Receiver, unbounded};
+
+/// Handshake outputs of one end of the cable.
+/// DTR arrives as DSR at the other end, and RTS arrives as CTS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerialLines {
+    pub dtr: bool,
+    pub rts: bool,
+}
+
+/// One end of a serial cable.
+/// The console side calls this from the CPU thread.
+pub trait SerialLink: Send {
+    /// Send a byte to the other end.
+    fn send(&mut self, data: u8);
+
+    /// Take the next byte sent by the other end.
+    fn receive(&mut self) -> Option<u8>;
+
+    /// Set our handshake outputs.
+    fn set_lines(&mut self, lines: SerialLines);
+
+    /// Handshake outputs of the other end.
+    fn remote_lines(&mut self) -> SerialLines;
+}
+
+enum LinkMessage {
+    Data(u8),
+    Lines(SerialLines),
+}
+
+const DATA_TAG: u8 = 0;
+const LINES_TAG: u8 = 1;
+
+/// Link cable that passes messages over channels.
+/// Sockets and terminals are bridged onto the channels by threads.
+pub struct LinkCable {
+    tx: Sender<LinkMessage>,
+    rx: Receiver<LinkMessage>,
+    rx_data: VecDeque<u8>,
+    lines: SerialLines,
+    remote: SerialLines,
+}
+
+impl LinkCable {
+    /// Make both ends of a cable, for connecting two consoles in one process.
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = unbounded();
+        let (b_tx, a_rx) = unbounded();
+        (Self::new(a_tx, a_rx), Self::new(b_tx, b_rx))
+    }
+
+    /// Connect to another emulator over TCP.
+    /// Handshake lines are sent along with the data.
+    pub fn tcp(stream: TcpStream) -> std::io::Result<Self> {
+        let _ = stream.set_nodelay(true);
+        let reader = stream.try_clone()?;
+        Ok(Self::from_stream(reader, stream, true))
+    }
+
+    /// Connect to another emulator over a Unix socket.
+    /// Handshake lines are sent along with the data.
+    #[cfg(unix)]
+    pub fn unix(stream: std::os::unix::net::UnixStream) -> std::io::Result<Self> {
+        let reader = stream.try_clone()?;
+        Ok(Self::from_stream(reader, stream, true))
+    }
+
+    /// Open a terminal device, such as a pseudo-terminal.
+    /// Only data is sent: the terminal should already be in raw mode,
+    /// and the other end's handshake lines are always on.
+    pub fn tty(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = file.try_clone()?;
+        let cable = Self::from_stream(reader, file, false);
+        Ok(Self {
+            remote: SerialLines { dtr: true, rts: true },
+            ..cable
+        })
+    }
+
+    /// Bridge a byte stream onto a cable.
+    /// If framed, each message is two bytes: a tag and a value.
+    pub fn from_stream(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static, framed: bool) -> Self {
+        let (out_tx, out_rx) = unbounded();
+        let (in_tx, in_rx) = unbounded();
+        std::thread::spawn(move || write_stream(writer, out_rx, framed));
+        std::thread::spawn(move || read_stream(reader, in_tx, framed));
+        Self::new(out_tx, in_rx)
+    }
+}
+
+impl SerialLink for LinkCable {
+    fn send(&mut self, data: u8) {
+        let _ = self.tx.send(LinkMessage::Data(data));
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.poll();
+        self.rx_data.pop_front()
+    }
+
+    fn set_lines(&mut self, lines: SerialLines) {
+        if lines != self.lines {
+            self.lines = lines;
+            let _ = self.tx.send(LinkMessage::Lines(lines));
+        }
+    }
+
+    fn remote_lines(&mut self) -> SerialLines {
+        self.poll();
+        self.remote
+    }
+}
+
+// Internal
+impl LinkCable {
+    fn new(tx: Sender<LinkMessage>, rx: Receiver<LinkMessage>) -> Self {
+        Self {
+            tx, rx,
+            rx_data: VecDeque::new(),
+            lines: SerialLines::default(),
+            remote: SerialLines::default(),
+        }
+    }
+
+    fn poll(&mut self) {
+        loop {
+            match self.rx.try_recv() {
+                Ok(LinkMessage::Data(data)) => self.rx_data.push_back(data),
+                Ok(LinkMessage::Lines(lines)) => self.remote = lines,
+                Err(crossbeam_channel::TryRecvError::Empty) => break,
+                Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                    // Unplugged.
+                    self.remote = SerialLines::default();
+                    break;
+                },
+            }
+        }
+    }
+}
+
+fn write_stream(mut writer: impl Write, messages: Receiver<LinkMessage>, framed: bool) {
+    for message in messages.iter() {
+        let result = match (message, framed) {
+            (LinkMessage::Data(data), true) => writer.write_all(&[DATA_TAG, data]),
+            (LinkMessage::Lines(lines), true) => {
+                let value = (lines.dtr as u8) | ((lines.rts as u8) << 1);
+                writer.write_all(&[LINES_TAG, value])
+            },
+            (LinkMessage::Data(data), false) => writer.write_all(&[data]),
+            (LinkMessage::Lines(_), false) => Ok(()),
+        };
+        if result.and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_stream(mut reader: impl Read, messages: Sender<LinkMessage>, framed: bool) {
+    let mut buffer = [0_u8; 2];
+    let message_len = if framed {2} else {1};
+    while reader.read_exact(&mut buffer[..message_len]).is_ok() {
+        let message = match (framed, buffer[0]) {
+            (false, data) => LinkMessage::Data(data),
+            (true, DATA_TAG) => LinkMessage::Data(buffer[1]),
+            (true, LINES_TAG) => LinkMessage::Lines(SerialLines {
+                dtr: buffer[1] & 1 != 0,
+                rts: buffer[1] & 2 != 0,
+            }),
+            (true, _) => return,
+        };
+        if messages.send(message).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,371 @@
+pub mod link;
+#[cfg(test)]
+mod test;
+
+use std::collections::VecDeque;
+
+use crate::{
+    interrupt::Interrupt,
+    utils::{
+        bits::*,
+        interface::MemInterface
+    }
+};
+use link::{SerialLink, SerialLines};
+
+const RX_FIFO_SIZE: usize = 8;
+
+/// SIO1: the serial port, used for the link cable.
+pub struct SerialIO {
+    status:         SerialStatus,
+    mode:           SerialMode,
+    control:        SerialControl,
+    baud_reload:    u16,
+
+    timer:          u16,
+
+    link:           Option<Box<dyn SerialLink>>,
+    /// Handshake outputs of the other end, as of the last poll.
+    remote:         SerialLines,
+    tx_buffer:      Option<u8>,
+    /// Byte being sent, and cycles until it has been sent.
+    tx_shift:       Option<(u8, u32)>,
+    rx_fifo:        VecDeque<u8>,
+    /// Cycles until the link is checked for another byte.
+    rx_timer:       u32,
+    irq_latch:      bool,
+}
+
+impl SerialIO {
+    pub fn new() -> Self {
+        Self {
+            status:         SerialStatus::empty(),
+            mode:           SerialMode::empty(),
+            control:        SerialControl::empty(),
+            baud_reload:    0,
+
+            timer:          0,
+
+            link:           None,
+            remote:         SerialLines::default(),
+            tx_buffer:      None,
+            tx_shift:       None,
+            rx_fifo:        VecDeque::new(),
+            rx_timer:       0,
+            irq_latch:      false,
+        }
+    }
+
+    /// Plug in a link cable, replacing any existing one.
+    pub fn connect_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+        self.rx_timer = 0;
+        self.update_lines();
+    }
+
+    pub fn disconnect_link(&mut self) {
+        self.link = None;
+        self.remote = SerialLines::default();
+    }
+
+    pub fn clock(&mut self, cycles: usize) -> Interrupt {
+        let cycles = cycles as u32;
+        self.clock_baud_timer(cycles);
+        self.clock_tx(cycles);
+        self.clock_rx(cycles);
+
+        if self.irq_latch {
+            self.irq_latch = false;
+            Interrupt::SIO
+        } else {
+            Interrupt::empty()
+        }
+    }
+}
+
+impl MemInterface for SerialIO {
+    fn read_word(&mut self, addr: u32) -> u32 {
+        match addr {
+            0x1F80_1050 => self.receive_data(),
+            0x1F80_1054 => self.get_status(),
+            0x1F80_1058 => self.get_mode_control(),
+            0x1F80_105C => (self.baud_reload as u32) << 16,
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+
+    fn write_word(&mut self, addr: u32, data: u32) {
+        match addr {
+            0x1F80_1050 => self.send_data(data as u8),
+            0x1F80_1054 => {}, // status
+            0x1F80_1058 => {
+                self.set_mode(data as u16);
+                self.set_control((data >> 16) as u16);
+            },
+            0x1F80_105C => self.set_baudrate((data >> 16) as u16),
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        match addr {
+            0x1F80_1050 => self.receive_data() as u16,
+            0x1F80_1054 => self.get_status() as u16,
+            0x1F80_1056 => (self.get_status() >> 16) as u16,
+            0x1F80_1058 => self.mode.bits(),
+            0x1F80_105A => self.control.bits(),
+            0x1F80_105E => self.baud_reload as u16,
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+
+    fn write_halfword(&mut self, addr: u32, data: u16) {
+        match addr {
+            0x1F80_1050 => self.send_data(data as u8),
+            0x1F80_1058 => self.set_mode(data),
+            0x1F80_105A => self.set_control(data),
+            0x1F80_105E => self.set_baudrate(data),
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        match addr {
+            0x1F80_1050 => self.receive_data() as u8,
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, data: u8) {
+        match addr {
+            0x1F80_1050 => self.send_data(data),
+            _ => panic!("invalid SIO addr {:X}", addr),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy)]
+    struct SerialStatus: u16 {
+        const IRQ               = bit!(9);
+        const CTSInputLevel     = bit!(8);
+        const DSRInputLevel     = bit!(7);
+        const RXInputLevel      = bit!(6);
+        const RXBadStopBit      = bit!(5);
+        const RXFIFOOverrun     = bit!(4);
+        const RXParityError     = bit!(3);
+        const TXReady2          = bit!(2);
+        const RXFifoNotEmpty    = bit!(1);
+        const TXReady1          = bit!(0);
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy)]
+    struct SerialMode: u16 {
+        const StopBitLength         = bits![6, 7];
+        const ParityType            = bit!(5);
+        const ParityEnable          = bit!(4);
+        const CharLength            = bits![2, 3];
+        const BaudrateReloadFactor  = bits![0, 1];
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy)]
+    struct SerialControl: u16 {
+        const DSRIntEnable  = bit!(12);
+        const RXIntEnable   = bit!(11);
+        const TXIntEnable   = bit!(10);
+        const RXIntMode     = bits![8, 9];
+        const Reset         = bit!(6);
+        const RTSOutput     = bit!(5);
+        const ACK           = bit!(4);
+        const TXOutput      = bit!(3);
+        const RXEnable      = bit!(2);
+        const DTROutput     = bit!(1);
+        const TXEnable      = bit!(0);
+
+        const Writable = bits![0, 1, 2, 3, 5, 8, 9, 10, 11, 12];
+    }
+}
+
+// Internal
+impl SerialIO {
+    fn send_data(&mut self, data: u8) {
+        // The TX FIFO is a single byte.
+        self.tx_buffer = Some(data);
+    }
+
+    fn receive_data(&mut self) -> u32 {
+        self.rx_fifo.pop_front().unwrap_or(0) as u32
+    }
+
+    fn get_status(&self) -> u32 {
+        let mut status = self.status;
+        status.set(SerialStatus::TXReady1, self.tx_buffer.is_none());
+        status.set(SerialStatus::TXReady2, self.tx_buffer.is_none() && self.tx_shift.is_none());
+        status.set(SerialStatus::RXFifoNotEmpty, !self.rx_fifo.is_empty());
+        status.set(SerialStatus::DSRInputLevel, self.remote.dtr);
+        status.set(SerialStatus::CTSInputLevel, self.remote.rts);
+        let timer = (self.timer as u32) << 11;
+        status.bits() as u32 | timer
+    }
+
+    fn get_mode_control(&self) -> u32 {
+        let mode = self.mode.bits() as u32;
+        let control = (self.control.bits() as u32) << 16;
+        mode | control
+    }
+
+    fn set_mode(&mut self, data: u16) {
+        self.mode = SerialMode::from_bits_truncate(data);
+    }
+
+    fn set_control(&mut self, data: u16) {
+        let control = SerialControl::from_bits_truncate(data);
+        if control.contains(SerialControl::Reset) {
+            self.reset();
+            return;
+        }
+        if control.contains(SerialControl::ACK) {
+            self.status.remove(SerialStatus::IRQ | SerialStatus::RXFIFOOverrun | SerialStatus::RXBadStopBit | SerialStatus::RXParityError);
+        }
+        if !control.contains(SerialControl::RXEnable) {
+            self.rx_fifo.clear();
+        }
+        self.control = control & SerialControl::Writable;
+        self.update_lines();
+    }
+
+    fn set_baudrate(&mut self, data: u16) {
+        self.baud_reload = data;
+    }
+
+    fn reset(&mut self) {
+        self.status = SerialStatus::empty();
+        self.mode = SerialMode::empty();
+        self.control = SerialControl::empty();
+        self.tx_buffer = None;
+        self.tx_shift = None;
+        self.rx_fifo.clear();
+        self.update_lines();
+    }
+
+    fn update_lines(&mut self) {
+        let lines = SerialLines {
+            dtr: self.control.contains(SerialControl::DTROutput),
+            rts: self.control.contains(SerialControl::RTSOutput),
+        };
+        if let Some(link) = self.link.as_mut() {
+            link.set_lines(lines);
+        }
+    }
+
+    fn trigger_irq(&mut self) {
+        if !self.status.contains(SerialStatus::IRQ) {
+            self.status.insert(SerialStatus::IRQ);
+            self.irq_latch = true;
+        }
+    }
+
+    /// Cycles to send one bit.
+    fn bit_cycles(&self) -> u32 {
+        let factor = match (self.mode & SerialMode::BaudrateReloadFactor).bits() {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        ((self.baud_reload as u32 * factor) & !1).max(factor)
+    }
+
+    /// Cycles to send one character, including start, parity and stop bits.
+    fn char_cycles(&self) -> u32 {
+        let data_bits = 5 + ((self.mode & SerialMode::CharLength).bits() >> 2) as u32;
+        let parity_bits = if self.mode.contains(SerialMode::ParityEnable) {1} else {0};
+        let stop_half_bits = match (self.mode & SerialMode::StopBitLength).bits() >> 6 {
+            2 => 3,
+            3 => 4,
+            _ => 2,
+        };
+        let half_bits = 2 * (1 + data_bits + parity_bits) + stop_half_bits;
+        self.bit_cycles() * half_bits / 2
+    }
+
+    fn char_mask(&self) -> u8 {
+        let data_bits = 5 + ((self.mode & SerialMode::CharLength).bits() >> 2);
+        (0xFF_u16 >> (8 - data_bits)) as u8
+    }
+
+    fn clock_baud_timer(&mut self, cycles: u32) {
+        let reload = (self.bit_cycles() / 2).max(1);
+        let elapsed = cycles % reload;
+        let timer = self.timer as u32;
+        let timer = if timer > elapsed {
+            timer - elapsed
+        } else {
+            reload - (elapsed - timer)
+        };
+        self.timer = (timer & 0x7FFF) as u16;
+    }
+
+    fn clock_tx(&mut self, cycles: u32) {
+        if let Some((data, cycles_left)) = self.tx_shift {
+            if cycles_left > cycles {
+                self.tx_shift = Some((data, cycles_left - cycles));
+                return;
+            }
+            self.tx_shift = None;
+            if let Some(link) = self.link.as_mut() {
+                link.send(data);
+            }
+            if self.tx_buffer.is_none() && self.control.contains(SerialControl::TXIntEnable) {
+                self.trigger_irq();
+            }
+        }
+        // Only transmit while the other end is ready to receive.
+        if self.tx_buffer.is_some() && self.control.contains(SerialControl::TXEnable) && self.remote.rts {
+            let data = self.tx_buffer.take().unwrap() & self.char_mask();
+            self.tx_shift = Some((data, self.char_cycles()));
+            if self.control.contains(SerialControl::TXIntEnable) {
+                self.trigger_irq();
+            }
+        }
+    }
+
+    fn clock_rx(&mut self, cycles: u32) {
+        if self.link.is_none() {
+            return;
+        }
+        if self.rx_timer > cycles {
+            self.rx_timer -= cycles;
+            return;
+        }
+        // Take at most one byte per character time.
+        self.rx_timer = self.char_cycles();
+        let link = self.link.as_mut().unwrap();
+
+        let remote = link.remote_lines();
+        let received = link.receive();
+        if remote.dtr && !self.remote.dtr && self.control.contains(SerialControl::DSRIntEnable) {
+            self.trigger_irq();
+        }
+        self.remote = remote;
+
+        let Some(data) = received else {
+            return;
+        };
+        if !self.control.contains(SerialControl::RXEnable) {
+            return;
+        }
+        if self.rx_fifo.len() == RX_FIFO_SIZE {
+            self.status.insert(SerialStatus::RXFIFOOverrun);
+            return;
+        }
+        self.rx_fifo.push_back(data);
+        let int_level = 1 << ((self.control & SerialControl::RXIntMode).bits() >> 8);
+        if self.control.contains(SerialControl::RXIntEnable) && self.rx_fifo.len() >= int_level {
+            self.trigger_irq();
+        }
+    }
+}
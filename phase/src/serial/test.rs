@@ -0,0 +1,59 @@
+use super::*;
+use link::LinkCable;
+
+/// Two serial ports joined by a cable, set to 8N1 at a fast rate.
+fn linked_ports() -> (SerialIO, SerialIO) {
+    let (cable_a, cable_b) = LinkCable::pair();
+    let mut ports = (SerialIO::new(), SerialIO::new());
+    for (port, cable) in [(&mut ports.0, cable_a), (&mut ports.1, cable_b)] {
+        port.connect_link(Box::new(cable));
+        port.write_halfword(0x1F80_105E, 4);
+        port.write_halfword(0x1F80_1058, 0x4D);
+        port.write_halfword(0x1F80_105A, 0x0827);
+    }
+    (ports.0, ports.1)
+}
+
+fn clock_both(ports: &mut (SerialIO, SerialIO), cycles: usize) -> (Interrupt, Interrupt) {
+    let mut irqs = (Interrupt::empty(), Interrupt::empty());
+    for _ in 0..cycles {
+        irqs.0 |= ports.0.clock(1);
+        irqs.1 |= ports.1.clock(1);
+    }
+    irqs
+}
+
+#[test]
+fn handshake() {
+    let mut ports = linked_ports();
+    clock_both(&mut ports, 100);
+    let status = ports.0.read_halfword(0x1F80_1054);
+    assert!(status & SerialStatus::DSRInputLevel.bits() != 0);
+    assert!(status & SerialStatus::CTSInputLevel.bits() != 0);
+
+    ports.1.write_halfword(0x1F80_105A, 0x0807);
+    clock_both(&mut ports, 100);
+    let status = ports.0.read_halfword(0x1F80_1054);
+    assert!(status & SerialStatus::DSRInputLevel.bits() != 0);
+    assert!(status & SerialStatus::CTSInputLevel.bits() == 0);
+}
+
+#[test]
+fn transfer() {
+    let mut ports = linked_ports();
+    clock_both(&mut ports, 100);
+    ports.0.write_byte(0x1F80_1050, 0xA5);
+    assert!(ports.0.read_halfword(0x1F80_1054) & SerialStatus::TXReady2.bits() == 0);
+
+    // 10 bits at 4 cycles each.
+    let irqs = clock_both(&mut ports, 100);
+    assert!(ports.0.read_halfword(0x1F80_1054) & SerialStatus::TXReady2.bits() != 0);
+    assert!(irqs.1.contains(Interrupt::SIO));
+    assert!(ports.1.read_halfword(0x1F80_1054) & SerialStatus::RXFifoNotEmpty.bits() != 0);
+    assert_eq!(ports.1.read_byte(0x1F80_1050), 0xA5);
+    assert!(ports.1.read_halfword(0x1F80_1054) & SerialStatus::RXFifoNotEmpty.bits() == 0);
+
+    // Acknowledge.
+    ports.1.write_halfword(0x1F80_105A, 0x0837);
+    assert!(ports.1.read_halfword(0x1F80_1054) & SerialStatus::IRQ.bits() == 0);
+}
@@ -95,7 +95,7 @@ impl MemInterface for Timers {
     }
 
     fn read_halfword(&mut self, addr: u32) -> u16 {
-        let data = match addr {
+        match addr {
             0x1F801100 => self.timers[0].counter,
             0x1F801104 => self.timers[0].read_mode(),
             0x1F801108 => self.timers[0].target,
@@ -111,13 +111,10 @@ impl MemInterface for Timers {
             0x1F801130 => self.timers[2].counter, // For GT2
 
             _ => panic!("invalid timer addr {:X}", addr),
-        };
-        //println!("timer read {:X} from {:X}", data, addr);
-        data
+        }
     }
     
     fn write_halfword(&mut self, addr: u32, data: u16) {
-        //println!("timer write {:X} to {:X}", data, addr);
         match addr {
             0x1F801100 => self.timers[0].counter = 0,
             0x1F801104 => self.timers[0].write_mode(data),